@@ -0,0 +1,52 @@
+use timelog::filter::{self, Filter};
+use timelog::interval::{Interval, TaggedInterval};
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use criterion::{criterion_group, criterion_main, Criterion};
+
+const INTERVALS: i64 = 10_000;
+
+/// Build back-to-back half-hour intervals cycling through ten tags.
+fn intervals(base: DateTime<Utc>) -> Vec<TaggedInterval> {
+    (0..INTERVALS)
+        .map(|i| {
+            let start = base + Duration::minutes(30 * i);
+            let interval = Interval::closed(start, Duration::minutes(30).to_std().unwrap());
+            TaggedInterval::new(i as u32 % 10, interval)
+        })
+        .collect()
+}
+
+/// A filter in the shape built by `list`: a tag disjunction, a time range, and a duration bound.
+fn complex_filter(base: DateTime<Utc>) -> Filter {
+    let start = base + Duration::days(30);
+    let end = start + Duration::days(7);
+
+    filter::or_all((0..5).map(filter::has_tag))
+        & filter::started_before(end)
+        & (filter::is_open() | filter::ended_after_strict(start))
+        & filter::longer_than(Duration::minutes(15))
+}
+
+fn filter_evaluation(c: &mut Criterion) {
+    let base = Utc.ymd(2020, 1, 1).and_hms(0, 0, 0);
+    let intervals = intervals(base);
+    let filter = complex_filter(base);
+    let compiled = filter.compile();
+
+    assert_eq!(
+        intervals.iter().filter(|int| filter.eval(int)).count(),
+        intervals.iter().filter(|int| compiled.eval(int)).count()
+    );
+
+    c.bench_function("complex filter, stack evaluator", |b| {
+        b.iter(|| intervals.iter().filter(|int| filter.eval(int)).count())
+    });
+
+    c.bench_function("complex filter, compiled", |b| {
+        b.iter(|| intervals.iter().filter(|int| compiled.eval(int)).count())
+    });
+}
+
+criterion_group!(benches, filter_evaluation);
+criterion_main!(benches);
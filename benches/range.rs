@@ -0,0 +1,61 @@
+use timelog::filter;
+use timelog::timelog::TimeLog;
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use criterion::{criterion_group, criterion_main, Criterion};
+
+const INTERVALS: i64 = 100_000;
+
+/// Build a timelog of back-to-back half-hour intervals alternating between two tags.
+fn large_timelog(base: DateTime<Utc>) -> TimeLog {
+    let intervals: Vec<_> = (0..INTERVALS)
+        .map(|i| {
+            let start = base + Duration::minutes(30 * i);
+            format!(
+                r#"{{"tag":{},"interval":{{"start":"{}","duration":{{"secs":1800,"nanos":0}}}}}}"#,
+                i % 2,
+                start.to_rfc3339()
+            )
+        })
+        .collect();
+
+    let json = format!(
+        r#"{{"tags":["work","home"],"intervals":[{}]}}"#,
+        intervals.join(",")
+    );
+    serde_json::from_str(&json).unwrap()
+}
+
+fn range_queries(c: &mut Criterion) {
+    let base = Utc.ymd(2015, 1, 1).and_hms(0, 0, 0);
+    let timelog = large_timelog(base);
+
+    let start = base + Duration::days(1000);
+    let end = start + Duration::days(1);
+    let filter =
+        filter::started_before(end) & (filter::is_open() | filter::ended_after_strict(start));
+
+    assert_eq!(
+        timelog.iter().filter(filter.build_ref()).count(),
+        timelog
+            .iter_overlapping(Some(start), Some(end))
+            .filter(filter.build_ref())
+            .count()
+    );
+
+    c.bench_function("one day, linear scan", |b| {
+        b.iter(|| timelog.iter().filter(filter.build_ref()).count())
+    });
+
+    c.bench_function("one day, start time index", |b| {
+        b.iter(|| {
+            timelog
+                .iter_overlapping(Some(start), Some(end))
+                .filter(filter.build_ref())
+                .count()
+        })
+    });
+}
+
+criterion_group!(benches, range_queries);
+criterion_main!(benches);
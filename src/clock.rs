@@ -0,0 +1,29 @@
+//! Sources of the current time.
+
+use chrono::{DateTime, Utc};
+
+/// A source of the current time.
+pub trait Clock {
+    /// Get the current time.
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// A clock that reports the real system time.
+#[derive(Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Hash, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock that always reports the same time.
+#[derive(Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Hash)]
+pub struct FixedClock(pub DateTime<Utc>);
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
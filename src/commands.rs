@@ -1,15 +1,29 @@
 //! CLI command implementations.
 
+use crate::clock::{Clock, SystemClock};
+use crate::config::{self, ConfigError, Settings};
+use crate::export::{self, ExportFormat};
 use crate::filter::{self, Filter};
-use crate::interval;
-use crate::timelog::{TimeLog, TimeLogError};
-
-use chrono::offset::Offset;
-use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use crate::interval::{format_duration, format_duration_exact, TaggedInterval};
+use crate::tags::TagId;
+use crate::template::{Template, TemplateError};
+use crate::timelog::{self, TimeLog, TimeLogError, ValidationIssue};
+use crate::zone;
+
+use chrono::{
+    DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, SecondsFormat, TimeZone,
+    Utc, Weekday,
+};
 use structopt::StructOpt;
 
-use std::collections::BTreeSet;
-use std::io::{self, Write};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, IsTerminal, Write};
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
 
 use std::error::Error;
 use std::fmt::{self, Display, Formatter};
@@ -19,32 +33,116 @@ use std::fmt::{self, Display, Formatter};
 pub enum ChangeStatus {
     Changed,
     Unchanged,
+    /// The timelog did not change, and no intervals matched the command's criteria.
+    NoMatches,
 }
 
 impl ChangeStatus {
     pub fn is_changed(self) -> bool {
         self == ChangeStatus::Changed
     }
+
+    pub fn is_no_matches(self) -> bool {
+        self == ChangeStatus::NoMatches
+    }
 }
 
 #[derive(Debug, Clone, StructOpt)]
 pub enum Command {
-    /// Open a new interval for the given tag, or the tag 'default'.
+    /// Open a new interval for the given tag, or the default tag.
+    ///
+    /// The start time is rounded down to the nearest quarter hour. If an interval for the tag was
+    /// closed at or after that time, it is reopened instead of starting a new interval, unless
+    /// --strict is given.
     Open {
         tag: Option<String>,
 
         /// Whether to allow creation of a new tag without prompt.
         #[structopt(short, long)]
         create: bool,
+
+        /// Always start a new interval, rather than reopening a recently closed one.
+        #[structopt(long, visible_alias = "new")]
+        strict: bool,
+
+        /// Open a new interval even if the tag already has an open interval.
+        ///
+        /// Each open interval for a tag must then be closed separately; see `close --index`.
+        #[structopt(long)]
+        allow_multiple: bool,
+
+        /// Also count the interval toward this tag. May be given multiple times.
+        ///
+        /// The interval is still opened and closed under its main tag, but is selected by filters
+        /// for any of its tags.
+        #[structopt(long, number_of_values = 1)]
+        also: Vec<String>,
+    },
+
+    /// Close the currently open interval for the given tag, or the default tag.
+    ///
+    /// If the tag has more than one open interval, the one that started earliest is closed unless
+    /// --index is given.
+    Close {
+        tag: Option<String>,
+
+        /// Close the open interval with this index. Open intervals for a tag are numbered from 0
+        /// in order of their start times, as shown by `status`.
+        #[structopt(long, default_value = "0")]
+        index: usize,
     },
 
-    /// Close the currently open interval for the given tag, or the tag 'default'.
-    Close { tag: Option<String> },
+    /// Continue the most recent interval for the given tag, or the default tag.
+    ///
+    /// If the most recent interval for the tag ended within the last quarter hour, it is reopened
+    /// and extended. Otherwise, a new interval is opened as with `open`.
+    Continue { tag: Option<String> },
+
+    /// Change the tag of a single interval.
+    ///
+    /// The interval changed is the one with tag FROM-TAG that contains the time given by --at, or
+    /// the most recent interval with tag FROM-TAG if --at is not given.
+    Retag {
+        from_tag: String,
+
+        to_tag: String,
+
+        /// Retag the interval containing this time.
+        #[structopt(long)]
+        at: Option<String>,
+
+        /// Require --at to be a full date and time, YYYY-MM-DD,HH:MM, rather than accepting any
+        /// time specification.
+        #[structopt(long, requires = "at")]
+        exact: bool,
+    },
 
     /// List logged intervals.
     List {
         #[structopt(flatten)]
         info: TagsInRange,
+
+        /// Print each interval using this template instead of the default layout, 'markdown' for
+        /// a Markdown table, or 'jsonl' for one JSON object per line.
+        ///
+        /// Placeholders are {tag}, {start}, {end}, and {duration}. Use {{ and }} for literal
+        /// braces, and \t and \n for tabs and newlines.
+        #[structopt(long)]
+        format: Option<ListFormat>,
+
+        /// Print only the number of matching intervals.
+        #[structopt(long, conflicts_with = "format")]
+        count_only: bool,
+
+        /// Also show the exact duration of each interval, to the second. For open intervals, this
+        /// is the exact time elapsed rather than the time rounded to the next quarter hour.
+        #[structopt(long, conflicts_with_all = &["format", "count-only"])]
+        verbose: bool,
+
+        /// If no intervals match, report how many intervals each clause of the filter criteria
+        /// matches on its own.
+        #[structopt(long, conflicts_with = "count-only")]
+        explain: bool,
     },
 
     /// Purge logged intervals.
@@ -53,10 +151,149 @@ pub enum Command {
         info: TagsInRange,
     },
 
+    /// Remove closed intervals with zero duration, or with a duration below a threshold.
+    Prune {
+        /// Remove closed intervals shorter than this duration (H, H:MM, or H:MM:SS) instead of
+        /// only those with zero duration.
+        #[structopt(long)]
+        min: Option<DurationSpec>,
+    },
+
+    /// Merge intervals of the same tags that are separated by short gaps.
+    ///
+    /// Intervals with the same tags are merged when the later one starts no more than --gap after
+    /// the earlier one ends. By default the merged intervals are only listed and the log is left
+    /// unchanged; with --write, the log is rewritten with the merged intervals after confirmation.
+    Coalesce {
+        /// The longest gap (H, H:MM, or H:MM:SS) between intervals that are merged.
+        #[structopt(long, default_value = "0")]
+        gap: DurationSpec,
+
+        /// Store the merged intervals in the log instead of only listing them.
+        #[structopt(long)]
+        write: bool,
+    },
+
+    /// Round every closed interval to quarter hours.
+    ///
+    /// Intervals are rounded when they are closed, but intervals that were merged, imported, or
+    /// edited by hand may not be. Open intervals are left unchanged.
+    Reround,
+
     /// Aggregate the durations of logged intervals.
+    ///
+    /// Only the parts of intervals that fall within the selected time range are counted, so an
+    /// interval that started yesterday contributes only its time since midnight to --today.
     Aggregate {
         #[structopt(flatten)]
         info: TagsInRange,
+
+        /// Count the whole duration of every selected interval, even the parts outside the
+        /// selected time range.
+        #[structopt(long)]
+        no_clip: bool,
+
+        /// Count intervals with several tags once for each of their tags, rather than once in
+        /// total. If tags are given, only those tags are counted.
+        #[structopt(long)]
+        per_tag: bool,
+
+        /// Also print the total for each day (labeled YYYY-MM-DD) or ISO week (labeled
+        /// YYYY-Www). Intervals are counted toward the day or week of their local start date.
+        #[structopt(long, value_name = "day|week")]
+        group_by: Option<GroupBy>,
+
+        /// With --group-by, count intervals toward the day or week of their end date instead.
+        /// Open intervals count toward the current date.
+        #[structopt(long, requires = "group-by")]
+        by_end_date: bool,
+
+        /// Round the total up to the next multiple of this many minutes. Individual intervals are
+        /// not affected.
+        #[structopt(long, value_name = "minutes")]
+        round_to: Option<NonZeroU32>,
+    },
+
+    /// List the untracked gaps between logged intervals.
+    ///
+    /// Only intervals matching the filter criteria are considered, so giving tags finds the gaps
+    /// between intervals of those tags. Each gap is listed with the intervals before and after it.
+    /// Time before the first interval and after the last is not reported.
+    Gaps {
+        #[structopt(flatten)]
+        info: TagsInRange,
+
+        /// Only report gaps at least this long (H, H:MM, or H:MM:SS).
+        #[structopt(long, default_value = "0")]
+        min_gap: DurationSpec,
+    },
+
+    /// Report statistics about logged intervals.
+    Stats {
+        #[structopt(flatten)]
+        info: TagsInRange,
+    },
+
+    /// Report time used against the budgets set with --budget.
+    ///
+    /// For each budgeted tag, reports the time allocated, the time used by intervals matching the
+    /// filter criteria, and the time remaining.
+    Budget {
+        #[structopt(flatten)]
+        info: TagsInRange,
+    },
+
+    /// Compare logged time against the estimates set with --estimate.
+    ///
+    /// For each estimated tag, reports the time estimated, the time logged by intervals matching
+    /// the filter criteria, and the variance between them, labeled as over or under the estimate.
+    Compare {
+        #[structopt(flatten)]
+        info: TagsInRange,
+    },
+
+    /// Export logged intervals for use in other tools.
+    Export {
+        #[structopt(flatten)]
+        info: TagsInRange,
+
+        /// The format to export to: 'csv', 'ics', or 'timewarrior'.
+        #[structopt(long, default_value = "csv")]
+        format: ExportFormat,
+
+        /// Write the export to this file instead of standard output.
+        #[structopt(long, parse(from_os_str))]
+        output_file: Option<PathBuf>,
+
+        /// The character separating fields of CSV exports, such as ';' for spreadsheets that use
+        /// commas as decimal separators.
+        #[structopt(long, default_value = ",")]
+        csv_delimiter: char,
+
+        /// Append to the output file instead of overwriting it.
+        ///
+        /// Only CSV exports may be appended to. If the output file already has a CSV header, only
+        /// data rows are written; files with any other first line are left untouched.
+        #[structopt(long, requires = "output-file")]
+        append: bool,
+    },
+
+    /// Compare the timelog to another log file.
+    ///
+    /// Lists the intervals added to, removed from, and changed in the timelog relative to the
+    /// other file. Intervals are matched by tag name and start time.
+    Diff {
+        /// The log file to compare against.
+        #[structopt(parse(from_os_str))]
+        other: PathBuf,
+    },
+
+    /// Show the earliest interval for each of the given tags, or the earliest interval overall if
+    /// no tags are given.
+    #[structopt(visible_alias = "oldest")]
+    First {
+        /// Tags for which to see the earliest interval.
+        tags: Vec<String>,
     },
 
     /// Report open intervals.
@@ -64,36 +301,178 @@ pub enum Command {
         /// Tags for which to see open intervals. If none are specified, see open intervals for all
         /// tags.
         tags: Vec<String>,
+
+        /// Print one line per open interval in a stable, script-parseable format.
+        ///
+        /// Each line contains the tag name, the start time in seconds since the Unix epoch, and
+        /// the elapsed time in seconds, separated by single spaces. This format is stable across
+        /// versions.
+        #[structopt(long)]
+        porcelain: bool,
+
+        /// Also list the intervals closed today, under a separate heading.
+        #[structopt(long, conflicts_with = "porcelain")]
+        include_today: bool,
+
+        /// For open intervals that started before today, also show how much of the elapsed time
+        /// falls within today.
+        #[structopt(long, conflicts_with = "porcelain")]
+        since_midnight: bool,
+    },
+
+    /// Check the timelog for structural problems.
+    Check,
+
+    /// Check the configuration and environment for problems.
+    ///
+    /// Reports the resolved logfile path, whether the logfile exists and can be parsed, the
+    /// effective time zone, and the rounding granularity. Exits with a nonzero status if any
+    /// problems are found.
+    Doctor,
+
+    /// Copy the logfile to a backup file.
+    ///
+    /// By default, the backup is written alongside the logfile, with the current time appended to
+    /// its name, and never replaces an earlier backup. Reports the path of the backup on standard
+    /// error.
+    Backup {
+        /// Write the backup to this file, or into this directory under the default name.
+        path: Option<PathBuf>,
+    },
+
+    /// Set the default tag, used by `open`, `close`, and `continue` when no tag is given.
+    ///
+    /// The default tag is stored in the log file. Until one is set, the tag 'default' is used.
+    SetDefault {
+        /// The name of the new default tag.
+        #[structopt(required_unless = "clear")]
+        tag: Option<String>,
+
+        /// Clear the default tag, so that the tag 'default' is used again.
+        #[structopt(long, conflicts_with = "tag")]
+        clear: bool,
+    },
+
+    /// Show the default tag, used by `open`, `close`, and `continue` when no tag is given.
+    Default,
+
+    /// Declare tags ahead of time, without opening any intervals.
+    ///
+    /// Declared tags can be opened without being prompted to create them, and are kept by
+    /// `tags --gc` even while no interval uses them.
+    AddTag {
+        /// The names of the tags to declare.
+        #[structopt(required = true)]
+        names: Vec<String>,
     },
 
     /// List current tags.
-    Tags,
+    Tags {
+        /// List only tag names that are not used by any interval.
+        #[structopt(long)]
+        unused: bool,
+
+        /// Remove tag names that are not used by any interval.
+        #[structopt(long)]
+        gc: bool,
+
+        /// Print a JSON array with an object for each tag, giving its ID, name, number of
+        /// intervals, total time in minutes, and when it was last active.
+        #[structopt(long, conflicts_with_all = &["unused", "gc"])]
+        json: bool,
+    },
+
+    /// Any other subcommand NAME is run as the external command `timelog-NAME`, found on the
+    /// PATH, with the remaining arguments.
+    #[structopt(external_subcommand)]
+    External(Vec<String>),
 }
 
 impl Command {
-    /// Execute this command with the given timelog and output streams.
+    /// The interval filter criteria of this command, if it only reads the intervals matching
+    /// them.
+    ///
+    /// For such commands, the timelog need only contain the matching intervals, so it can be read
+    /// with `Options::current_timelog_filtered`.
+    pub fn read_only_filter(&self) -> Option<&TagsInRange> {
+        match self {
+            Command::List { explain: true, .. } => None,
+            Command::List { info, .. }
+            | Command::Aggregate { info, .. }
+            | Command::Gaps { info, .. }
+            | Command::Stats { info }
+            | Command::Budget { info }
+            | Command::Compare { info }
+            | Command::Export { info, .. } => Some(info),
+            _ => None,
+        }
+    }
+
+    /// Does this command only read the timelog, never changing it?
+    ///
+    /// This includes every command with a `read_only_filter`.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only_filter().is_some()
+            || matches!(
+                self,
+                Command::List { .. }
+                    | Command::Diff { .. }
+                    | Command::First { .. }
+                    | Command::Status { .. }
+                    | Command::Check
+                    | Command::Doctor
+                    | Command::Backup { .. }
+                    | Command::Default
+                    | Command::Tags { gc: false, .. }
+            )
+    }
+
+    /// Execute this command with the given timelog, output streams, and settings.
     ///
     /// On success, returns whether the timelog was changed as a result of this command.
     pub fn execute<W>(
         &self,
         timelog: &mut TimeLog,
         outputs: Outputs<W>,
+        settings: &Settings,
     ) -> Result<ChangeStatus, CommandError>
     where
         W: Write,
     {
         let mut context = CommandContext {
             command: self,
+            settings,
             timelog,
             outputs,
         };
 
         context.execute()
     }
+
+    /// Execute this command with the given timelog and default settings, capturing its output.
+    ///
+    /// On success, returns whether the timelog was changed, along with everything the command
+    /// wrote to its output and error streams.
+    pub fn run_to_string(
+        &self,
+        timelog: &mut TimeLog,
+    ) -> Result<(ChangeStatus, String, String), CommandError> {
+        let mut output = Vec::new();
+        let mut error = Vec::new();
+        let outputs = Outputs::new(&mut output, Some(&mut error));
+        let status = self.execute(timelog, outputs, &Settings::default())?;
+
+        Ok((
+            status,
+            String::from_utf8_lossy(&output).into_owned(),
+            String::from_utf8_lossy(&error).into_owned(),
+        ))
+    }
 }
 
 struct CommandContext<'c, 't, W> {
     command: &'c Command,
+    settings: &'c Settings,
     timelog: &'t mut TimeLog,
     outputs: Outputs<W>,
 }
@@ -104,97 +483,425 @@ where
 {
     fn execute(&mut self) -> Result<ChangeStatus, CommandError> {
         match self.command {
-            Command::Open { tag, create } => self.open(
-                &tag.as_ref().cloned().unwrap_or_else(|| "default".into()),
-                *create,
-            ),
-            Command::Close { tag } => {
-                self.close(&tag.as_ref().cloned().unwrap_or_else(|| "default".into()))
+            Command::Open {
+                tag,
+                create,
+                strict,
+                allow_multiple,
+                also,
+            } => {
+                let tag = self.tag_name(&tag.clone().unwrap_or_else(|| self.default_tag()));
+                let also: Vec<_> = also.iter().map(|name| self.tag_name(name)).collect();
+                self.open(&tag, &also, *create, !*strict, *allow_multiple)
+            }
+            Command::Close { tag, index } => {
+                let tag = self.tag_name(&tag.clone().unwrap_or_else(|| self.default_tag()));
+                self.close(&tag, *index)
+            }
+            Command::Continue { tag } => {
+                let tag = self.tag_name(&tag.clone().unwrap_or_else(|| self.default_tag()));
+                self.continue_(&tag)
+            }
+            Command::Retag {
+                from_tag,
+                to_tag,
+                at,
+                exact,
+            } => {
+                let at = match at {
+                    Some(at) if *exact => Some(exact_datetime_from_str(at)?),
+                    Some(at) => Some(datetime_from_str(at)?),
+                    None => None,
+                };
+                let (from_tag, to_tag) = (self.tag_name(from_tag), self.tag_name(to_tag));
+                self.retag(&from_tag, &to_tag, at)
             }
-            Command::List { info } => {
+            Command::List {
+                info,
+                format,
+                count_only,
+                verbose,
+                explain,
+            } => {
                 info.log_debug();
-                self.list(info)
+                if *count_only {
+                    self.count(info)
+                } else {
+                    self.list(info, format.as_ref(), *verbose, *explain)
+                }
             }
             Command::Purge { info } => {
                 info.log_debug();
                 self.purge(info)
             }
-            Command::Aggregate { info } => {
+            Command::Prune { min } => self.prune(min.map(DurationSpec::duration)),
+            Command::Coalesce { gap, write } => self.coalesce(gap.duration(), *write),
+            Command::Reround => self.reround(),
+            Command::Aggregate {
+                info,
+                no_clip,
+                per_tag,
+                group_by,
+                by_end_date,
+                round_to,
+            } => {
                 info.log_debug();
-                self.aggregate(info)
+                let group_by = group_by.map(|group_by| (group_by, *by_end_date));
+                self.aggregate(info, !*no_clip, *per_tag, group_by, *round_to)
+            }
+            Command::Gaps { info, min_gap } => {
+                info.log_debug();
+                self.gaps(info, min_gap.duration())
+            }
+            Command::Stats { info } => {
+                info.log_debug();
+                self.stats(info)
+            }
+            Command::Budget { info } => {
+                info.log_debug();
+                self.budget(info)
+            }
+            Command::Compare { info } => {
+                info.log_debug();
+                self.compare(info)
+            }
+            Command::Export {
+                info,
+                format,
+                output_file,
+                csv_delimiter,
+                append,
+            } => {
+                info.log_debug();
+                self.export(info, *format, *csv_delimiter, output_file.as_ref(), *append)
+            }
+            Command::Diff { other } => self.diff(other),
+            Command::First { tags } => self.first(tags),
+            Command::Status {
+                tags,
+                porcelain,
+                include_today,
+                since_midnight,
+            } => self.status(tags.as_ref(), *porcelain, *include_today, *since_midnight),
+
+            Command::Check => self.check(),
+            // Diagnosed by `Options::doctor`, before any logfile is loaded.
+            Command::Doctor => Ok(ChangeStatus::Unchanged),
+            // Written by `Options::backup`, which copies the logfile without loading it.
+            Command::Backup { .. } => Ok(ChangeStatus::Unchanged),
+            // Run as a separate process by the binary.
+            Command::External(_) => Ok(ChangeStatus::Unchanged),
+
+            Command::SetDefault { tag, .. } => self.set_default(tag.as_deref()),
+            Command::Default => {
+                let tag = self.default_tag();
+                writeln!(self.outputs.output_mut(), "{}", tag)?;
+                Ok(ChangeStatus::Unchanged)
+            }
+            Command::AddTag { names } => self.add_tags(names),
+            Command::Tags { unused, gc, json } => {
+                if *gc {
+                    self.gc_tags()
+                } else if *unused {
+                    self.unused_tags()
+                } else if *json {
+                    self.tags_json()
+                } else {
+                    self.tags()
+                }
             }
-            Command::Status { tags } => self.status(tags.as_ref()),
+        }
+    }
 
-            Command::Tags => self.tags(),
+    /// The name of the tag to use when none is given.
+    ///
+    /// This is the current git branch if the git branch tag setting is enabled and one is checked
+    /// out. Otherwise, it is the timelog's default tag if one is set, or the tag 'default'.
+    fn default_tag(&self) -> String {
+        if self.settings.git_branch_tag {
+            if let Some(branch) = git_branch(Path::new(".")) {
+                return branch;
+            }
         }
+
+        self.timelog.default_tag().unwrap_or("default").into()
+    }
+
+    /// The name of the existing tag that the given tag name refers to, or the given name if it
+    /// refers to no existing tag.
+    fn tag_name(&self, name: &str) -> String {
+        find_tag(self.timelog, name, self.settings)
+            .and_then(|tag| self.timelog.tag_name(tag))
+            .unwrap_or(name)
+            .into()
     }
 
-    fn open(&mut self, tag: &str, create: bool) -> Result<ChangeStatus, CommandError> {
-        if self.timelog.tag_id(tag).is_none() && tag != "default" && !create {
-            writeln!(self.outputs.error_mut(), "Creating new tag '{}'.", tag)?;
+    fn open(
+        &mut self,
+        tag: &str,
+        also: &[String],
+        create: bool,
+        reopen: bool,
+        allow_multiple: bool,
+    ) -> Result<ChangeStatus, CommandError> {
+        let new_tags: Vec<_> = std::iter::once(tag)
+            .chain(also.iter().map(String::as_str))
+            .filter(|&name| self.timelog.tag_id(name).is_none() && name != "default")
+            .collect();
+        if !new_tags.is_empty() && !create {
+            for name in new_tags {
+                writeln!(self.outputs.error_mut(), "Creating new tag '{}'.", name)?;
+            }
             if !self.user_confirmation(false)? {
-                writeln!(self.outputs.error_mut(), "Cancelling open")?;
+                writeln!(self.outputs.info_mut(), "Cancelling open")?;
                 return Ok(ChangeStatus::Unchanged);
             }
         }
 
-        match self.timelog.open(tag) {
-            Ok(int) => {
-                let start = Local.from_utc_datetime(&int.start().naive_utc());
+        let result = if allow_multiple {
+            Ok(self.timelog.open_another(tag))
+        } else {
+            self.timelog.open(tag, reopen)
+        };
+
+        match result {
+            Ok(mut int) => {
+                for extra in also {
+                    int = self.timelog.add_extra_tag(&int, extra)?;
+                }
+                let start = zone::current().from_utc(&int.start());
                 writeln!(
-                    self.outputs.error_mut(),
+                    self.outputs.info_mut(),
                     "Opened new interval for tag '{}' at {}",
                     tag,
-                    start.format(interval::FMT_STR)
+                    start.format(self.settings.time_format.as_str())
                 )?;
+                if let Some(ahead) =
+                    timelog::start_skew(&int, Utc::now(), self.settings.skew_threshold)
+                {
+                    writeln!(
+                        self.outputs.error_mut(),
+                        "Warning: interval starts {} in the future; has the system clock moved \
+                         backward?",
+                        format_duration(ahead)
+                    )?;
+                }
+                self.run_hook(self.settings.on_open.as_ref(), tag, int.start())?;
                 Ok(ChangeStatus::Changed)
             }
             Err(err) => Err(err.into()),
         }
     }
 
-    fn close(&mut self, tag: &str) -> Result<ChangeStatus, CommandError> {
-        match self.timelog.close(tag) {
+    fn close(&mut self, tag: &str, index: usize) -> Result<ChangeStatus, CommandError> {
+        let min = self.settings.min_duration.unwrap_or_else(Duration::zero);
+        match self.timelog.close_nth_at_least(tag, index, min) {
             Ok(int) => {
                 writeln!(
-                    self.outputs.error_mut(),
+                    self.outputs.info_mut(),
                     "Closed interval for tag '{}': {}",
                     tag,
-                    int.interval()
+                    int.interval().display_with(&self.settings.time_format)
                 )?;
+                self.run_hook(self.settings.on_close.as_ref(), tag, int.end().unwrap())?;
                 Ok(ChangeStatus::Changed)
             }
+            Err(TimeLogError::TooShort) => {
+                writeln!(
+                    self.outputs.error_mut(),
+                    "Interval for tag '{}' is shorter than the minimum duration of {}. Discard it?",
+                    tag,
+                    format_duration(min)
+                )?;
+                if self.user_confirmation(false)? {
+                    self.timelog.discard_nth(tag, index)?;
+                    writeln!(self.outputs.info_mut(), "Discarded interval")?;
+                    Ok(ChangeStatus::Changed)
+                } else {
+                    writeln!(self.outputs.info_mut(), "Leaving interval open")?;
+                    Ok(ChangeStatus::Unchanged)
+                }
+            }
+            Err(TimeLogError::StartsInFuture) => {
+                writeln!(
+                    self.outputs.error_mut(),
+                    "Interval for tag '{}' starts after the current time; has the system clock \
+                     moved backward? Discard it?",
+                    tag
+                )?;
+                if self.user_confirmation(false)? {
+                    self.timelog.discard_nth(tag, index)?;
+                    writeln!(self.outputs.info_mut(), "Discarded interval")?;
+                    Ok(ChangeStatus::Changed)
+                } else {
+                    writeln!(
+                        self.outputs.info_mut(),
+                        "Leaving interval open; close it again once the clock has caught up"
+                    )?;
+                    Ok(ChangeStatus::Unchanged)
+                }
+            }
             Err(err) => Err(err.into()),
         }
     }
 
-    fn list(&mut self, info: &TagsInRange) -> Result<ChangeStatus, CommandError> {
-        let filter = info.filter(self.timelog)?;
-        self.list_filter(&filter)?;
-        Ok(ChangeStatus::Unchanged)
+    fn continue_(&mut self, tag: &str) -> Result<ChangeStatus, CommandError> {
+        match self.timelog.resume(tag, Duration::minutes(15))? {
+            Some(int) => {
+                let start = zone::current().from_utc(&int.start());
+                writeln!(
+                    self.outputs.info_mut(),
+                    "Continued interval for tag '{}' started at {}",
+                    tag,
+                    start.format(self.settings.time_format.as_str())
+                )?;
+                self.run_hook(self.settings.on_open.as_ref(), tag, int.start())?;
+                Ok(ChangeStatus::Changed)
+            }
+            None => self.open(tag, &[], false, true, false),
+        }
     }
 
-    fn list_filter(&mut self, filter: &Filter) -> Result<(), CommandError> {
-        let max_tagwidth = self
-            .timelog
-            .iter()
-            .filter_map(|int| {
-                if filter.eval(int) {
-                    Some(self.timelog.tag_name(int.tag()).unwrap().len())
-                } else {
-                    None
+    fn retag(
+        &mut self,
+        from: &str,
+        to: &str,
+        at: Option<DateTime<Utc>>,
+    ) -> Result<ChangeStatus, CommandError> {
+        let int = self.timelog.retag(from, to, at)?;
+        writeln!(
+            self.outputs.info_mut(),
+            "Retagged interval {} from '{}' to '{}'",
+            int.interval().display_with(&self.settings.time_format),
+            from,
+            to
+        )?;
+
+        if self.timelog.unused_tag_names().any(|name| name == from) {
+            self.timelog.gc_tag_names();
+        }
+
+        Ok(ChangeStatus::Changed)
+    }
+
+    fn list(
+        &mut self,
+        info: &TagsInRange,
+        format: Option<&ListFormat>,
+        verbose: bool,
+        explain: bool,
+    ) -> Result<ChangeStatus, CommandError> {
+        let selection = info.select(self.timelog, self.settings)?;
+        if selection.iter(self.timelog).next().is_none() {
+            if explain {
+                self.explain(&selection.filter)?;
+            }
+            return Ok(ChangeStatus::NoMatches);
+        }
+
+        match format {
+            Some(ListFormat::Template(template)) => {
+                for int in selection.iter(self.timelog) {
+                    let tag = self.timelog.tag_name(int.tag()).unwrap();
+                    let line = template.render(tag, int.interval(), &self.settings.time_format);
+                    writeln!(self.outputs.output_mut(), "{}", line)?;
                 }
-            })
-            .max()
-            .unwrap_or(0);
+            }
+            Some(ListFormat::Markdown) => {
+                let row: Template = "| {tag} | {start} | {end} | {duration} |".parse().unwrap();
+                writeln!(
+                    self.outputs.output_mut(),
+                    "| tag | start | end | duration |"
+                )?;
+                writeln!(self.outputs.output_mut(), "| --- | --- | --- | ---: |")?;
+                for int in selection.iter(self.timelog) {
+                    let tag = markdown_escape(self.timelog.tag_name(int.tag()).unwrap());
+                    let line = row.render(&tag, int.interval(), &self.settings.time_format);
+                    writeln!(self.outputs.output_mut(), "{}", line)?;
+                }
+            }
+            Some(ListFormat::JsonLines) => {
+                for int in selection.iter(self.timelog) {
+                    let object = serde_json::json!({
+                        "tag": self.timelog.tag_name(int.tag()).unwrap(),
+                        "start": int.start().to_rfc3339_opts(SecondsFormat::Secs, true),
+                        "end": int.end().map(|end| end.to_rfc3339_opts(SecondsFormat::Secs, true)),
+                        "duration": int.duration_clamped().num_seconds(),
+                    });
+                    writeln!(self.outputs.output_mut(), "{}", object)?;
+                }
+            }
+            None => self.list_filter(&selection, verbose)?,
+        }
+
+        Ok(ChangeStatus::Unchanged)
+    }
+
+    /// Report how many intervals each conjunct of the filter matches on its own.
+    fn explain(&mut self, filter: &Filter) -> Result<(), CommandError> {
+        let total = self.timelog.iter().count();
+        writeln!(
+            self.outputs.error_mut(),
+            "No intervals match filter criteria. Of {} interval{}, each clause alone matches:",
+            total,
+            if total == 1 { "" } else { "s" }
+        )?;
+
+        for clause in filter.conjuncts() {
+            let count = self.timelog.query(&clause).count();
+            writeln!(self.outputs.error_mut(), "  {:>6}  {}", count, clause)?;
+        }
+
+        for tag in filter.tag_ids() {
+            let name = self.timelog.tag_name(tag).unwrap_or("?");
+            writeln!(self.outputs.error_mut(), "where tag {} is '{}'", tag, name)?;
+        }
+
+        Ok(())
+    }
+
+    fn count(&mut self, info: &TagsInRange) -> Result<ChangeStatus, CommandError> {
+        let selection = info.select(self.timelog, self.settings)?;
+        let count = selection.iter(self.timelog).count();
+        writeln!(self.outputs.output_mut(), "{}", count)?;
+
+        if count == 0 {
+            Ok(ChangeStatus::NoMatches)
+        } else {
+            Ok(ChangeStatus::Unchanged)
+        }
+    }
+
+    fn list_filter(&mut self, selection: &Selection, verbose: bool) -> Result<(), CommandError> {
+        let rows: Vec<_> = selection.iter(self.timelog).cloned().collect();
+        self.list_rows(&rows, verbose)
+    }
+
+    /// The names of all tags of an interval, joined by `+`, with the primary tag first.
+    fn tags_label(&self, int: &TaggedInterval) -> String {
+        let names: Vec<_> = int
+            .tags()
+            .map(|tag| self.timelog.tag_name(tag).unwrap())
+            .collect();
+        names.join("+")
+    }
+
+    /// Print the given intervals as a table of tag names and intervals.
+    fn list_rows(&mut self, rows: &[TaggedInterval], verbose: bool) -> Result<(), CommandError> {
+        let rows: Vec<_> = rows.iter().map(|int| (self.tags_label(int), int)).collect();
+        let max_tagwidth = rows.iter().map(|(tag, _)| tag.len()).max().unwrap_or(0);
+
+        for (tag, int) in rows {
+            let mut display = int.interval().display_with(&self.settings.time_format);
+            if verbose {
+                display = display.verbose();
+            }
 
-        for int in self.timelog.iter().filter(filter.build_ref()) {
-            let tag = self.timelog.tag_name(int.tag()).unwrap();
             writeln!(
                 self.outputs.output_mut(),
                 "{:<width$} | {}",
                 tag,
-                int.interval(),
+                display,
                 width = max_tagwidth
             )?;
         }
@@ -203,157 +910,965 @@ where
     }
 
     fn purge(&mut self, info: &TagsInRange) -> Result<ChangeStatus, CommandError> {
-        let filter = info.filter(self.timelog)?;
-        let filter_fn = filter.build();
+        let selection = info.select(self.timelog, self.settings)?;
 
-        if self.timelog.iter().any(&filter_fn) {
-            if filter.evals_true() {
+        if selection.iter(self.timelog).next().is_some() {
+            if selection.filter.evals_true() {
                 writeln!(self.outputs.error_mut(), "Purging ALL INTERVALS!")?;
             } else {
                 writeln!(self.outputs.error_mut(), "Purging the following intervals:")?;
-                self.list_filter(&filter)?;
+                self.list_filter(&selection, false)?;
             }
 
             if self.user_confirmation(false)? {
-                writeln!(self.outputs.error_mut(), "Purging.")?;
-                self.timelog.remove(&filter_fn);
+                writeln!(self.outputs.info_mut(), "Purging.")?;
+                self.timelog.remove(selection.filter.build());
                 self.timelog.gc_tag_names();
                 Ok(ChangeStatus::Changed)
             } else {
-                writeln!(self.outputs.error_mut(), "Purge cancelled.")?;
+                writeln!(self.outputs.info_mut(), "Purge cancelled.")?;
                 Ok(ChangeStatus::Unchanged)
             }
         } else {
             writeln!(
-                self.outputs.error_mut(),
+                self.outputs.info_mut(),
                 "No intervals match filter criteria; purge cancelled."
             )?;
-            Ok(ChangeStatus::Unchanged)
+            Ok(ChangeStatus::NoMatches)
+        }
+    }
+
+    fn prune(&mut self, min: Option<Duration>) -> Result<ChangeStatus, CommandError> {
+        let filter = filter::is_closed()
+            & min.map_or_else(
+                || filter::shorter_than(Duration::zero()),
+                filter::shorter_than_strict,
+            );
+
+        let count = self.timelog.query(&filter).count();
+        if count == 0 {
+            writeln!(self.outputs.info_mut(), "No intervals to prune.")?;
+            return Ok(ChangeStatus::NoMatches);
+        }
+
+        writeln!(
+            self.outputs.error_mut(),
+            "Pruning {} interval{}:",
+            count,
+            if count == 1 { "" } else { "s" }
+        )?;
+        self.list_filter(&filter.clone().into(), false)?;
+
+        if self.user_confirmation(false)? {
+            writeln!(self.outputs.info_mut(), "Pruning.")?;
+            self.timelog.remove(filter.build());
+            self.timelog.gc_tag_names();
+            Ok(ChangeStatus::Changed)
+        } else {
+            writeln!(self.outputs.info_mut(), "Prune cancelled.")?;
+            Ok(ChangeStatus::Unchanged)
+        }
+    }
+
+    fn coalesce(&mut self, gap: Duration, write: bool) -> Result<ChangeStatus, CommandError> {
+        let coalesced = self.timelog.coalesced(gap);
+        let merged = self.timelog.iter().count() - coalesced.len();
+
+        if !write {
+            self.list_rows(&coalesced, false)?;
+            writeln!(
+                self.outputs.info_mut(),
+                "{} interval{} would be merged; use --write to store the result.",
+                merged,
+                if merged == 1 { "" } else { "s" }
+            )?;
+            return Ok(ChangeStatus::Unchanged);
+        }
+
+        if merged == 0 {
+            writeln!(self.outputs.info_mut(), "No intervals to coalesce.")?;
+            return Ok(ChangeStatus::NoMatches);
+        }
+
+        let changed: Vec<_> = coalesced
+            .into_iter()
+            .filter(|int| !self.timelog.iter().any(|other| other == int))
+            .collect();
+        writeln!(
+            self.outputs.error_mut(),
+            "Merging {} interval{} into the following:",
+            merged + changed.len(),
+            if merged + changed.len() == 1 { "" } else { "s" }
+        )?;
+        self.list_rows(&changed, false)?;
+
+        if self.user_confirmation(false)? {
+            writeln!(self.outputs.info_mut(), "Coalescing.")?;
+            self.timelog.coalesce(gap);
+            Ok(ChangeStatus::Changed)
+        } else {
+            writeln!(self.outputs.info_mut(), "Coalesce cancelled.")?;
+            Ok(ChangeStatus::Unchanged)
+        }
+    }
+
+    fn reround(&mut self) -> Result<ChangeStatus, CommandError> {
+        let unrounded: Vec<_> = self
+            .timelog
+            .iter()
+            .filter(|int| int.is_closed() && int.round_to_quarter_hours() != **int)
+            .cloned()
+            .collect();
+        if unrounded.is_empty() {
+            writeln!(
+                self.outputs.info_mut(),
+                "All closed intervals are already rounded."
+            )?;
+            return Ok(ChangeStatus::NoMatches);
+        }
+
+        writeln!(
+            self.outputs.error_mut(),
+            "Rounding {} interval{} to quarter hours:",
+            unrounded.len(),
+            if unrounded.len() == 1 { "" } else { "s" }
+        )?;
+        self.list_rows(&unrounded, false)?;
+
+        if self.user_confirmation(false)? {
+            let changed = self.timelog.round_closed_intervals();
+            writeln!(
+                self.outputs.info_mut(),
+                "Rounded {} interval{}.",
+                changed,
+                if changed == 1 { "" } else { "s" }
+            )?;
+            Ok(ChangeStatus::Changed)
+        } else {
+            writeln!(self.outputs.info_mut(), "Reround cancelled.")?;
+            Ok(ChangeStatus::Unchanged)
+        }
+    }
+
+    fn aggregate(
+        &mut self,
+        info: &TagsInRange,
+        clip: bool,
+        per_tag: bool,
+        group_by: Option<(GroupBy, bool)>,
+        round_to: Option<NonZeroU32>,
+    ) -> Result<ChangeStatus, CommandError> {
+        let selection = info.select(self.timelog, self.settings)?;
+        if selection.iter(self.timelog).next().is_none() {
+            writeln!(
+                self.outputs.info_mut(),
+                "No intervals match filter criteria."
+            )?;
+            return Ok(ChangeStatus::NoMatches);
+        }
+
+        let (start, end) = if clip {
+            (selection.start, selection.end)
+        } else {
+            (None, None)
+        };
+
+        writeln!(
+            self.outputs.info_mut(),
+            "Aggregating the following intervals:"
+        )?;
+        self.list_filter(&selection, false)?;
+
+        let counted_tags = info.tag_ids(self.timelog, self.settings);
+        let weight = |int: &TaggedInterval| {
+            if per_tag {
+                int.tags()
+                    .filter(|tag| counted_tags.is_empty() || counted_tags.contains(tag))
+                    .count() as i32
+            } else {
+                1
+            }
+        };
+
+        // With --per-tag, an interval counts once for each of its counted tags, which is the sum
+        // of the totals for each tag alone.
+        let total = if per_tag {
+            let tags: BTreeSet<_> = if counted_tags.is_empty() {
+                self.timelog.tags().map(|(tag, _)| tag).collect()
+            } else {
+                counted_tags.iter().copied().collect()
+            };
+            tags.into_iter()
+                .map(|tag| {
+                    let filter = selection.filter.clone() & filter::has_tag(tag);
+                    self.timelog.aggregate_within(&filter, start, end)
+                })
+                .fold(Duration::zero(), |total, d| total + d)
+        } else {
+            self.timelog.aggregate_within(&selection.filter, start, end)
+        };
+
+        if let Some((group_by, by_end_date)) = group_by {
+            let mut groups: BTreeMap<String, Duration> = BTreeMap::new();
+            for int in selection.iter(self.timelog) {
+                let time = if by_end_date {
+                    int.end().unwrap_or_else(Utc::now)
+                } else {
+                    int.start()
+                };
+                let date = day_of(zone::current().from_utc(&time), self.settings);
+                let group = groups
+                    .entry(group_by.label(date))
+                    .or_insert_with(Duration::zero);
+                *group = *group + int.interval().duration_within(start, end) * weight(int);
+            }
+
+            for (label, duration) in groups {
+                writeln!(
+                    self.outputs.output_mut(),
+                    "{} {}",
+                    label,
+                    format_duration(duration)
+                )?;
+            }
+        }
+
+        let total = match round_to {
+            Some(minutes) => round_up_to_minutes(total, minutes),
+            None => total,
+        };
+
+        writeln!(
+            self.outputs.output_mut(),
+            "Total {}",
+            format_duration(total)
+        )?;
+
+        Ok(ChangeStatus::Unchanged)
+    }
+
+    fn stats(&mut self, info: &TagsInRange) -> Result<ChangeStatus, CommandError> {
+        let selection = info.select(self.timelog, self.settings)?;
+
+        let mut durations: Vec<_> = selection
+            .iter(self.timelog)
+            .map(|int| int.duration_clamped())
+            .collect();
+
+        if durations.is_empty() {
+            writeln!(
+                self.outputs.info_mut(),
+                "No intervals match filter criteria."
+            )?;
+            return Ok(ChangeStatus::NoMatches);
+        }
+
+        durations.sort();
+
+        let count = durations.len();
+        let total = durations
+            .iter()
+            .fold(Duration::seconds(0), |total, &d| total + d);
+        let mean = total / count as i32;
+        let median = if count % 2 == 0 {
+            (durations[count / 2 - 1] + durations[count / 2]) / 2
+        } else {
+            durations[count / 2]
+        };
+
+        let active_days: BTreeSet<_> = selection
+            .iter(self.timelog)
+            .map(|int| day_of(zone::current().from_utc(&int.start()), self.settings))
+            .collect();
+
+        let per_day = count as f64 / active_days.len() as f64;
+        let rows = [
+            ("Intervals:", count.to_string()),
+            ("Total:", format_duration(total)),
+            ("Mean:", format_duration(mean)),
+            ("Median:", format_duration(median)),
+            ("Longest:", format_duration(durations[count - 1])),
+            ("Shortest:", format_duration(durations[0])),
+            ("Intervals per active day:", format!("{:.2}", per_day)),
+        ];
+
+        for (label, value) in rows.iter() {
+            writeln!(self.outputs.output_mut(), "{:<26}{}", label, value)?;
+        }
+
+        Ok(ChangeStatus::Unchanged)
+    }
+
+    fn gaps(
+        &mut self,
+        info: &TagsInRange,
+        min_gap: Duration,
+    ) -> Result<ChangeStatus, CommandError> {
+        let selection = info.select(self.timelog, self.settings)?;
+        let mut ints: Vec<_> = selection.iter(self.timelog).collect();
+        ints.sort_by(|a, b| TaggedInterval::by_start(a, b));
+
+        // Each gap runs from the latest end of the intervals before it to the start of the next.
+        let mut gaps = Vec::new();
+        let mut latest: Option<(DateTime<Utc>, &TaggedInterval)> = None;
+        for int in ints {
+            let end = int.start() + int.duration_clamped();
+            if let Some((prev_end, prev)) = latest {
+                let gap = int.start() - prev_end;
+                if gap > Duration::zero() && gap >= min_gap {
+                    gaps.push((prev_end, int.start(), prev, int));
+                }
+                if end <= prev_end {
+                    continue;
+                }
+            }
+            latest = Some((end, int));
+        }
+
+        if gaps.is_empty() {
+            writeln!(self.outputs.info_mut(), "No gaps found.")?;
+            return Ok(ChangeStatus::NoMatches);
+        }
+
+        let fmt = self.settings.time_format.as_str();
+        let lines: Vec<_> = gaps
+            .into_iter()
+            .map(|(start, end, before, after)| {
+                format!(
+                    "{} -- {} ({}) | after {}, before {}",
+                    zone::current().from_utc(&start).format(fmt),
+                    zone::current().from_utc(&end).format(fmt),
+                    format_duration(end - start),
+                    self.tags_label(before),
+                    self.tags_label(after)
+                )
+            })
+            .collect();
+        for line in lines {
+            writeln!(self.outputs.output_mut(), "{}", line)?;
+        }
+
+        Ok(ChangeStatus::Unchanged)
+    }
+
+    fn budget(&mut self, info: &TagsInRange) -> Result<ChangeStatus, CommandError> {
+        if self.settings.budgets.is_empty() {
+            writeln!(self.outputs.info_mut(), "No budgets set.")?;
+            return Ok(ChangeStatus::NoMatches);
+        }
+
+        let selection = info.select(self.timelog, self.settings)?;
+        let max_tagwidth = self
+            .settings
+            .budgets
+            .iter()
+            .map(|budget| budget.tag.len())
+            .max()
+            .unwrap_or(0)
+            .max(3);
+
+        writeln!(
+            self.outputs.output_mut(),
+            "{:<width$} | {:>9} | {:>9} | {:>9}",
+            "Tag",
+            "Allocated",
+            "Used",
+            "Remaining",
+            width = max_tagwidth
+        )?;
+
+        for budget in &self.settings.budgets {
+            let used = match find_tag(self.timelog, &budget.tag, self.settings) {
+                Some(tag) => {
+                    let filter = selection.filter.clone() & filter::has_tag(tag);
+                    self.timelog
+                        .aggregate_within(&filter, selection.start, selection.end)
+                }
+                None => Duration::seconds(0),
+            };
+
+            let remaining = budget.allocated - used;
+            let over = if remaining < Duration::zero() {
+                " OVER BUDGET"
+            } else {
+                ""
+            };
+
+            writeln!(
+                self.outputs.output_mut(),
+                "{:<width$} | {:>9} | {:>9} | {:>9}{}",
+                budget.tag,
+                format_duration(budget.allocated),
+                format_duration(used),
+                format_duration(remaining),
+                over,
+                width = max_tagwidth
+            )?;
+        }
+
+        Ok(ChangeStatus::Unchanged)
+    }
+
+    fn compare(&mut self, info: &TagsInRange) -> Result<ChangeStatus, CommandError> {
+        if self.settings.estimates.is_empty() {
+            writeln!(self.outputs.info_mut(), "No estimates set.")?;
+            return Ok(ChangeStatus::NoMatches);
+        }
+
+        let selection = info.select(self.timelog, self.settings)?;
+        let max_tagwidth = self
+            .settings
+            .estimates
+            .iter()
+            .map(|estimate| estimate.tag.len())
+            .max()
+            .unwrap_or(0)
+            .max(3);
+
+        writeln!(
+            self.outputs.output_mut(),
+            "{:<width$} | {:>9} | {:>9} | {:>9}",
+            "Tag",
+            "Estimated",
+            "Actual",
+            "Variance",
+            width = max_tagwidth
+        )?;
+
+        for estimate in &self.settings.estimates {
+            let actual = match find_tag(self.timelog, &estimate.tag, self.settings) {
+                Some(tag) => {
+                    let filter = selection.filter.clone() & filter::has_tag(tag);
+                    self.timelog
+                        .aggregate_within(&filter, selection.start, selection.end)
+                }
+                None => Duration::seconds(0),
+            };
+
+            let variance = actual - estimate.estimated;
+            let (sign, label) = match variance.cmp(&Duration::zero()) {
+                Ordering::Greater => ("+", " OVER ESTIMATE"),
+                Ordering::Less => ("", " UNDER ESTIMATE"),
+                Ordering::Equal => ("", ""),
+            };
+
+            writeln!(
+                self.outputs.output_mut(),
+                "{:<width$} | {:>9} | {:>9} | {:>9}{}",
+                estimate.tag,
+                format_duration(estimate.estimated),
+                format_duration(actual),
+                format!("{}{}", sign, format_duration(variance)),
+                label,
+                width = max_tagwidth
+            )?;
+        }
+
+        Ok(ChangeStatus::Unchanged)
+    }
+
+    fn export(
+        &mut self,
+        info: &TagsInRange,
+        format: ExportFormat,
+        csv_delimiter: char,
+        output_file: Option<&PathBuf>,
+        append: bool,
+    ) -> Result<ChangeStatus, CommandError> {
+        if ['"', '\n', '\r'].contains(&csv_delimiter) {
+            return Err(CommandError::InvalidArguments(format!(
+                "invalid CSV delimiter {:?}",
+                csv_delimiter
+            )));
+        }
+
+        let filter = info.filter(self.timelog, self.settings)?;
+        if !self.timelog.iter().any(filter.build()) {
+            return Ok(ChangeStatus::NoMatches);
+        }
+
+        if let (Some(path), true) = (output_file, append) {
+            self.export_append(&filter, format, csv_delimiter, path)?;
+        } else if let Some(path) = output_file {
+            let mut file = File::create(path)
+                .map_err(|err| CommandError::CannotCreateOutputFile(path.clone(), err))?;
+            write_export(self.timelog, &filter, format, csv_delimiter, &mut file)?;
+        } else {
+            let out = self.outputs.output_mut();
+            write_export(self.timelog, &filter, format, csv_delimiter, out)?;
+        }
+
+        Ok(ChangeStatus::Unchanged)
+    }
+
+    fn export_append(
+        &mut self,
+        filter: &Filter,
+        format: ExportFormat,
+        csv_delimiter: char,
+        path: &Path,
+    ) -> Result<(), CommandError> {
+        if format != ExportFormat::Csv {
+            return Err(CommandError::InvalidArguments(
+                "--append is only supported for CSV exports".into(),
+            ));
+        }
+
+        let existing = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(CommandError::CannotReadFile(path.to_path_buf(), err)),
+        };
+        let header = export::csv_header(csv_delimiter);
+        if !existing.is_empty() && existing.lines().next() != Some(header.as_str()) {
+            return Err(CommandError::InvalidArguments(format!(
+                "cannot append to '{}': it does not start with a CSV export header",
+                path.display()
+            )));
+        }
+
+        let mut file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)
+            .map_err(|err| CommandError::CannotCreateOutputFile(path.to_path_buf(), err))?;
+        if existing.is_empty() {
+            export::write_csv(self.timelog, filter, csv_delimiter, &mut file)?;
+        } else {
+            if !existing.ends_with('\n') {
+                writeln!(file)?;
+            }
+            export::write_csv_rows(self.timelog, filter, csv_delimiter, &mut file)?;
+        }
+
+        Ok(())
+    }
+
+    fn diff(&mut self, other: &Path) -> Result<ChangeStatus, CommandError> {
+        let file = File::open(other)
+            .map_err(|err| CommandError::CannotReadFile(other.to_path_buf(), err))?;
+        let other_log: TimeLog = config::read_logfile(other, file, |r| serde_json::from_reader(r))
+            .map_err(|err| CommandError::CannotParseFile(other.to_path_buf(), err))?;
+
+        let diff = other_log.diff(self.timelog);
+        if diff.is_empty() {
+            writeln!(self.outputs.info_mut(), "No differences.")?;
+            return Ok(ChangeStatus::Unchanged);
+        }
+
+        let fmt = &self.settings.time_format;
+        let out = self.outputs.output_mut();
+        for (heading, ints) in [("Added", &diff.added), ("Removed", &diff.removed)] {
+            if !ints.is_empty() {
+                writeln!(out, "{}:", heading)?;
+                for (tag, int) in ints {
+                    writeln!(out, "  {} | {}", tag, int.display_with(fmt))?;
+                }
+            }
+        }
+
+        if !diff.changed.is_empty() {
+            writeln!(out, "Changed:")?;
+            for (tag, old, new) in &diff.changed {
+                writeln!(out, "  {} | {}", tag, old.display_with(fmt))?;
+                writeln!(
+                    out,
+                    "  {} | {}",
+                    " ".repeat(tag.len()),
+                    new.display_with(fmt)
+                )?;
+            }
+        }
+
+        Ok(ChangeStatus::Unchanged)
+    }
+
+    fn first(&mut self, tags: &[String]) -> Result<ChangeStatus, CommandError> {
+        let earliest = |tag: Option<TagId>| {
+            self.timelog
+                .iter()
+                .filter(|int| tag.is_none_or(|tag| int.has_tag(tag)))
+                .min_by_key(|int| int.start())
+                .cloned()
+        };
+
+        let rows: Vec<_> = if tags.is_empty() {
+            earliest(None).into_iter().collect()
+        } else {
+            tags.iter()
+                .filter_map(|name| find_tag(self.timelog, name, self.settings))
+                .filter_map(|tag| earliest(Some(tag)))
+                .collect()
+        };
+
+        if rows.is_empty() {
+            writeln!(self.outputs.info_mut(), "No matching intervals.")?;
+            return Ok(ChangeStatus::NoMatches);
+        }
+
+        self.list_rows(&rows, false)?;
+        Ok(ChangeStatus::Unchanged)
+    }
+
+    fn status(
+        &mut self,
+        tags: &[String],
+        porcelain: bool,
+        include_today: bool,
+        since_midnight: bool,
+    ) -> Result<ChangeStatus, CommandError> {
+        let tags_filter = if tags.is_empty() {
+            filter::filter_true()
+        } else {
+            filter::or_all(
+                tags.iter()
+                    .map(|name| find_tag(self.timelog, name, self.settings))
+                    .filter(|t| t.is_some())
+                    .map(|t| filter::has_tag(t.unwrap())),
+            )
+        };
+        let filter = filter::is_open() & tags_filter.clone();
+
+        if porcelain {
+            let now = Utc::now();
+            let mut any = false;
+            for int in self.timelog.query(&filter) {
+                writeln!(
+                    self.outputs.output_mut(),
+                    "{} {} {}",
+                    self.timelog.tag_name(int.tag()).unwrap(),
+                    int.start().timestamp(),
+                    now.signed_duration_since(int.start()).num_seconds()
+                )?;
+                any = true;
+            }
+
+            return Ok(if any {
+                ChangeStatus::Unchanged
+            } else {
+                ChangeStatus::NoMatches
+            });
+        }
+
+        let open: Vec<_> = self.timelog.query(&filter).collect();
+        if !open.is_empty() {
+            let mut by_tag: BTreeMap<_, Vec<_>> = BTreeMap::new();
+            for (i, int) in open.iter().enumerate() {
+                by_tag.entry(int.tag()).or_default().push(i);
+            }
+            by_tag
+                .values_mut()
+                .for_each(|ints| ints.sort_by_key(|&i| open[i].start()));
+
+            // Tags with several open intervals are labeled with each interval's index, as used by
+            // `close --index`.
+            let labels: Vec<_> = open
+                .iter()
+                .enumerate()
+                .map(|(i, int)| {
+                    let name = self.timelog.tag_name(int.tag()).unwrap();
+                    let ints = &by_tag[&int.tag()];
+                    if ints.len() > 1 {
+                        let index = ints.iter().position(|&j| j == i).unwrap();
+                        format!("{} [{}]", name, index)
+                    } else {
+                        name.to_string()
+                    }
+                })
+                .collect();
+            let max_width = labels.iter().map(String::len).max().unwrap_or(0);
+            let (today_start, _) = today_window(self.settings);
+
+            writeln!(self.outputs.info_mut(), "Currently open intervals:")?;
+            for (label, int) in labels.iter().zip(&open) {
+                writeln!(
+                    self.outputs.output_mut(),
+                    "{:<width$} | {}",
+                    label,
+                    int.interval().display_with(&self.settings.time_format),
+                    width = max_width
+                )?;
+
+                if since_midnight && int.start() < today_start {
+                    let today = int.interval().duration_within(Some(today_start), None);
+                    writeln!(
+                        self.outputs.output_mut(),
+                        "{:<width$} | {} elapsed, {} of it today",
+                        "",
+                        format_duration(int.duration_clamped()),
+                        format_duration(today),
+                        width = max_width
+                    )?;
+                }
+            }
+        } else {
+            writeln!(
+                self.outputs.info_mut(),
+                "No currently open intervals matching these filter criteria."
+            )?;
+        }
+
+        let mut any = !open.is_empty();
+        if include_today {
+            let (start, end) = today_window(self.settings);
+            let filter = tags_filter
+                & filter::is_closed()
+                & filter::ended_after_strict(start)
+                & filter::started_before(end);
+            let closed: Vec<_> = self.timelog.query(&filter).cloned().collect();
+
+            if closed.is_empty() {
+                writeln!(self.outputs.info_mut(), "No intervals closed today.")?;
+            } else {
+                writeln!(self.outputs.info_mut(), "Closed today:")?;
+                self.list_rows(&closed, false)?;
+                any = true;
+            }
         }
+
+        Ok(if any {
+            ChangeStatus::Unchanged
+        } else {
+            ChangeStatus::NoMatches
+        })
     }
 
-    fn aggregate(&mut self, info: &TagsInRange) -> Result<ChangeStatus, CommandError> {
-        let filter = info.filter(self.timelog)?;
+    fn set_default(&mut self, tag: Option<&str>) -> Result<ChangeStatus, CommandError> {
+        let tag = tag.map(|name| self.tag_name(name));
+        if self.timelog.default_tag() == tag.as_deref() {
+            writeln!(self.outputs.info_mut(), "Default tag unchanged.")?;
+            return Ok(ChangeStatus::Unchanged);
+        }
+
+        self.timelog.set_default_tag(tag.as_deref());
+        match tag {
+            Some(tag) => writeln!(self.outputs.info_mut(), "Default tag set to '{}'.", tag)?,
+            None => writeln!(self.outputs.info_mut(), "Default tag cleared.")?,
+        }
+        Ok(ChangeStatus::Changed)
+    }
 
-        writeln!(
-            self.outputs.error_mut(),
-            "Aggregating the following intervals:"
-        )?;
-        self.list_filter(&filter)?;
+    fn add_tags(&mut self, names: &[String]) -> Result<ChangeStatus, CommandError> {
+        let mut status = ChangeStatus::Unchanged;
+        for name in names {
+            if self.timelog.declare_tag(name) {
+                writeln!(self.outputs.info_mut(), "Declared tag '{}'", name)?;
+                status = ChangeStatus::Changed;
+            } else {
+                writeln!(
+                    self.outputs.info_mut(),
+                    "Tag '{}' is already declared",
+                    name
+                )?;
+            }
+        }
 
-        let filter = filter.build_ref();
+        Ok(status)
+    }
 
-        let total = self
+    fn tags(&mut self) -> Result<ChangeStatus, CommandError> {
+        let tagnames: BTreeSet<_> = self
             .timelog
             .iter()
-            .filter(filter)
-            .fold(Duration::seconds(0), |d, int| d + int.duration());
+            .flat_map(|int| int.tags())
+            .map(|tag| String::from(self.timelog.tag_name(tag).unwrap()))
+            .chain(self.timelog.declared_tag_names().map(String::from))
+            .collect();
+
+        for name in tagnames {
+            writeln!(self.outputs.output_mut(), "{}", name)?;
+        }
+
+        Ok(ChangeStatus::Unchanged)
+    }
+
+    /// Print the tags listed by `tags`, with statistics, as a JSON array.
+    fn tags_json(&mut self) -> Result<ChangeStatus, CommandError> {
+        let totals = self.timelog.tag_totals();
+        let mut tags: BTreeMap<&str, serde_json::Value> = BTreeMap::new();
+        for (id, name) in self.timelog.tags() {
+            let ints: Vec<_> = self.timelog.query(&filter::has_tag(id)).collect();
+            if ints.is_empty() && !self.timelog.declared_tag_names().any(|n| n == name) {
+                continue;
+            }
+
+            let last_active = ints
+                .iter()
+                .map(|int| int.end().unwrap_or_else(Utc::now))
+                .max()
+                .map(|time| time.to_rfc3339_opts(SecondsFormat::Secs, true));
+            let total = totals.get(&id).copied().unwrap_or_else(Duration::zero);
+            tags.insert(
+                name,
+                serde_json::json!({
+                    "id": id,
+                    "name": name,
+                    "interval_count": ints.len(),
+                    "total_minutes": total.num_minutes(),
+                    "last_active": last_active,
+                }),
+            );
+        }
 
+        let tags: Vec<_> = tags.into_values().collect();
         writeln!(
             self.outputs.output_mut(),
-            "Total {}:{:02}",
-            total.num_hours(),
-            total.num_minutes() % 60
+            "{}",
+            serde_json::Value::from(tags)
         )?;
+        Ok(ChangeStatus::Unchanged)
+    }
+
+    fn check(&mut self) -> Result<ChangeStatus, CommandError> {
+        let issues = self.timelog.validate();
+        if issues.is_empty() {
+            writeln!(self.outputs.info_mut(), "No problems found.")?;
+            return Ok(ChangeStatus::Unchanged);
+        }
+
+        let ints: Vec<_> = self.timelog.iter().collect();
+        for issue in issues {
+            let tag_name = |tag| self.timelog.tag_name(tag).unwrap_or("<unknown>");
+            let fmt = &self.settings.time_format;
+            match issue {
+                ValidationIssue::UnknownTag { index, tag } => writeln!(
+                    self.outputs.output_mut(),
+                    "Unknown tag ID {}: {}",
+                    tag,
+                    ints[index].interval().display_with(fmt)
+                )?,
+                ValidationIssue::MultipleOpen { tag, count } => writeln!(
+                    self.outputs.output_mut(),
+                    "Tag '{}' has {} open intervals",
+                    tag_name(tag),
+                    count
+                )?,
+                ValidationIssue::Overlap { tag, first, second } => writeln!(
+                    self.outputs.output_mut(),
+                    "Overlapping intervals for tag '{}': {} and {}",
+                    tag_name(tag),
+                    ints[first].interval().display_with(fmt),
+                    ints[second].interval().display_with(fmt)
+                )?,
+            }
+        }
 
         Ok(ChangeStatus::Unchanged)
     }
 
-    fn status(&mut self, tags: &[String]) -> Result<ChangeStatus, CommandError> {
-        let filter = if tags.is_empty() {
-            filter::is_open()
+    fn unused_tags(&mut self) -> Result<ChangeStatus, CommandError> {
+        let names: Vec<_> = self.timelog.unused_tag_names().map(String::from).collect();
+        for name in &names {
+            writeln!(self.outputs.output_mut(), "{}", name)?;
+        }
+
+        if names.is_empty() {
+            Ok(ChangeStatus::NoMatches)
         } else {
-            let tags_filter = filter::or_all(
-                tags.iter()
-                    .map(|name| self.timelog.tag_id(name))
-                    .filter(|t| t.is_some())
-                    .map(|t| filter::has_tag(t.unwrap())),
-            );
+            Ok(ChangeStatus::Unchanged)
+        }
+    }
 
-            filter::is_open() & tags_filter
-        };
+    fn gc_tags(&mut self) -> Result<ChangeStatus, CommandError> {
+        let names: Vec<_> = self.timelog.unused_tag_names().map(String::from).collect();
+        if names.is_empty() {
+            writeln!(self.outputs.info_mut(), "No unused tags.")?;
+            return Ok(ChangeStatus::Unchanged);
+        }
 
-        if self.timelog.iter().any(filter.build()) {
-            writeln!(self.outputs.error_mut(), "Currently open intervals:")?;
-            self.list_filter(&filter)?;
-        } else {
-            writeln!(
-                self.outputs.error_mut(),
-                "No currently open intervals matching these filter criteria."
-            )?;
+        writeln!(self.outputs.info_mut(), "Removing unused tags:")?;
+        for name in &names {
+            writeln!(self.outputs.info_mut(), "{}", name)?;
         }
 
-        Ok(ChangeStatus::Unchanged)
+        self.timelog.gc_tag_names();
+        Ok(ChangeStatus::Changed)
     }
 
-    fn tags(&mut self) -> Result<ChangeStatus, CommandError> {
-        let tagnames: BTreeSet<_> = self
-            .timelog
-            .iter()
-            .map(|int| String::from(self.timelog.tag_name(int.tag()).unwrap()))
-            .collect();
+    /// Run a user-configured hook command, if any, with the given tag name and time.
+    ///
+    /// The hook's standard output is sent to standard error, so that it cannot be mistaken for
+    /// the command's own output. Failure to run the hook, or the hook exiting unsuccessfully, is
+    /// reported as a warning but is not an error.
+    fn run_hook(
+        &mut self,
+        hook: Option<&String>,
+        tag: &str,
+        time: DateTime<Utc>,
+    ) -> Result<(), CommandError> {
+        let hook = match hook {
+            Some(hook) => hook,
+            None => return Ok(()),
+        };
 
-        for name in tagnames {
-            writeln!(self.outputs.output_mut(), "{}", name)?;
+        let time = time.to_rfc3339_opts(SecondsFormat::Secs, true);
+        log::debug!("Running hook '{}' for tag '{}' at {}", hook, tag, time);
+
+        let status = process::Command::new(hook)
+            .arg(tag)
+            .arg(&time)
+            .env("TIMELOG_TAG", tag)
+            .env("TIMELOG_TIME", &time)
+            .stdout(io::stderr())
+            .status();
+
+        match status {
+            Ok(status) if status.success() => {}
+            Ok(status) => writeln!(
+                self.outputs.error_mut(),
+                "Warning: hook '{}' exited unsuccessfully ({})",
+                hook,
+                status
+            )?,
+            Err(err) => writeln!(
+                self.outputs.error_mut(),
+                "Warning: failed to run hook '{}': {}",
+                hook,
+                err
+            )?,
         }
 
-        Ok(ChangeStatus::Unchanged)
+        Ok(())
     }
 
+    /// Ask the user to confirm an action, returning their answer.
+    ///
+    /// With `--yes`, this returns `true` without asking. Otherwise, if standard input is not a
+    /// terminal, this fails with `CommandError::ConfirmationRequired` rather than waiting for an
+    /// answer. An empty answer means `default`, unless overridden by `--confirm-default`, and the
+    /// end of input means no.
     fn user_confirmation(&mut self, default: bool) -> Result<bool, CommandError> {
+        if self.settings.yes {
+            return Ok(true);
+        }
+
+        if !io::stdin().is_terminal() {
+            return Err(CommandError::ConfirmationRequired);
+        }
+
+        let default = self.settings.confirm_default.unwrap_or(default);
         let options = if default { "(Y/n)" } else { "(y/N)" };
 
         let mut line = String::new();
-        let mut result = default;
 
         loop {
             write!(self.outputs.error_mut(), "Okay? {} ", options)?;
             self.outputs.error_mut().flush()?;
-            io::stdin().read_line(&mut line).unwrap();
-
-            let line_chars: Vec<_> = line.chars().collect();
-
-            if line.len() < 2 {
-                break;
-            } else if line_chars.len() == 2 {
-                match line_chars[0] {
-                    'y' | 'Y' => {
-                        result = true;
-                        break;
-                    }
-
-                    'n' | 'N' => {
-                        result = false;
-                        break;
-                    }
-
-                    _ => {
-                        line.clear();
-                        continue;
-                    }
-                }
-            } else {
-                let upper = line.to_uppercase();
-                match upper.as_ref() {
-                    "YES\n" => {
-                        result = true;
-                        break;
-                    }
 
-                    "NO\n" => {
-                        result = false;
-                        break;
-                    }
+            line.clear();
+            if io::stdin().read_line(&mut line)? == 0 {
+                // End of input; decline rather than assume the default.
+                writeln!(self.outputs.error_mut())?;
+                return Ok(false);
+            }
 
-                    _ => {
-                        line.clear();
-                        continue;
-                    }
-                }
+            match line.trim().to_uppercase().as_ref() {
+                "" => return Ok(default),
+                "Y" | "YES" => return Ok(true),
+                "N" | "NO" => return Ok(false),
+                _ => continue,
             }
         }
-
-        Ok(result)
     }
 }
 
@@ -361,10 +1876,16 @@ where
 #[derive(Debug, Clone, StructOpt)]
 pub struct TagsInRange {
     /// Select only intervals that started before this time.
+    ///
+    /// Times may be given as H:MM, H:MMam, a date (YYYY-MM-DD or MMMD,YYYY) optionally followed by
+    /// a comma and a time, an ISO week (YYYY-Www, meaning midnight on its Monday), an offset from
+    /// now (+H:MM or -H:MM), `today`, or a weekday name (meaning midnight on its most recent
+    /// occurrence, which is today if today is that weekday).
     #[structopt(short, long, parse(try_from_str = datetime_from_str))]
     before: Option<DateTime<Utc>>,
 
-    /// Select only intervals that ended after this time (or are currently open).
+    /// Select only intervals that ended after this time (or are currently open). Accepts the same
+    /// time formats as --before.
     #[structopt(short, long, parse(try_from_str = datetime_from_str))]
     after: Option<DateTime<Utc>>,
 
@@ -372,6 +1893,25 @@ pub struct TagsInRange {
     #[structopt(long)]
     today: bool,
 
+    /// Select only intervals that overlap the previous calendar day. Mutually exclusive with
+    /// --today.
+    #[structopt(long, conflicts_with = "today")]
+    yesterday: bool,
+
+    /// Select only intervals that overlap the current week. The first day of the week is set by
+    /// --week-start.
+    #[structopt(long, conflicts_with_all = &["today", "yesterday"])]
+    week: bool,
+
+    /// Select only intervals that overlap the given month (YYYY-MM), or the current month if no
+    /// month is given.
+    #[structopt(long, conflicts_with_all = &["today", "yesterday", "week"])]
+    month: Option<Option<Month>>,
+
+    /// Select only intervals that were in progress at this instant.
+    #[structopt(long, parse(try_from_str = datetime_from_str))]
+    active_at: Option<DateTime<Utc>>,
+
     /// Select only open intervals. Mutually exclusive with --closed.
     #[structopt(short, long)]
     open: bool,
@@ -380,50 +1920,46 @@ pub struct TagsInRange {
     #[structopt(short, long)]
     closed: bool,
 
+    /// Exclude intervals with this tag. May be given multiple times.
+    #[structopt(long = "exclude-tag", number_of_values = 1)]
+    exclude_tags: Vec<String>,
+
     /// Select only intervals with these tags. If none are given, select intervals with any tag.
     tags: Vec<String>,
 }
 
 impl TagsInRange {
     /// Construct a filter matching this `TagsInRange`.
-    pub fn filter(&self, timelog: &TimeLog) -> Result<Filter, CommandError> {
+    pub fn filter(&self, timelog: &TimeLog, settings: &Settings) -> Result<Filter, CommandError> {
         let tags_filter = if self.tags.is_empty() {
             filter::filter_true()
         } else {
-            filter::or_all(self.tags.iter().filter_map(|name| {
-                let tag = timelog.tag_id(name)?;
-                Some(filter::has_tag(tag))
-            }))
+            filter::or_all(
+                self.tag_ids(timelog, settings)
+                    .into_iter()
+                    .map(filter::has_tag),
+            )
         };
 
-        let todaytime = Local::today().and_hms(0, 0, 0);
-        let todaytime = (todaytime - todaytime.offset().fix()).with_timezone(&Utc);
-        let tomorrowtime = todaytime + Duration::days(1);
+        let exclude_filter = filter::and_all(self.exclude_tags.iter().filter_map(|name| {
+            let tag = find_tag(timelog, name, settings)?;
+            Some(!filter::has_tag(tag))
+        }));
 
-        let before_filter = if let Some(beforetime) = self.before {
-            if self.today && tomorrowtime < beforetime {
-                filter::started_before(tomorrowtime)
-            } else {
-                filter::started_before(beforetime)
+        let (start, end) = self.bounds(settings);
+        if let (Some(start), Some(end)) = (start, end) {
+            if start > end {
+                return Err(CommandError::InvertedRange(start, end));
             }
-        } else if self.today {
-            filter::started_before(tomorrowtime)
-        } else {
-            filter::filter_true()
-        };
+        }
 
-        let after_filter = if let Some(aftertime) = self.after {
-            filter::is_open()
-                | if self.today && aftertime < todaytime {
-                    filter::ended_after_strict(todaytime)
-                } else {
-                    filter::ended_after_strict(aftertime)
-                }
-        } else if self.today {
-            filter::is_open() | filter::ended_after_strict(todaytime)
-        } else {
-            filter::filter_true()
-        };
+        let active_filter = self
+            .active_at
+            .map_or_else(filter::filter_true, filter::active_at);
+        let before_filter = end.map_or_else(filter::filter_true, filter::started_before);
+        let after_filter = start.map_or_else(filter::filter_true, |t| {
+            filter::is_open() | filter::ended_after_strict(t)
+        });
 
         let open_closed_filter = {
             match (self.open, self.closed) {
@@ -434,12 +1970,105 @@ impl TagsInRange {
             }
         }?;
 
-        let res = tags_filter & before_filter & after_filter & open_closed_filter;
-        log::debug!("TagsInRange filter: {:?}", res);
+        let res = tags_filter
+            & exclude_filter
+            & active_filter
+            & before_filter
+            & after_filter
+            & open_closed_filter;
+        log::debug!("TagsInRange filter: {}", res);
 
         Ok(res)
     }
 
+    /// The IDs of the tags selected by this `TagsInRange`, skipping tags that do not exist. This is
+    /// empty if no tags were given.
+    fn tag_ids(&self, timelog: &TimeLog, settings: &Settings) -> Vec<TagId> {
+        self.tags
+            .iter()
+            .filter_map(|name| find_tag(timelog, name, settings))
+            .collect()
+    }
+
+    /// Construct a selection of intervals matching this `TagsInRange`.
+    fn select(&self, timelog: &TimeLog, settings: &Settings) -> Result<Selection, CommandError> {
+        let (start, end) = self.bounds(settings);
+        Ok(Selection {
+            filter: self.filter(timelog, settings)?,
+            start,
+            end,
+        })
+    }
+
+    /// The bounds of the time range selected by this `TagsInRange`, as start and end times.
+    ///
+    /// This combines `--after` and `--before` with the window selected by `--today`,
+    /// `--yesterday`, `--week`, or `--month`. A bound is `None` if that side of the range is
+    /// unrestricted.
+    pub fn bounds(&self, settings: &Settings) -> (Option<DateTime<Utc>>, Option<DateTime<Utc>>) {
+        self.bounds_with(settings, &SystemClock)
+    }
+
+    /// The bounds of the time range selected by this `TagsInRange`, with windows such as `--today`
+    /// taken relative to the current time according to the given clock.
+    ///
+    /// See `bounds` for details.
+    pub fn bounds_with<C>(
+        &self,
+        settings: &Settings,
+        clock: &C,
+    ) -> (Option<DateTime<Utc>>, Option<DateTime<Utc>>)
+    where
+        C: Clock,
+    {
+        let (mut start, mut end) = (self.after, self.before);
+        if let Some((window_start, window_end)) = self.window(settings, clock.now()) {
+            start = Some(start.map_or(window_start, |t| t.max(window_start)));
+            end = Some(end.map_or(window_end, |t| t.min(window_end)));
+        }
+
+        (start, end)
+    }
+
+    /// The time window selected by the `--today`, `--yesterday`, `--week`, or `--month` flags, if
+    /// any.
+    ///
+    /// The window is computed in local time, with days starting at the configured day start
+    /// time, and returned as its start and end times in UTC.
+    fn window(
+        &self,
+        settings: &Settings,
+        now: DateTime<Utc>,
+    ) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        let today = day_of(zone::current().from_utc(&now), settings);
+        let day_start = |date| local_day_start(date, settings);
+
+        if self.today {
+            Some((day_start(today), day_start(today + Duration::days(1))))
+        } else if self.yesterday {
+            Some((day_start(today - Duration::days(1)), day_start(today)))
+        } else if self.week {
+            let days_into_week = (today.weekday().num_days_from_monday() + 7
+                - settings.week_start.num_days_from_monday())
+                % 7;
+            let first = today - Duration::days(days_into_week as i64);
+            Some((day_start(first), day_start(first + Duration::weeks(1))))
+        } else if let Some(month) = self.month {
+            let first = month.map_or_else(|| today.with_day(1).unwrap(), |m| m.0);
+            let next = if first.month() == 12 {
+                NaiveDate::from_ymd(first.year() + 1, 1, 1)
+            } else {
+                NaiveDate::from_ymd(first.year(), first.month() + 1, 1)
+            };
+
+            let start = day_start(first);
+            let end = day_start(next);
+            Some((start, end))
+        } else {
+            None
+        }
+    }
+
     fn log_debug(&self) {
         if let Some(before) = self.before {
             log::debug!("Before time: {}", before);
@@ -451,10 +2080,48 @@ impl TagsInRange {
     }
 }
 
+/// A filter on intervals, along with bounds on the time range of the intervals it can match.
+///
+/// The bounds allow the timelog's start time index to skip intervals outside the range.
+#[derive(Debug, Clone)]
+struct Selection {
+    filter: Filter,
+    start: Option<DateTime<Utc>>,
+    end: Option<DateTime<Utc>>,
+}
+
+impl Selection {
+    /// An iterator over the intervals of the timelog matching this selection.
+    fn iter<'t>(&'t self, timelog: &'t TimeLog) -> impl Iterator<Item = &'t TaggedInterval> + 't {
+        timelog
+            .iter_overlapping(self.start, self.end)
+            .filter(self.filter.build_ref())
+    }
+}
+
+impl From<Filter> for Selection {
+    fn from(filter: Filter) -> Selection {
+        Selection {
+            filter,
+            start: None,
+            end: None,
+        }
+    }
+}
+
+/// The output and error streams a command writes to.
+///
+/// Command results are written to the output stream, and informational messages, prompts, and
+/// warnings to the error stream. If no separate error stream is given, both go to the output
+/// stream. Any `Write` implementation can be used, such as `Vec<u8>` to capture output in memory.
+///
+/// If `quiet` is set, informational messages are discarded; prompts and warnings are still
+/// written.
 #[derive(Debug, Clone)]
 pub struct Outputs<W> {
     pub output: W,
     pub error: Option<W>,
+    pub quiet: bool,
 }
 
 impl<W> Outputs<W>
@@ -462,7 +2129,11 @@ where
     W: Write,
 {
     pub fn new(output: W, error: Option<W>) -> Outputs<W> {
-        Outputs { output, error }
+        Outputs {
+            output,
+            error,
+            quiet: false,
+        }
     }
 
     pub fn output(&self) -> &W {
@@ -480,6 +2151,39 @@ where
     pub fn error_mut(&mut self) -> &mut W {
         self.error.as_mut().unwrap_or(&mut self.output)
     }
+
+    /// The stream for informational messages: the error stream, or nowhere if `quiet` is set.
+    pub fn info_mut(&mut self) -> Info<'_, W> {
+        if self.quiet {
+            Info(None)
+        } else {
+            Info(Some(self.error_mut()))
+        }
+    }
+}
+
+/// A writer for informational messages, which discards everything written to it when output is
+/// quiet.
+#[derive(Debug)]
+pub struct Info<'a, W>(Option<&'a mut W>);
+
+impl<W> Write for Info<'_, W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.0 {
+            Some(w) => w.write(buf),
+            None => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.0 {
+            Some(w) => w.flush(),
+            None => Ok(()),
+        }
+    }
 }
 
 pub type StdOutputs = Outputs<Box<dyn Write>>;
@@ -489,6 +2193,7 @@ impl Default for StdOutputs {
         Outputs {
             output: Box::new(io::stdout()),
             error: Some(Box::new(io::stderr())),
+            quiet: false,
         }
     }
 }
@@ -497,7 +2202,16 @@ impl Default for StdOutputs {
 pub enum CommandError {
     TimeLogError(TimeLogError),
     TimeParseError,
+    /// A time was not given as a full date and time, where one is required.
+    InexactTime(String),
     InconsistentFilter,
+    /// The selected time range ends before it starts, as with `--after 18:00 --before 09:00`.
+    InvertedRange(DateTime<Utc>, DateTime<Utc>),
+    InvalidArguments(String),
+    ConfirmationRequired,
+    CannotCreateOutputFile(PathBuf, io::Error),
+    CannotReadFile(PathBuf, io::Error),
+    CannotParseFile(PathBuf, ConfigError),
     IoError(io::Error),
 }
 
@@ -506,7 +2220,32 @@ impl Display for CommandError {
         match self {
             CommandError::TimeLogError(err) => Display::fmt(err, f),
             CommandError::TimeParseError => write!(f, "error parsing time specification"),
+            CommandError::InexactTime(s) => write!(
+                f,
+                "'{}' is not an unambiguous date and time; expected YYYY-MM-DD,HH:MM",
+                s
+            ),
             CommandError::InconsistentFilter => write!(f, "inconsistent filters specified"),
+            CommandError::InvertedRange(start, end) => write!(
+                f,
+                "inverted time range: starts at {} but ends earlier, at {}",
+                zone::current().from_utc(start).format("%Y-%m-%d %H:%M"),
+                zone::current().from_utc(end).format("%Y-%m-%d %H:%M")
+            ),
+            CommandError::InvalidArguments(message) => write!(f, "{}", message),
+            CommandError::ConfirmationRequired => write!(
+                f,
+                "confirmation required, but standard input is not a terminal; use --yes to proceed"
+            ),
+            CommandError::CannotCreateOutputFile(path, err) => {
+                write!(f, "cannot create output file '{}': {}", path.display(), err)
+            }
+            CommandError::CannotReadFile(path, err) => {
+                write!(f, "cannot read file '{}': {}", path.display(), err)
+            }
+            CommandError::CannotParseFile(path, err) => {
+                write!(f, "cannot parse file '{}': {}", path.display(), err)
+            }
             CommandError::IoError(err) => write!(f, "{}", err),
         }
     }
@@ -526,6 +2265,204 @@ impl From<io::Error> for CommandError {
     }
 }
 
+/// Write an export in the given format, using the given delimiter for CSV exports.
+fn write_export<W>(
+    timelog: &TimeLog,
+    filter: &Filter,
+    format: ExportFormat,
+    csv_delimiter: char,
+    out: &mut W,
+) -> io::Result<()>
+where
+    W: Write + ?Sized,
+{
+    match format {
+        ExportFormat::Csv => export::write_csv(timelog, filter, csv_delimiter, out),
+        _ => export::write_export(timelog, filter, format, out),
+    }
+}
+
+/// The name of the git branch checked out in the given directory, if it is in a git repository
+/// and not in detached HEAD state.
+///
+/// This is the branch used as the default tag with the git branch tag setting.
+pub fn git_branch(dir: &Path) -> Option<String> {
+    let output = process::Command::new("git")
+        .args(["rev-parse", "--abbrev-ref", "HEAD"])
+        .current_dir(dir)
+        .stderr(process::Stdio::null())
+        .output()
+        .map_err(|err| log::debug!("Cannot run git: {}", err))
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    let branch = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    log::debug!("Current git branch: {}", branch);
+    if branch.is_empty() || branch == "HEAD" {
+        None
+    } else {
+        Some(branch)
+    }
+}
+
+/// Look up the ID of the tag with the given name, ignoring case if the settings say to.
+fn find_tag(timelog: &TimeLog, name: &str, settings: &Settings) -> Option<TagId> {
+    if settings.tag_case_insensitive {
+        timelog.tag_id_ignore_case(name)
+    } else {
+        timelog.tag_id(name)
+    }
+}
+
+/// The UTC time of midnight at the start of the given local date.
+///
+/// See `Zone::resolve_local` for how midnights skipped or repeated by daylight saving time
+/// transitions are handled.
+fn local_midnight(date: NaiveDate) -> DateTime<Utc> {
+    zone::current().resolve_local(&date.and_hms(0, 0, 0))
+}
+
+/// The UTC time at which the given local date starts, according to the day start setting.
+fn local_day_start(date: NaiveDate, settings: &Settings) -> DateTime<Utc> {
+    zone::current().resolve_local(&date.and_time(settings.day_start))
+}
+
+/// The start and end times of the current day, according to the day start setting.
+///
+/// The end is the start of the next day, so the window is 23 or 25 hours long on days when
+/// clocks change.
+fn today_window(settings: &Settings) -> (DateTime<Utc>, DateTime<Utc>) {
+    let today = day_of(zone::current().now(), settings);
+    (
+        local_day_start(today, settings),
+        local_day_start(today + Duration::days(1), settings),
+    )
+}
+
+/// The date of the day containing the given local time, according to the day start setting.
+///
+/// Times before the day start time belong to the previous calendar day.
+fn day_of<Tz: TimeZone>(time: DateTime<Tz>, settings: &Settings) -> NaiveDate {
+    let since_day_start = settings.day_start - NaiveTime::from_hms(0, 0, 0);
+    (time.naive_local() - since_day_start).date()
+}
+
+/// Escape the characters of a string that Markdown would treat as table delimiters or inline
+/// formatting.
+fn markdown_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(
+            c,
+            '\\' | '|' | '*' | '_' | '`' | '~' | '[' | ']' | '<' | '>' | '#'
+        ) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Round a duration up to the next multiple of the given number of minutes.
+fn round_up_to_minutes(duration: Duration, minutes: NonZeroU32) -> Duration {
+    let step = i64::from(minutes.get()) * 60;
+    let secs = duration.num_seconds();
+    Duration::seconds((secs + step - 1) / step * step)
+}
+
+/// A period by which `aggregate` groups durations.
+#[derive(Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Hash)]
+pub enum GroupBy {
+    /// A calendar day in the local time zone.
+    Day,
+    /// An ISO week, starting on Monday.
+    Week,
+}
+
+impl GroupBy {
+    /// The label of the group containing the given local date.
+    fn label(self, date: NaiveDate) -> String {
+        match self {
+            GroupBy::Day => date.format("%Y-%m-%d").to_string(),
+            GroupBy::Week => {
+                let week = date.iso_week();
+                format!("{}-W{:02}", week.year(), week.week())
+            }
+        }
+    }
+}
+
+impl FromStr for GroupBy {
+    type Err = CommandError;
+
+    fn from_str(s: &str) -> Result<GroupBy, CommandError> {
+        match s {
+            "day" => Ok(GroupBy::Day),
+            "week" => Ok(GroupBy::Week),
+            _ => Err(CommandError::InvalidArguments(format!(
+                "unknown grouping '{}'; expected day or week",
+                s
+            ))),
+        }
+    }
+}
+
+/// The output format of the `list` command.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ListFormat {
+    /// A GitHub-flavored Markdown table with tag, start, end, and duration columns.
+    Markdown,
+    /// Newline-delimited JSON, with one compact object per interval. Objects have the tag name,
+    /// the start and end times in RFC 3339 format (the end is null for open intervals), and the
+    /// duration in seconds.
+    JsonLines,
+    /// One line per interval, rendered with a template.
+    Template(Template),
+}
+
+impl FromStr for ListFormat {
+    type Err = TemplateError;
+
+    /// Parse `markdown` (or `md`), `jsonl` (or `json-lines`), or otherwise a template.
+    fn from_str(s: &str) -> Result<ListFormat, TemplateError> {
+        match s {
+            "markdown" | "md" => Ok(ListFormat::Markdown),
+            "jsonl" | "json-lines" => Ok(ListFormat::JsonLines),
+            _ => s.parse().map(ListFormat::Template),
+        }
+    }
+}
+
+/// A calendar month, represented by its first day.
+#[derive(Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Hash)]
+struct Month(NaiveDate);
+
+impl FromStr for Month {
+    type Err = CommandError;
+
+    /// Parse a `YYYY-MM` month specification.
+    fn from_str(s: &str) -> Result<Month, CommandError> {
+        NaiveDate::parse_from_str(&format!("{}-1", s.trim()), "%Y-%m-%d")
+            .map(Month)
+            .map_err(|_| CommandError::TimeParseError)
+    }
+}
+
+/// Parse a full local date and time, `YYYY-MM-DD,HH:MM`.
+///
+/// Unlike `datetime_from_str`, no other forms are accepted, and local times that are ambiguous or
+/// nonexistent due to daylight saving time transitions are rejected.
+fn exact_datetime_from_str(s: &str) -> Result<DateTime<Utc>, CommandError> {
+    let trimmed: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+    NaiveDateTime::parse_from_str(&trimmed, "%Y-%m-%d,%H:%M")
+        .ok()
+        .and_then(|datetime| zone::current().from_local(&datetime).single())
+        .map(|datetime| datetime.with_timezone(&Utc))
+        .ok_or_else(|| CommandError::InexactTime(s.into()))
+}
+
 fn datetime_from_str(s: &str) -> Result<DateTime<Utc>, CommandError> {
     const TIME_FMTS: &[&str] = &[
         "%-H:%M",   // H:MM
@@ -538,34 +2475,37 @@ fn datetime_from_str(s: &str) -> Result<DateTime<Utc>, CommandError> {
         "%b%-d,%Y",   // MMMD,YYYY
     ];
 
-    let now = Local::now();
+    let now = zone::current().now();
     let s: String = s.chars().filter(|c| !c.is_whitespace()).collect();
 
     for fmt in TIME_FMTS {
         if let Ok(time) = NaiveTime::parse_from_str(&s, fmt) {
             let datetime = NaiveDateTime::new(now.naive_local().date(), time);
-            let res = Utc.from_utc_datetime(&(datetime - now.offset().fix()));
-            return Ok(res);
+            return Ok(zone::current().resolve_local(&datetime));
         }
     }
 
     for fmt in DATE_FMTS {
         if let Ok(date) = NaiveDate::parse_from_str(&s, fmt) {
-            let datetime =
-                NaiveDateTime::new(date, NaiveTime::from_hms(0, 0, 0)) - now.offset().fix();
-            return Ok(Utc.from_local_datetime(&datetime).unwrap());
+            return Ok(local_midnight(date));
         }
     }
 
+    if let Some((year, week)) = s.split_once("-W").or_else(|| s.split_once("-w")) {
+        let year = year.parse().map_err(|_| CommandError::TimeParseError)?;
+        let week = week.parse().map_err(|_| CommandError::TimeParseError)?;
+        let monday = NaiveDate::from_isoywd_opt(year, week, Weekday::Mon)
+            .ok_or(CommandError::TimeParseError)?;
+        return Ok(local_midnight(monday));
+    }
+
     for time_fmt in TIME_FMTS {
         for date_fmt in DATE_FMTS {
             let mut fmt = String::from(*date_fmt);
             fmt.push(',');
             fmt.push_str(time_fmt);
             if let Ok(datetime) = NaiveDateTime::parse_from_str(&s, &fmt) {
-                return Ok(Utc
-                    .from_local_datetime(&(datetime - now.offset().fix()))
-                    .unwrap());
+                return Ok(zone::current().resolve_local(&datetime));
             }
         }
     }
@@ -573,17 +2513,28 @@ fn datetime_from_str(s: &str) -> Result<DateTime<Utc>, CommandError> {
     if let Some(c @ ('+' | '-')) = s.chars().next() {
         let s = &s[1..];
         let dur = duration_from_str(s)?;
-        if c == '+' {
+        return if c == '+' {
             Ok(Utc::now() + dur)
         } else {
             Ok(Utc::now() - dur)
-        }
-    } else {
-        Err(CommandError::TimeParseError)
+        };
+    }
+
+    let today = now.naive_local().date();
+    if s.eq_ignore_ascii_case("today") {
+        return Ok(local_midnight(today));
+    }
+
+    if let Ok(weekday) = s.parse::<Weekday>() {
+        let days_since =
+            (today.weekday().num_days_from_monday() + 7 - weekday.num_days_from_monday()) % 7;
+        return Ok(local_midnight(today - Duration::days(days_since as i64)));
     }
+
+    Err(CommandError::TimeParseError)
 }
 
-fn duration_from_str(s: &str) -> Result<Duration, CommandError> {
+pub(crate) fn duration_from_str(s: &str) -> Result<Duration, CommandError> {
     let tokens: Vec<_> = s.split(':').collect();
 
     let (hours, minutes, seconds) = if tokens.len() == 1 {
@@ -628,3 +2579,42 @@ fn duration_from_str(s: &str) -> Result<Duration, CommandError> {
         seconds as i64 + 60 * minutes as i64 + 60 * 60 * hours as i64,
     ))
 }
+
+/// A non-negative duration given as `H`, `H:MM`, or `H:MM:SS`, for use as a command line
+/// argument.
+///
+/// Displays as `H:MM`, or as `H:MM:SS` if the duration is not a whole number of minutes, which
+/// parses back to the same duration.
+#[derive(Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Hash)]
+pub struct DurationSpec(Duration);
+
+impl DurationSpec {
+    /// The duration given by this specification.
+    pub fn duration(self) -> Duration {
+        self.0
+    }
+}
+
+impl From<DurationSpec> for Duration {
+    fn from(spec: DurationSpec) -> Duration {
+        spec.0
+    }
+}
+
+impl FromStr for DurationSpec {
+    type Err = CommandError;
+
+    fn from_str(s: &str) -> Result<DurationSpec, CommandError> {
+        duration_from_str(s).map(DurationSpec)
+    }
+}
+
+impl Display for DurationSpec {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.0.num_seconds() % 60 == 0 {
+            write!(f, "{}", format_duration(self.0))
+        } else {
+            write!(f, "{}", format_duration_exact(self.0))
+        }
+    }
+}
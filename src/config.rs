@@ -1,15 +1,24 @@
 //! Configuration definitions and command-line arguments.
 
-use crate::commands::Command;
+use crate::commands::{self, Command};
+use crate::filter::Filter;
+use crate::interval::{self, format_duration_exact, TimeFormat};
 use crate::timelog::TimeLog;
+use crate::zone;
 
+use chrono::{NaiveTime, Weekday};
+use fs2::FileExt;
+use json_comments::{CommentSettings, StripComments};
 use structopt::StructOpt;
 
 use std::env;
 use std::ffi::OsString;
-use std::fs::File;
-use std::io;
-use std::path::PathBuf;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use std::error::Error;
 use std::fmt::{self, Display, Formatter};
@@ -20,7 +29,7 @@ use ConfigError::*;
 mod internal {
     use std::path::PathBuf;
 
-    pub const LOGFILE_VAR: &'static str = "TIMELOG_DBG_LOGFILE";
+    pub const LOGFILE_VAR: &str = "TIMELOG_DBG_LOGFILE";
 
     pub fn default_logfile() -> Option<PathBuf> {
         Some("./timelog".into())
@@ -29,7 +38,9 @@ mod internal {
 
 #[cfg(not(debug_assertions))]
 mod internal {
-    pub const LOGFILE_VAR: &'static str = "TIMELOG_LOGFILE";
+    use std::path::PathBuf;
+
+    pub const LOGFILE_VAR: &str = "TIMELOG_LOGFILE";
 
     pub fn default_logfile() -> Option<PathBuf> {
         let home_dir = dirs::home_dir()?;
@@ -47,7 +58,14 @@ use internal::*;
 ///    timelog will use its value.
 /// 3. Finally, timelog will attempt to use `${HOME}/.timelog`.
 ///
-/// If none of these locations can be found, timelog will report an error.
+/// If none of these locations can be found, timelog will report an error. A leading `~` and any
+/// `$VAR` or `${VAR}` references in the path are expanded.
+///
+/// A log file whose name ends in `.jsonc` may contain `//` and `/* */` comments. They are ignored
+/// when reading, and are not preserved when timelog writes the file.
+///
+/// Times are displayed and interpreted in the local time zone, unless the `TIMELOG_TZ` environment
+/// variable names another time zone (such as `America/New_York` or `UTC`).
 #[derive(Debug, Clone, StructOpt)]
 #[structopt(verbatim_doc_comment)]
 pub struct Options {
@@ -58,25 +76,334 @@ pub struct Options {
     #[structopt(long, short, parse(from_occurrences))]
     pub verbose: usize,
 
+    /// Suppress informational messages. Command output, prompts, warnings, and errors are still
+    /// printed.
+    #[structopt(long, short)]
+    pub quiet: bool,
+
+    #[structopt(flatten)]
+    pub settings: Settings,
+
     #[structopt(subcommand)]
     pub command: Command,
 }
 
+/// Settings that affect how commands are executed and how their results are displayed.
+#[derive(Debug, Clone, StructOpt)]
+pub struct Settings {
+    /// The strftime-style format used to display dates and times.
+    #[structopt(long, env = "TIMELOG_TIME_FORMAT", default_value)]
+    pub time_format: TimeFormat,
+
+    /// The first day of the week, used when selecting intervals by week.
+    #[structopt(
+        long,
+        env = "TIMELOG_WEEK_START",
+        default_value = "Mon",
+        parse(try_from_str = weekday_from_str)
+    )]
+    pub week_start: Weekday,
+
+    /// The time of day, as H:MM, at which each day starts.
+    ///
+    /// Selecting intervals by day, week, or month, and counting active days, treat each day as
+    /// running from this time until the same time the next calendar day. Setting this past
+    /// midnight keeps late-night work with the day it started on.
+    #[structopt(
+        long,
+        env = "TIMELOG_DAY_START",
+        default_value = "0:00",
+        parse(try_from_str = time_of_day_from_str)
+    )]
+    pub day_start: NaiveTime,
+
+    /// The shortest interval that `close` will record, as H, H:MM, or H:MM:SS.
+    ///
+    /// Closing an interval that would be shorter than this, after rounding, leaves it open and
+    /// offers to discard it instead.
+    #[structopt(
+        long,
+        env = "TIMELOG_MIN_DURATION",
+        parse(try_from_str = commands::duration_from_str)
+    )]
+    pub min_duration: Option<chrono::Duration>,
+
+    /// How far in the future, as H, H:MM, or H:MM:SS, an interval may start before `open` warns
+    /// that the system clock may have moved backward.
+    #[structopt(
+        long,
+        env = "TIMELOG_SKEW_THRESHOLD",
+        default_value = "0:15",
+        parse(try_from_str = commands::duration_from_str)
+    )]
+    pub skew_threshold: chrono::Duration,
+
+    /// Answer yes to confirmation prompts without asking.
+    #[structopt(long, short = "y")]
+    pub yes: bool,
+
+    /// The answer, 'yes' or 'no', assumed when a confirmation prompt is answered with an empty
+    /// line. By default this depends on the prompt.
+    #[structopt(
+        long,
+        env = "TIMELOG_CONFIRM_DEFAULT",
+        parse(try_from_str = answer_from_str)
+    )]
+    pub confirm_default: Option<bool>,
+
+    /// Whether to match tag names given on the command line to existing tags regardless of case,
+    /// as 'yes' or 'no'.
+    ///
+    /// With this set, `open Work` reuses an existing `work` tag rather than creating a new one.
+    /// Tag names are still stored as they were first written.
+    #[structopt(
+        long,
+        env = "TIMELOG_TAG_CASE_INSENSITIVE",
+        default_value = "no",
+        value_name = "yes|no",
+        parse(try_from_str = switch_from_str)
+    )]
+    pub tag_case_insensitive: bool,
+
+    /// Whether to use the current git branch as the tag for `open`, `close`, and `continue` when
+    /// no tag is given, as 'yes' or 'no'.
+    ///
+    /// The branch is found with `git rev-parse --abbrev-ref HEAD` in the working directory. Outside
+    /// a git repository, or with no branch checked out, the default tag is used as usual.
+    #[structopt(
+        long,
+        env = "TIMELOG_GIT_BRANCH_TAG",
+        default_value = "no",
+        value_name = "yes|no",
+        parse(try_from_str = switch_from_str)
+    )]
+    pub git_branch_tag: bool,
+
+    /// A command to run after an interval is opened.
+    ///
+    /// The command is run with the tag name and the interval's start time (in RFC 3339 format)
+    /// as arguments, which are also available as the TIMELOG_TAG and TIMELOG_TIME environment
+    /// variables. Its standard output is redirected to standard error.
+    #[structopt(long, env = "TIMELOG_ON_OPEN")]
+    pub on_open: Option<String>,
+
+    /// A command to run after an interval is closed.
+    ///
+    /// The command is run with the tag name and the interval's end time (in RFC 3339 format) as
+    /// arguments, which are also available as the TIMELOG_TAG and TIMELOG_TIME environment
+    /// variables. As with --on-open, its standard output is redirected to standard error.
+    #[structopt(long, env = "TIMELOG_ON_CLOSE")]
+    pub on_close: Option<String>,
+
+    /// Time budgets for tags, as TAG=DURATION. May be given multiple times, or as a
+    /// comma-separated list in the environment variable.
+    ///
+    /// Durations are given as H, H:MM, or H:MM:SS. Budgets are reported by the `budget` command.
+    #[structopt(
+        long = "budget",
+        env = "TIMELOG_BUDGETS",
+        number_of_values = 1,
+        use_delimiter = true
+    )]
+    pub budgets: Vec<Budget>,
+
+    /// Time estimates for tags, as TAG=DURATION. May be given multiple times, or as a
+    /// comma-separated list in the environment variable.
+    ///
+    /// Durations are given as H, H:MM, or H:MM:SS. Estimates are compared against logged time by
+    /// the `compare` command.
+    #[structopt(
+        long = "estimate",
+        env = "TIMELOG_ESTIMATES",
+        number_of_values = 1,
+        use_delimiter = true
+    )]
+    pub estimates: Vec<Estimate>,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            time_format: TimeFormat::default(),
+            week_start: Weekday::Mon,
+            day_start: NaiveTime::from_hms(0, 0, 0),
+            min_duration: None,
+            skew_threshold: chrono::Duration::minutes(15),
+            yes: false,
+            confirm_default: None,
+            tag_case_insensitive: false,
+            git_branch_tag: false,
+            on_open: None,
+            on_close: None,
+            budgets: Vec::new(),
+            estimates: Vec::new(),
+        }
+    }
+}
+
+/// A time budget allocated to a tag.
+#[derive(Debug, Clone, PartialOrd, Ord, PartialEq, Eq, Hash)]
+pub struct Budget {
+    /// The name of the tag.
+    pub tag: String,
+    /// The time allocated to the tag.
+    pub allocated: chrono::Duration,
+}
+
+impl FromStr for Budget {
+    type Err = ConfigError;
+
+    /// Parse a `TAG=DURATION` budget specification.
+    fn from_str(s: &str) -> Result<Budget, ConfigError> {
+        let (tag, allocated) = tag_duration_from_str(s).ok_or_else(|| InvalidBudget(s.into()))?;
+        Ok(Budget { tag, allocated })
+    }
+}
+
+/// A time estimate for a tag.
+#[derive(Debug, Clone, PartialOrd, Ord, PartialEq, Eq, Hash)]
+pub struct Estimate {
+    /// The name of the tag.
+    pub tag: String,
+    /// The time the tag is estimated to take.
+    pub estimated: chrono::Duration,
+}
+
+impl FromStr for Estimate {
+    type Err = ConfigError;
+
+    /// Parse a `TAG=DURATION` estimate specification.
+    fn from_str(s: &str) -> Result<Estimate, ConfigError> {
+        let (tag, estimated) = tag_duration_from_str(s).ok_or_else(|| InvalidEstimate(s.into()))?;
+        Ok(Estimate { tag, estimated })
+    }
+}
+
+fn tag_duration_from_str(s: &str) -> Option<(String, chrono::Duration)> {
+    let (tag, duration) = s.rsplit_once('=')?;
+    let duration = commands::duration_from_str(duration.trim()).ok()?;
+    Some((tag.trim().into(), duration))
+}
+
+fn weekday_from_str(s: &str) -> Result<Weekday, ConfigError> {
+    s.parse().map_err(|_| InvalidWeekday(s.into()))
+}
+
+fn answer_from_str(s: &str) -> Result<bool, ConfigError> {
+    match s.trim().to_lowercase().as_ref() {
+        "y" | "yes" => Ok(true),
+        "n" | "no" => Ok(false),
+        _ => Err(InvalidAnswer(s.into())),
+    }
+}
+
+/// Parse an on/off setting, such as one given in an environment variable.
+fn switch_from_str(s: &str) -> Result<bool, ConfigError> {
+    match s.trim().to_lowercase().as_ref() {
+        "y" | "yes" | "true" | "on" | "1" => Ok(true),
+        "" | "n" | "no" | "false" | "off" | "0" => Ok(false),
+        _ => Err(InvalidAnswer(s.into())),
+    }
+}
+
+fn time_of_day_from_str(s: &str) -> Result<NaiveTime, ConfigError> {
+    NaiveTime::parse_from_str(s.trim(), "%-H:%M").map_err(|_| InvalidTimeOfDay(s.into()))
+}
+
 impl Options {
     /// Get the path to the logfile according to this set of options.
+    ///
+    /// A leading `~` and any `$VAR` or `${VAR}` references in the path are expanded.
     pub fn logfile_path(&self) -> Result<PathBuf, ConfigError> {
-        self.logfile
+        let path = self
+            .logfile
             .clone()
             .or_else(|| env::var_os(LOGFILE_VAR).map(<PathBuf as From<OsString>>::from))
             .or_else(default_logfile)
-            .ok_or(CannotFindLogFile)
+            .ok_or(CannotFindLogFile)?;
+
+        expand_path(path)
+    }
+
+    /// Acquire an exclusive lock on the logfile, for commands that may change it.
+    ///
+    /// The lock is held on a `.lock` file alongside the logfile, and is released when the returned
+    /// guard is dropped. If another process holds the lock, this waits briefly for it to be
+    /// released before giving up with `ConfigError::LogFileLocked`.
+    ///
+    /// The logfile's directory is not created here, but by `write_timelog`. If it does not exist
+    /// yet, there is no log to protect, and the returned guard holds nothing.
+    pub fn lock_logfile(&self) -> Result<LogFileLock, ConfigError> {
+        let logfile = self.logfile_path()?;
+        if let Some(dir) = logfile.parent() {
+            if !dir.as_os_str().is_empty() && !dir.is_dir() {
+                return Ok(LogFileLock { file: None });
+            }
+        }
+        let mut path = logfile.into_os_string();
+        path.push(".lock");
+
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        wait_for_lock(file, FileExt::try_lock_exclusive)
+    }
+
+    /// Acquire a shared lock on the logfile, for commands that only read it.
+    ///
+    /// Any number of readers may hold the lock at once, but not while a writer holds the exclusive
+    /// lock taken by `lock_logfile`. Nothing is created on disk: if no `.lock` file exists yet, no
+    /// writer can hold the lock, and the returned guard holds nothing.
+    pub fn lock_logfile_shared(&self) -> Result<LogFileLock, ConfigError> {
+        let mut path = self.logfile_path()?.into_os_string();
+        path.push(".lock");
+
+        match File::open(path) {
+            Ok(file) => wait_for_lock(file, FileExt::try_lock_shared),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(LogFileLock { file: None }),
+            Err(err) => Err(err.into()),
+        }
     }
 
     /// Load the current timelog from the logfile.
     pub fn current_timelog(&self) -> Result<TimeLog, ConfigError> {
         let path = self.logfile_path()?;
-        match File::open(path) {
-            Ok(file) => Ok(serde_json::from_reader(file)?),
+        match File::open(&path) {
+            Ok(file) => {
+                log::info!("Loading {}", path.display());
+                let timelog: TimeLog = read_logfile(&path, file, |r| serde_json::from_reader(r))?;
+                log::info!("Loaded {} intervals", timelog.iter().count());
+                Ok(timelog)
+            }
+            Err(err) => match err.kind() {
+                io::ErrorKind::NotFound => Ok(TimeLog::new()),
+                _ => Err(err.into()),
+            },
+        }
+    }
+
+    /// Read only the intervals of the timelog that pass a filter.
+    ///
+    /// This is equivalent to reading the whole timelog with `current_timelog` and then removing
+    /// the intervals that fail the filter, but never holds the whole timelog in memory. The filter
+    /// is constructed by `make_filter` from a timelog containing the tags of the logfile; see
+    /// `TimeLog::from_reader_filtered`.
+    pub fn current_timelog_filtered<F>(&self, make_filter: F) -> Result<TimeLog, ConfigError>
+    where
+        F: FnOnce(&TimeLog) -> Filter,
+    {
+        let path = self.logfile_path()?;
+        match File::open(&path) {
+            Ok(file) => {
+                log::info!("Loading {}", path.display());
+                let timelog = read_logfile(&path, file, |r| {
+                    TimeLog::from_reader_filtered(r, make_filter)
+                })?;
+                log::info!("Loaded {} matching intervals", timelog.iter().count());
+                Ok(timelog)
+            }
             Err(err) => match err.kind() {
                 io::ErrorKind::NotFound => Ok(TimeLog::new()),
                 _ => Err(err.into()),
@@ -85,10 +412,260 @@ impl Options {
     }
 
     /// Write the given timelog to the logfile.
+    ///
+    /// The logfile's directory is created if it does not exist.
     pub fn write_timelog(&self, timelog: &TimeLog) -> Result<(), ConfigError> {
         let path = self.logfile_path()?;
-        let file = File::create(path)?;
-        Ok(serde_json::to_writer(file, timelog)?)
+        create_parent_dir(&path)?;
+        let file = File::create(&path)?;
+        log::info!(
+            "Writing {} intervals to {}",
+            timelog.iter().count(),
+            path.display()
+        );
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer(&mut writer, timelog)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        log::info!("Wrote {} bytes", fs::metadata(&path)?.len());
+        Ok(())
+    }
+
+    /// Copy the logfile to a backup file, and return the path of the backup.
+    ///
+    /// If `path` is not given, the backup is written alongside the logfile, named after it with
+    /// the current local time and a `.bak` extension appended. If `path` is an existing directory,
+    /// the backup is written into it under that name. A backup under that name never replaces an
+    /// existing file; if one exists, a numeric suffix is added to the time. A backup written to a
+    /// file named by `path` replaces that file.
+    pub fn backup(&self, path: Option<&Path>) -> Result<PathBuf, ConfigError> {
+        let logfile = self.logfile_path()?;
+        let mut source = File::open(&logfile)?;
+        let (backup, mut dest) = match path {
+            Some(path) if !path.is_dir() => {
+                let dest = File::create(path).map_err(|err| CannotWriteBackup(path.into(), err))?;
+                (path.to_path_buf(), dest)
+            }
+            _ => {
+                let dir = path.unwrap_or_else(|| logfile.parent().unwrap_or(Path::new("")));
+                let stamp = zone::current().now().format("%Y%m%d-%H%M%S").to_string();
+                let mut suffix = 0;
+                loop {
+                    let mut name = logfile.file_name().unwrap_or_default().to_os_string();
+                    match suffix {
+                        0 => name.push(format!(".{}.bak", stamp)),
+                        n => name.push(format!(".{}-{}.bak", stamp, n)),
+                    }
+                    let backup = dir.join(name);
+                    match OpenOptions::new()
+                        .write(true)
+                        .create_new(true)
+                        .open(&backup)
+                    {
+                        Ok(dest) => break (backup, dest),
+                        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => suffix += 1,
+                        Err(err) => return Err(CannotWriteBackup(backup, err)),
+                    }
+                }
+            }
+        };
+        io::copy(&mut source, &mut dest).map_err(|err| CannotWriteBackup(backup.clone(), err))?;
+        log::info!("Copied {} to {}", logfile.display(), backup.display());
+
+        Ok(backup)
+    }
+
+    /// Diagnose problems with the configuration and environment, writing a report to `out`.
+    ///
+    /// The report gives the resolved logfile path, whether the logfile exists and can be parsed,
+    /// the effective time zone, the rounding granularity, and the minimum interval duration. A
+    /// missing logfile directory is only a problem if it cannot be created. Returns whether no
+    /// problems were found.
+    pub fn doctor<W: Write>(&self, mut out: W) -> io::Result<bool> {
+        let mut healthy = true;
+
+        match self.logfile_path() {
+            Ok(path) => {
+                writeln!(out, "Log file: {}", path.display())?;
+                match File::open(&path) {
+                    Ok(file) => match read_logfile::<TimeLog, _>(&path, file, |r| {
+                        serde_json::from_reader(r)
+                    }) {
+                        Ok(timelog) => {
+                            writeln!(out, "  parsed {} intervals", timelog.iter().count())?
+                        }
+                        Err(err) => {
+                            healthy = false;
+                            writeln!(out, "  cannot be parsed: {}", err)?;
+                        }
+                    },
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                        writeln!(out, "  does not exist yet")?;
+                        let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+                        if let Some(dir) = dir.filter(|dir| !dir.is_dir()) {
+                            // The directory is created on first write, which can only fail if
+                            // its nearest existing ancestor is not a writable directory.
+                            let ancestor = dir.ancestors().find(|dir| dir.exists());
+                            let creatable = ancestor.is_none_or(|ancestor| {
+                                fs::metadata(ancestor).is_ok_and(|meta| {
+                                    meta.is_dir() && !meta.permissions().readonly()
+                                })
+                            });
+                            if creatable {
+                                writeln!(
+                                    out,
+                                    "  directory {} will be created on first write",
+                                    dir.display()
+                                )?;
+                            } else {
+                                healthy = false;
+                                writeln!(
+                                    out,
+                                    "  directory {} does not exist and cannot be created",
+                                    dir.display()
+                                )?;
+                            }
+                        }
+                    }
+                    Err(err) => {
+                        healthy = false;
+                        writeln!(out, "  cannot be opened: {}", err)?;
+                    }
+                }
+            }
+            Err(err) => {
+                healthy = false;
+                writeln!(out, "Log file: {}", err)?;
+            }
+        }
+
+        let zone = zone::current();
+        writeln!(out, "Time zone: {} (UTC{})", zone, zone.now().offset())?;
+        writeln!(
+            out,
+            "Rounding: interval times to {} minutes",
+            interval::ROUNDING_MINUTES
+        )?;
+        match self.settings.min_duration {
+            Some(min) => writeln!(out, "Minimum duration: {}", format_duration_exact(min))?,
+            None => writeln!(out, "Minimum duration: none")?,
+        }
+
+        Ok(healthy)
+    }
+}
+
+/// How long to wait for another process to release the logfile lock.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How often to retry acquiring a contended logfile lock.
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A lock on the logfile, released on drop.
+#[derive(Debug)]
+pub struct LogFileLock {
+    file: Option<File>,
+}
+
+impl Drop for LogFileLock {
+    fn drop(&mut self) {
+        if let Some(file) = &self.file {
+            let _ = FileExt::unlock(file);
+        }
+    }
+}
+
+/// Lock an open lock file with `try_lock`, retrying while another process holds a conflicting lock.
+fn wait_for_lock<F>(file: File, try_lock: F) -> Result<LogFileLock, ConfigError>
+where
+    F: Fn(&File) -> io::Result<()>,
+{
+    let start = Instant::now();
+    loop {
+        match try_lock(&file) {
+            Ok(()) => return Ok(LogFileLock { file: Some(file) }),
+            Err(err) if err.kind() == fs2::lock_contended_error().kind() => {
+                if start.elapsed() >= LOCK_TIMEOUT {
+                    return Err(LogFileLocked);
+                }
+                thread::sleep(LOCK_RETRY_INTERVAL);
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+/// Expand a leading `~` and any `$VAR` or `${VAR}` environment variable references in a path.
+///
+/// Paths that are not valid unicode are returned unchanged.
+fn expand_path(path: PathBuf) -> Result<PathBuf, ConfigError> {
+    let path_str = match path.to_str() {
+        Some(s) => s,
+        None => return Ok(path),
+    };
+
+    let mut expanded = String::new();
+    let mut rest = path_str;
+
+    if rest == "~" || rest.starts_with("~/") {
+        let home = dirs::home_dir().ok_or(CannotFindHomeDir)?;
+        expanded.push_str(&home.to_string_lossy());
+        rest = &rest[1..];
+    }
+
+    while let Some(idx) = rest.find('$') {
+        expanded.push_str(&rest[..idx]);
+        rest = &rest[idx + 1..];
+
+        let (name, remainder) = if let Some(braced) = rest.strip_prefix('{') {
+            let end = braced
+                .find('}')
+                .ok_or_else(|| InvalidLogFilePath(path_str.into()))?;
+            (&braced[..end], &braced[end + 1..])
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            (&rest[..end], &rest[end..])
+        };
+
+        if name.is_empty() {
+            return Err(InvalidLogFilePath(path_str.into()));
+        }
+
+        let value = env::var(name).map_err(|_| UndefinedVariable(name.into()))?;
+        expanded.push_str(&value);
+        rest = remainder;
+    }
+
+    expanded.push_str(rest);
+    Ok(expanded.into())
+}
+
+/// Create the directory containing the given file, and any missing parent directories.
+fn create_parent_dir(path: &Path) -> Result<(), ConfigError> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() && !dir.is_dir() => {
+            log::info!("Creating directory {}", dir.display());
+            fs::create_dir_all(dir).map_err(|err| CannotCreateLogDir(dir.to_path_buf(), err))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Parse an open logfile with `parse`.
+///
+/// Comments are stripped from logfiles with a `.jsonc` extension before they are parsed.
+pub(crate) fn read_logfile<T, F>(path: &Path, file: File, parse: F) -> Result<T, ConfigError>
+where
+    F: FnOnce(&mut dyn Read) -> serde_json::Result<T>,
+{
+    let mut reader = BufReader::new(file);
+    if path.extension().is_some_and(|ext| ext == "jsonc") {
+        let mut reader = StripComments::with_settings(CommentSettings::c_style(), reader);
+        parse(&mut reader).map_err(SerdeJsonWithComments)
+    } else {
+        parse(&mut reader).map_err(SerdeJson)
     }
 }
 
@@ -98,19 +675,80 @@ pub enum ConfigError {
     /// Error deserializing the JSON logfile.
     SerdeJson(serde_json::Error),
 
+    /// Error deserializing a logfile that may contain comments.
+    SerdeJsonWithComments(serde_json::Error),
+
     /// The logfile cannot be found.
     CannotFindLogFile,
 
     /// The logfile cannot be opened.
     CannotOpenLogFile(io::Error),
+
+    /// The directory containing the logfile does not exist and cannot be created.
+    CannotCreateLogDir(PathBuf, io::Error),
+
+    /// A backup of the logfile cannot be written.
+    CannotWriteBackup(PathBuf, io::Error),
+
+    /// The logfile is locked by another process.
+    LogFileLocked,
+
+    /// The logfile path refers to the home directory, which cannot be found.
+    CannotFindHomeDir,
+
+    /// The logfile path refers to an undefined environment variable.
+    UndefinedVariable(String),
+
+    /// The logfile path contains a malformed variable reference.
+    InvalidLogFilePath(String),
+
+    /// A weekday setting could not be parsed.
+    InvalidWeekday(String),
+
+    /// A budget specification could not be parsed.
+    InvalidBudget(String),
+
+    /// An estimate specification could not be parsed.
+    InvalidEstimate(String),
+
+    /// A time of day setting could not be parsed.
+    InvalidTimeOfDay(String),
+
+    /// A yes-or-no setting could not be parsed.
+    InvalidAnswer(String),
 }
 
 impl Display for ConfigError {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self {
-            SerdeJson(err) => write!(f, "error parsing log: {}", err),
+            SerdeJson(err) => write!(f, "error parsing log as JSON: {}", err),
+            SerdeJsonWithComments(err) => {
+                write!(f, "error parsing log as JSON with comments: {}", err)
+            }
             CannotFindLogFile => write!(f, "cannot find log file"),
             CannotOpenLogFile(err) => write!(f, "cannot open log file: {}", err),
+            CannotCreateLogDir(dir, err) => write!(
+                f,
+                "cannot create log file directory {}: {}",
+                dir.display(),
+                err
+            ),
+            CannotWriteBackup(path, err) => {
+                write!(f, "cannot write backup {}: {}", path.display(), err)
+            }
+            LogFileLocked => write!(f, "log is locked by another timelog process"),
+            CannotFindHomeDir => write!(f, "cannot find home directory"),
+            UndefinedVariable(name) => {
+                write!(f, "log file path refers to undefined variable '{}'", name)
+            }
+            InvalidLogFilePath(path) => write!(f, "invalid log file path '{}'", path),
+            InvalidWeekday(day) => write!(f, "invalid weekday '{}'", day),
+            InvalidBudget(spec) => write!(f, "invalid budget '{}'; expected TAG=DURATION", spec),
+            InvalidEstimate(spec) => {
+                write!(f, "invalid estimate '{}'; expected TAG=DURATION", spec)
+            }
+            InvalidTimeOfDay(time) => write!(f, "invalid time of day '{}'; expected H:MM", time),
+            InvalidAnswer(answer) => write!(f, "invalid answer '{}'; expected yes or no", answer),
         }
     }
 }
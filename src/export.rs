@@ -0,0 +1,227 @@
+//! Export of timelog intervals to other formats.
+
+use crate::filter::Filter;
+use crate::timelog::TimeLog;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// A format to which intervals can be exported.
+#[derive(Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Hash, Default)]
+pub enum ExportFormat {
+    /// Comma-separated values, with a header row. Columns are the tag name, the start and end
+    /// times in RFC 3339 format, and the duration in seconds. Open intervals have an empty end
+    /// time.
+    #[default]
+    Csv,
+    /// iCalendar, with one event per closed interval. Open intervals are skipped.
+    Ics,
+    /// Timewarrior's interval format, with one `inc` line per interval.
+    Timewarrior,
+}
+
+impl FromStr for ExportFormat {
+    type Err = ExportFormatError;
+
+    fn from_str(s: &str) -> Result<ExportFormat, ExportFormatError> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(ExportFormat::Csv),
+            "ics" | "ical" | "icalendar" => Ok(ExportFormat::Ics),
+            "timewarrior" | "timew" => Ok(ExportFormat::Timewarrior),
+            _ => Err(ExportFormatError(s.into())),
+        }
+    }
+}
+
+/// Error returned when parsing an unknown export format name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ExportFormatError(String);
+
+impl Display for ExportFormatError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "unknown export format '{}'", self.0)
+    }
+}
+
+impl Error for ExportFormatError {}
+
+/// Write the intervals of the timelog that satisfy the filter to the given writer in the given
+/// format.
+pub fn write_export<W>(
+    timelog: &TimeLog,
+    filter: &Filter,
+    format: ExportFormat,
+    out: &mut W,
+) -> io::Result<()>
+where
+    W: Write + ?Sized,
+{
+    match format {
+        ExportFormat::Csv => write_csv(timelog, filter, ',', out),
+        ExportFormat::Ics => write_ics(timelog, filter, out),
+        ExportFormat::Timewarrior => write_timewarrior(timelog, filter, out),
+    }
+}
+
+/// The header row of CSV exports with the given delimiter.
+pub fn csv_header(delimiter: char) -> String {
+    csv_row(&["tag", "start", "end", "duration"], delimiter)
+}
+
+/// Write the intervals of the timelog that satisfy the filter to the given writer as CSV, with
+/// fields separated by the given delimiter.
+///
+/// This is the format written by `write_export` for `ExportFormat::Csv`, which uses a comma as the
+/// delimiter. Fields containing the delimiter, a quote, or a line break are quoted.
+pub fn write_csv<W>(
+    timelog: &TimeLog,
+    filter: &Filter,
+    delimiter: char,
+    out: &mut W,
+) -> io::Result<()>
+where
+    W: Write + ?Sized,
+{
+    writeln!(out, "{}", csv_header(delimiter))?;
+    write_csv_rows(timelog, filter, delimiter, out)
+}
+
+/// Write the intervals of the timelog that satisfy the filter to the given writer as CSV rows,
+/// without a header row. See `write_csv`.
+pub fn write_csv_rows<W>(
+    timelog: &TimeLog,
+    filter: &Filter,
+    delimiter: char,
+    out: &mut W,
+) -> io::Result<()>
+where
+    W: Write + ?Sized,
+{
+    for int in timelog.query(filter) {
+        let tag = timelog.tag_name(int.tag()).unwrap();
+        let end = int
+            .end()
+            .map(|end| end.to_rfc3339_opts(SecondsFormat::Secs, true))
+            .unwrap_or_default();
+
+        let fields = [
+            tag,
+            &int.start().to_rfc3339_opts(SecondsFormat::Secs, true),
+            &end,
+            &int.duration_clamped().num_seconds().to_string(),
+        ];
+        writeln!(out, "{}", csv_row(&fields, delimiter))?;
+    }
+
+    Ok(())
+}
+
+/// Join fields into a CSV row, quoting each field as necessary.
+fn csv_row(fields: &[&str], delimiter: char) -> String {
+    let fields: Vec<_> = fields
+        .iter()
+        .map(|field| csv_field(field, delimiter))
+        .collect();
+    fields.join(&delimiter.to_string())
+}
+
+/// Quote a CSV field if it contains characters that would otherwise be misinterpreted.
+fn csv_field(field: &str, delimiter: char) -> String {
+    if field.contains([delimiter, '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.into()
+    }
+}
+
+fn write_ics<W>(timelog: &TimeLog, filter: &Filter, out: &mut W) -> io::Result<()>
+where
+    W: Write + ?Sized,
+{
+    let stamp = ics_time(Utc::now());
+
+    write!(out, "BEGIN:VCALENDAR\r\n")?;
+    write!(out, "VERSION:2.0\r\n")?;
+    write!(out, "PRODID:-//timelog//timelog//EN\r\n")?;
+
+    for int in timelog.query(filter) {
+        let end = match int.end() {
+            Some(end) => end,
+            None => continue,
+        };
+
+        let tag = timelog.tag_name(int.tag()).unwrap();
+        write!(out, "BEGIN:VEVENT\r\n")?;
+        write!(
+            out,
+            "UID:{}-{}@timelog\r\n",
+            int.start().timestamp(),
+            ics_text(tag)
+        )?;
+        write!(out, "DTSTAMP:{}\r\n", stamp)?;
+        write!(out, "DTSTART:{}\r\n", ics_time(int.start()))?;
+        write!(out, "DTEND:{}\r\n", ics_time(end))?;
+        write!(out, "SUMMARY:{}\r\n", ics_text(tag))?;
+        write!(out, "END:VEVENT\r\n")?;
+    }
+
+    write!(out, "END:VCALENDAR\r\n")
+}
+
+fn write_timewarrior<W>(timelog: &TimeLog, filter: &Filter, out: &mut W) -> io::Result<()>
+where
+    W: Write + ?Sized,
+{
+    for int in timelog.query(filter) {
+        let tag = timewarrior_tag(timelog.tag_name(int.tag()).unwrap());
+        match int.end() {
+            Some(end) => writeln!(
+                out,
+                "inc {} - {} # {}",
+                ics_time(int.start()),
+                ics_time(end),
+                tag
+            )?,
+            None => writeln!(out, "inc {} # {}", ics_time(int.start()), tag)?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Quote a tag name as a Timewarrior tag token if it contains whitespace or quotes.
+fn timewarrior_tag(tag: &str) -> String {
+    if tag.is_empty() || tag.contains(|c: char| c.is_whitespace() || c == '"') {
+        format!("\"{}\"", tag.replace('"', "\\\""))
+    } else {
+        tag.into()
+    }
+}
+
+/// Format a time as an iCalendar UTC date-time.
+///
+/// Timewarrior uses the same format for its interval timestamps.
+fn ics_time(time: DateTime<Utc>) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// Escape a string for use as an iCalendar text value.
+fn ics_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | ';' | ',' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push_str("\\n"),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
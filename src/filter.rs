@@ -1,13 +1,15 @@
 //! Boolean precidates for filtering tagged intervals.
 
-use crate::interval::TaggedInterval;
+use crate::clock::{Clock, SystemClock};
+use crate::interval::{self, TaggedInterval};
 use crate::tags::TagId;
 
 use chrono::{DateTime, Duration, Utc};
 
+use std::collections::{BTreeSet, HashSet};
 use std::ops::{BitAnd, BitOr, Not};
 
-use std::fmt::{self, Debug, Formatter};
+use std::fmt::{self, Debug, Display, Formatter};
 
 /// A filter for tagged intervals.
 ///
@@ -53,19 +55,89 @@ impl Filter {
         self.eval_const() == ConstFilter::NonConst
     }
 
+    /// Compile this filter into an expression tree that short-circuits `and` and `or`.
+    ///
+    /// Compiling takes time proportional to the size of the filter, so this is worthwhile when
+    /// the filter will be evaluated on many intervals.
+    pub fn compile(&self) -> CompiledFilter {
+        fn pop(stack: &mut Vec<Expr>) -> Box<Expr> {
+            Box::new(stack.pop().unwrap_or(Expr::Test(FilterNode::False)))
+        }
+
+        let mut stack = Vec::new();
+        for node in &self.nodes {
+            let expr = match node {
+                FilterNode::Not => Expr::Not(pop(&mut stack)),
+                FilterNode::And => {
+                    let rhs = pop(&mut stack);
+                    Expr::And(pop(&mut stack), rhs)
+                }
+                FilterNode::Or => {
+                    let rhs = pop(&mut stack);
+                    Expr::Or(pop(&mut stack), rhs)
+                }
+                terminal => Expr::Test(terminal.clone()),
+            };
+            stack.push(expr);
+        }
+
+        CompiledFilter {
+            root: *pop(&mut stack),
+        }
+    }
+
     /// Create a closure that evaluates this filter on a tagged interval.
+    ///
+    /// The filter is compiled once, up front; see `Filter::compile`.
     pub fn build(&self) -> impl Fn(&TaggedInterval) -> bool + '_ {
-        move |int| self.eval(int)
+        let compiled = self.compile();
+        move |int| compiled.eval(int)
     }
 
     /// Create a closure that evaluates this filter on a tagged interval.
+    ///
+    /// The filter is compiled once, up front; see `Filter::compile`.
     pub fn build_ref(&self) -> impl Fn(&&TaggedInterval) -> bool + '_ {
-        move |int| self.eval(int)
+        let compiled = self.compile();
+        move |int| compiled.eval(int)
     }
 
     /// Create a closure that evaluates this filter on a tagged interval.
+    ///
+    /// The filter is compiled once, up front; see `Filter::compile`.
     pub fn build_mut(&self) -> impl FnMut(&&mut TaggedInterval) -> bool + '_ {
-        move |int| self.eval(int)
+        let compiled = self.compile();
+        move |int| compiled.eval(int)
+    }
+
+    /// Simplify this filter.
+    ///
+    /// Constants are folded throughout the expression, double negations are removed, and binary
+    /// expressions with identical operands (such as `x and x`) are collapsed. The simplified filter
+    /// evaluates identically to the original on every interval.
+    pub fn simplify(self) -> Filter {
+        simplify_nodes(&self.nodes)
+    }
+
+    /// The conjuncts of this filter, which are the filters it requires all of.
+    ///
+    /// Nested `and` expressions are flattened, so `a & (b & c)` has the conjuncts `a`, `b`, and
+    /// `c`, in that order. A filter that is not an `and` expression is its own only conjunct.
+    pub fn conjuncts(&self) -> Vec<Filter> {
+        let mut conjuncts = Vec::new();
+        collect_conjuncts(&self.nodes, &mut conjuncts);
+        conjuncts
+    }
+
+    /// The IDs of the tags this filter refers to.
+    pub fn tag_ids(&self) -> BTreeSet<TagId> {
+        self.nodes
+            .iter()
+            .filter_map(|node| match node {
+                FilterNode::HasTag(tag) | FilterNode::HasPrimaryTag(tag) => Some(*tag),
+                _ => None,
+            })
+            .collect()
     }
 
     /// Create a filter that evaluates to true if either this or the given filter evaluate to true.
@@ -145,6 +217,43 @@ impl BitOr for Filter {
     }
 }
 
+/// A filter compiled into an expression tree, created with `Filter::compile`.
+///
+/// A compiled filter evaluates identically to the filter it was compiled from, but skips the
+/// second operand of `and` and `or` when the first operand determines the result.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CompiledFilter {
+    root: Expr,
+}
+
+impl CompiledFilter {
+    /// Evaluate this filter on the given interval.
+    pub fn eval(&self, int: &TaggedInterval) -> bool {
+        self.root.eval(int)
+    }
+}
+
+/// Expression tree of a compiled filter.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Expr {
+    /// A terminal filter node
+    Test(FilterNode),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, int: &TaggedInterval) -> bool {
+        match self {
+            Expr::Test(node) => node.test(int),
+            Expr::Not(expr) => !expr.eval(int),
+            Expr::And(lhs, rhs) => lhs.eval(int) && rhs.eval(int),
+            Expr::Or(lhs, rhs) => lhs.eval(int) || rhs.eval(int),
+        }
+    }
+}
+
 /// A filter that always evaluates to true.
 pub fn filter_true() -> Filter {
     Filter {
@@ -160,28 +269,51 @@ pub fn filter_false() -> Filter {
 }
 
 /// Create a filter that evaluates to true if all of the given filters evaluate to true.
+///
+/// Repeated filters are only included once.
 pub fn and_all<I>(filters: I) -> Filter
 where
     I: IntoIterator<Item = Filter>,
 {
-    filters.into_iter().fold(filter_true(), Filter::and)
+    dedup(filters).fold(filter_true(), Filter::and)
 }
 
 /// Create a filter that evaluates to true if any of the given filters evaluate to true.
+///
+/// Repeated filters are only included once.
 pub fn or_all<I>(filters: I) -> Filter
 where
     I: IntoIterator<Item = Filter>,
 {
-    filters.into_iter().fold(filter_false(), Filter::or)
+    dedup(filters).fold(filter_false(), Filter::or)
+}
+
+/// Remove repeated filters, keeping the first occurrence of each.
+fn dedup<I>(filters: I) -> impl Iterator<Item = Filter>
+where
+    I: IntoIterator<Item = Filter>,
+{
+    let mut seen = HashSet::new();
+    filters
+        .into_iter()
+        .filter(move |filter| seen.insert(filter.clone()))
 }
 
-/// A filter that passes if the interval has the given tag.
+/// A filter that passes if the interval has the given tag, either as its primary tag or as an
+/// extra tag.
 pub fn has_tag(tag: TagId) -> Filter {
     Filter {
         nodes: vec![FilterNode::HasTag(tag)],
     }
 }
 
+/// A filter that passes if the interval's primary tag is the given tag.
+pub fn has_primary_tag(tag: TagId) -> Filter {
+    Filter {
+        nodes: vec![FilterNode::HasPrimaryTag(tag)],
+    }
+}
+
 /// A filter that passes if the interval is closed.
 pub fn is_closed() -> Filter {
     Filter {
@@ -236,6 +368,28 @@ pub fn shorter_than_strict(duration: Duration) -> Filter {
     }
 }
 
+/// A filter that passes if the interval contains the given instant.
+///
+/// A closed interval contains the instants from its start time up to but not including its end
+/// time. An open interval contains the instants from its start time up to the current time.
+pub fn active_at(time: DateTime<Utc>) -> Filter {
+    active_at_with(time, &SystemClock)
+}
+
+/// A filter that passes if the interval contains the given instant, taking the current time from
+/// the given clock.
+///
+/// The current time is read once, when the filter is built, so that every interval is tested
+/// against the same instant.
+pub fn active_at_with<C>(time: DateTime<Utc>, clock: &C) -> Filter
+where
+    C: Clock,
+{
+    Filter {
+        nodes: vec![FilterNode::ActiveAt(time, clock.now())],
+    }
+}
+
 /// A filter that passes if the interval started no earlier than the given time.
 pub fn started_after(time: DateTime<Utc>) -> Filter {
     !started_before_strict(time)
@@ -289,6 +443,10 @@ fn write_as_tree(nodes: &[FilterNode], idx: usize, f: &mut Formatter) -> Result<
                 write!(f, "HasTag({})", tag)?;
                 Ok(idx - 1)
             }
+            FilterNode::HasPrimaryTag(tag) => {
+                write!(f, "HasPrimaryTag({})", tag)?;
+                Ok(idx - 1)
+            }
             FilterNode::IsClosed => {
                 write!(f, "IsClosed")?;
                 Ok(idx - 1)
@@ -317,6 +475,10 @@ fn write_as_tree(nodes: &[FilterNode], idx: usize, f: &mut Formatter) -> Result<
                 write!(f, "ShorterThanStrict({:?})", dur)?;
                 Ok(idx - 1)
             }
+            FilterNode::ActiveAt(time, now) => {
+                write!(f, "ActiveAt({:?}, {:?})", time, now)?;
+                Ok(idx - 1)
+            }
 
             FilterNode::Not => {
                 write!(f, "Not(")?;
@@ -348,6 +510,146 @@ fn write_as_tree(nodes: &[FilterNode], idx: usize, f: &mut Formatter) -> Result<
     }
 }
 
+/// Push the conjuncts of the subexpression made up of exactly the given nodes.
+fn collect_conjuncts(nodes: &[FilterNode], conjuncts: &mut Vec<Filter>) {
+    let end = nodes.len();
+    match nodes.last() {
+        Some(FilterNode::And) => {
+            let rhs_start = subtree_start(nodes, end - 1);
+            collect_conjuncts(&nodes[..rhs_start], conjuncts);
+            collect_conjuncts(&nodes[rhs_start..end - 1], conjuncts);
+        }
+        _ => conjuncts.push(Filter {
+            nodes: nodes.to_vec(),
+        }),
+    }
+}
+
+/// Simplify the subexpression made up of exactly the given nodes.
+fn simplify_nodes(nodes: &[FilterNode]) -> Filter {
+    let end = nodes.len();
+    match nodes.last() {
+        Some(FilterNode::Not) => {
+            let mut inner = simplify_nodes(&nodes[..end - 1]);
+            if inner.nodes.last() == Some(&FilterNode::Not) {
+                inner.nodes.pop();
+                inner
+            } else {
+                inner.inverted()
+            }
+        }
+
+        Some(op @ FilterNode::And) | Some(op @ FilterNode::Or) => {
+            let rhs_start = subtree_start(nodes, end - 1);
+            let lhs = simplify_nodes(&nodes[..rhs_start]);
+            let rhs = simplify_nodes(&nodes[rhs_start..end - 1]);
+
+            if lhs == rhs {
+                lhs
+            } else if op == &FilterNode::And {
+                lhs.and(rhs)
+            } else {
+                lhs.or(rhs)
+            }
+        }
+
+        Some(node) => Filter {
+            nodes: vec![node.clone()],
+        },
+
+        None => filter_false(),
+    }
+}
+
+/// Displays the filter as an infix expression, such as `has_tag(0) and not closed`.
+impl Display for Filter {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.nodes.is_empty() {
+            write!(f, "false")
+        } else {
+            write_infix(&self.nodes, f)
+        }
+    }
+}
+
+/// The index at which the subexpression ending just before `end` begins.
+fn subtree_start(nodes: &[FilterNode], end: usize) -> usize {
+    match nodes.get(end.wrapping_sub(1)) {
+        Some(FilterNode::Not) => subtree_start(nodes, end - 1),
+        Some(FilterNode::And) | Some(FilterNode::Or) => {
+            let rhs_start = subtree_start(nodes, end - 1);
+            subtree_start(nodes, rhs_start)
+        }
+        Some(_) => end - 1,
+        None => 0,
+    }
+}
+
+/// Write a complete subexpression in infix notation.
+fn write_infix(nodes: &[FilterNode], f: &mut Formatter) -> fmt::Result {
+    let end = nodes.len();
+    match nodes.last() {
+        Some(FilterNode::Not) => {
+            write!(f, "not ")?;
+            write_operand(&nodes[..end - 1], None, f)
+        }
+
+        Some(op @ FilterNode::And) | Some(op @ FilterNode::Or) => {
+            let rhs_start = subtree_start(nodes, end - 1);
+            write_operand(&nodes[..rhs_start], Some(op), f)?;
+            match op {
+                FilterNode::And => write!(f, " and ")?,
+                _ => write!(f, " or ")?,
+            }
+            write_operand(&nodes[rhs_start..end - 1], Some(op), f)
+        }
+
+        Some(FilterNode::True) => write!(f, "true"),
+        Some(FilterNode::False) => write!(f, "false"),
+        Some(FilterNode::HasTag(tag)) => write!(f, "has_tag({})", tag),
+        Some(FilterNode::HasPrimaryTag(tag)) => write!(f, "has_primary_tag({})", tag),
+        Some(FilterNode::IsClosed) => write!(f, "closed"),
+        Some(FilterNode::StartedBefore(time)) => write!(f, "started_before({})", time),
+        Some(FilterNode::EndedBefore(time)) => write!(f, "ended_before({})", time),
+        Some(FilterNode::ShorterThan(dur)) => {
+            write!(f, "shorter_than({})", interval::format_duration(*dur))
+        }
+        Some(FilterNode::StartedBeforeStrict(time)) => {
+            write!(f, "started_strictly_before({})", time)
+        }
+        Some(FilterNode::EndedBeforeStrict(time)) => write!(f, "ended_strictly_before({})", time),
+        Some(FilterNode::ShorterThanStrict(dur)) => write!(
+            f,
+            "strictly_shorter_than({})",
+            interval::format_duration(*dur)
+        ),
+        Some(FilterNode::ActiveAt(time, _)) => write!(f, "active_at({})", time),
+
+        None => Ok(()),
+    }
+}
+
+/// Write an operand of the given operator, parenthesizing it if it is a binary expression with a
+/// different operator.
+fn write_operand(
+    nodes: &[FilterNode],
+    parent: Option<&FilterNode>,
+    f: &mut Formatter,
+) -> fmt::Result {
+    let needs_parens = match nodes.last() {
+        Some(node @ FilterNode::And) | Some(node @ FilterNode::Or) => Some(node) != parent,
+        _ => false,
+    };
+
+    if needs_parens {
+        write!(f, "(")?;
+        write_infix(nodes, f)?;
+        write!(f, ")")
+    } else {
+        write_infix(nodes, f)
+    }
+}
+
 /// Filters are implemented internally as an RPN representation, using these operators, values, and
 /// predicates.
 #[derive(Debug, Clone, PartialOrd, Ord, PartialEq, Eq, Hash)]
@@ -357,8 +659,10 @@ enum FilterNode {
     True,
     /// Constant false
     False,
-    /// True if the interval has the given tag
+    /// True if the interval has the given tag, as its primary tag or an extra tag
     HasTag(TagId),
+    /// True if the interval's primary tag is the given tag
+    HasPrimaryTag(TagId),
     /// True if the interval is closed
     IsClosed,
     /// True if the interval started before this time (non-strict)
@@ -373,6 +677,9 @@ enum FilterNode {
     EndedBeforeStrict(DateTime<Utc>),
     /// True if the interval is shorter than this duration (strict)
     ShorterThanStrict(Duration),
+    /// True if the interval contains the first instant (and, if open, the first instant is not
+    /// after the second, the time at which the filter was built)
+    ActiveAt(DateTime<Utc>, DateTime<Utc>),
 
     // Operators
     /// Invert top of stack
@@ -387,21 +694,6 @@ impl FilterNode {
     /// Evaluate this filter node on a given value stack and interval.
     fn eval(&self, stack: &mut Vec<bool>, int: &TaggedInterval) {
         match self {
-            FilterNode::True => stack.push(true),
-            FilterNode::False => stack.push(false),
-            FilterNode::HasTag(tag) => stack.push(int.tag() == *tag),
-            FilterNode::IsClosed => stack.push(int.end().is_some()),
-            FilterNode::StartedBefore(time) => stack.push(int.start() <= *time),
-            FilterNode::EndedBefore(time) => {
-                stack.push(int.end().map(|end| end <= *time).unwrap_or(false))
-            }
-            FilterNode::ShorterThan(dur) => stack.push(int.duration() <= *dur),
-            FilterNode::StartedBeforeStrict(time) => stack.push(int.start() < *time),
-            FilterNode::EndedBeforeStrict(time) => {
-                stack.push(int.end().map(|end| end < *time).unwrap_or(false))
-            }
-            FilterNode::ShorterThanStrict(dur) => stack.push(int.duration() < *dur),
-
             FilterNode::Not => {
                 let b = stack.pop().unwrap_or(false);
                 stack.push(!b);
@@ -414,6 +706,34 @@ impl FilterNode {
                 let (b2, b1) = (stack.pop().unwrap_or(false), stack.pop().unwrap_or(false));
                 stack.push(b1 || b2);
             }
+
+            terminal => stack.push(terminal.test(int)),
+        }
+    }
+
+    /// Evaluate this terminal filter node on a given interval.
+    ///
+    /// Operators evaluate to false.
+    fn test(&self, int: &TaggedInterval) -> bool {
+        match self {
+            FilterNode::True => true,
+            FilterNode::False => false,
+            FilterNode::HasTag(tag) => int.has_tag(*tag),
+            FilterNode::HasPrimaryTag(tag) => int.tag() == *tag,
+            FilterNode::IsClosed => int.end().is_some(),
+            FilterNode::StartedBefore(time) => int.start() <= *time,
+            FilterNode::EndedBefore(time) => int.end().map(|end| end <= *time).unwrap_or(false),
+            FilterNode::ShorterThan(dur) => int.duration() <= *dur,
+            FilterNode::StartedBeforeStrict(time) => int.start() < *time,
+            FilterNode::EndedBeforeStrict(time) => {
+                int.end().map(|end| end < *time).unwrap_or(false)
+            }
+            FilterNode::ShorterThanStrict(dur) => int.duration() < *dur,
+            FilterNode::ActiveAt(time, now) => {
+                int.contains(*time) && (int.is_closed() || time <= now)
+            }
+
+            FilterNode::Not | FilterNode::And | FilterNode::Or => false,
         }
     }
 
@@ -1,23 +1,80 @@
 //! Time interval types and definitions.
 
 use crate::tags::TagId;
+use crate::zone;
 
-use chrono::{DateTime, Duration, Local, TimeZone, Timelike, Utc};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Duration, Timelike, Utc};
 use serde::{Deserialize, Serialize};
 
+use std::cmp::Ordering;
 use std::ops::Add;
+use std::str::FromStr;
 use std::time::Duration as StdDuration;
 
+use std::error::Error;
 use std::fmt::{self, Display, Formatter};
 
 pub static FMT_STR: &str = "%a %F %I:%M%P";
 
+/// A validated `strftime`-style format string used to display times.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TimeFormat(String);
+
+impl TimeFormat {
+    /// Get the format string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Default for TimeFormat {
+    fn default() -> TimeFormat {
+        TimeFormat(FMT_STR.into())
+    }
+}
+
+impl Display for TimeFormat {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for TimeFormat {
+    type Err = TimeFormatError;
+
+    fn from_str(s: &str) -> Result<TimeFormat, TimeFormatError> {
+        if StrftimeItems::new(s).any(|item| item == Item::Error) {
+            Err(TimeFormatError(s.into()))
+        } else {
+            Ok(TimeFormat(s.into()))
+        }
+    }
+}
+
+/// Error returned when a time format string contains an invalid specifier.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TimeFormatError(String);
+
+impl Display for TimeFormatError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "invalid time format '{}'", self.0)
+    }
+}
+
+impl Error for TimeFormatError {}
+
 /// A possibly-open time interval.
 ///
 /// An interval is represented by a start time and, if it is closed, a duration.
+///
+/// Durations are serialized as `H:MM` strings, or `H:MM:SS` if they are not a whole number of
+/// minutes. Durations in the older `{"secs": ..., "nanos": ...}` form are also accepted when
+/// deserializing.
 #[derive(Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Interval {
     start: DateTime<Utc>,
+    #[serde(with = "duration_serde")]
     duration: Option<StdDuration>,
 }
 
@@ -71,11 +128,18 @@ impl Interval {
 
     /// Round the start time back to the nearest quarter hour, and the end time forward to the
     /// nearest quarter hour.
+    ///
+    /// A closed interval that starts and ends within the same quarter hour is rounded to a
+    /// zero-length interval at the start of that quarter hour, rather than a full quarter hour.
     pub fn round_to_quarter_hours(self) -> Interval {
         let start = QuarterHour::floor(&self.start());
-        let duration = self
-            .end()
-            .and_then(|end| (QuarterHour::ceil(&end) - start).to_std().ok());
+        let duration = self.end().and_then(|end| {
+            if QuarterHour::floor(&end) == start {
+                Some(StdDuration::from_secs(0))
+            } else {
+                (QuarterHour::ceil(&end) - start).to_std().ok()
+            }
+        });
 
         Interval { start, duration }
     }
@@ -96,65 +160,271 @@ impl Interval {
             .map(|d| self.start + Duration::from_std(d).unwrap())
     }
 
+    /// Does this interval contain the given time?
+    ///
+    /// An interval contains its start time but not its end time. Open intervals contain every time
+    /// after their start.
+    pub fn contains(&self, time: DateTime<Utc>) -> bool {
+        self.start <= time && self.end().is_none_or(|end| time < end)
+    }
+
     /// Get the duration of this interval.
     ///
     /// If the interval is still open, this will return the duration elapsed between its start time
     /// and the current time. If the start time is in the future, this duration will be negative.
     pub fn duration(&self) -> Duration {
+        self.elapsed_at(Utc::now())
+    }
+
+    /// Get the duration of this interval as of the given time.
+    ///
+    /// If the interval is still open, this will return the duration elapsed between its start time
+    /// and `now`, rounded up to the next quarter hour. If the start time is after `now`, this
+    /// duration will be negative. If the interval is closed, `now` is ignored.
+    pub fn elapsed_at(&self, now: DateTime<Utc>) -> Duration {
         self.duration
             .map(|d| Duration::from_std(d).unwrap())
-            .unwrap_or_else(|| ceil_time(&Utc::now()).signed_duration_since(self.start))
+            .unwrap_or_else(|| ceil_time(&now).signed_duration_since(self.start))
+    }
+
+    /// Get the duration of this interval, clamped to be non-negative.
+    ///
+    /// This is the same as `duration`, except that an open interval starting in the future has a
+    /// duration of zero rather than a negative duration.
+    pub fn duration_clamped(&self) -> Duration {
+        self.duration().max(Duration::zero())
+    }
+
+    /// Get the clamped duration of the part of this interval that lies between `start` and `end`.
+    ///
+    /// A bound of `None` leaves that side of the interval unclipped. Open intervals are treated
+    /// as ending at the current time, as with `duration`.
+    pub fn duration_within(
+        &self,
+        start: Option<DateTime<Utc>>,
+        end: Option<DateTime<Utc>>,
+    ) -> Duration {
+        let int_end = self.start + self.duration();
+        let from = start.map_or(self.start, |start| start.max(self.start));
+        let to = end.map_or(int_end, |end| end.min(int_end));
+
+        to.signed_duration_since(from).max(Duration::zero())
+    }
+
+    /// Display this interval with its times rendered in the given format.
+    pub fn display_with<'a>(&'a self, format: &'a TimeFormat) -> IntervalDisplay<'a> {
+        IntervalDisplay {
+            interval: self,
+            format: format.as_str(),
+            verbose: false,
+        }
     }
 }
 
 impl Display for Interval {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        let start = Local.from_utc_datetime(&self.start.naive_utc());
+        let display = IntervalDisplay {
+            interval: self,
+            format: FMT_STR,
+            verbose: false,
+        };
+        Display::fmt(&display, f)
+    }
+}
+
+/// Helper for displaying an interval with a particular time format.
+#[derive(Debug, Clone, Copy)]
+pub struct IntervalDisplay<'a> {
+    interval: &'a Interval,
+    format: &'a str,
+    verbose: bool,
+}
 
-        fn fmt_duration(dur: Duration) -> String {
-            format!("{}:{:02}", dur.num_hours(), dur.num_minutes() % 60)
+impl<'a> IntervalDisplay<'a> {
+    /// Also display the exact duration of the interval, to the second.
+    ///
+    /// For open intervals this is the exact time elapsed since the start time, rather than the
+    /// elapsed time rounded up to the next quarter hour.
+    pub fn verbose(self) -> IntervalDisplay<'a> {
+        IntervalDisplay {
+            verbose: true,
+            ..self
         }
+    }
+}
 
-        match self.end() {
+impl<'a> Display for IntervalDisplay<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let IntervalDisplay {
+            interval,
+            format,
+            verbose,
+        } = *self;
+        let start = zone::current().from_utc(&interval.start);
+
+        match interval.end() {
             Some(end) => {
-                let end = Local.from_utc_datetime(&end.naive_utc());
+                let end = zone::current().from_utc(&end);
                 write!(
                     f,
                     "{} -- {} ({})",
-                    start.format(FMT_STR),
-                    end.format(FMT_STR),
-                    fmt_duration(self.duration()),
+                    start.format(format),
+                    end.format(format),
+                    format_duration(interval.duration()),
                 )
             }
 
             None => write!(
                 f,
                 "{} -- OPEN ({})",
-                start.format(FMT_STR),
-                fmt_duration(self.duration()),
+                start.format(format),
+                format_duration(interval.duration()),
             ),
+        }?;
+
+        if verbose {
+            match interval.duration {
+                Some(dur) => write!(
+                    f,
+                    " [exactly {}]",
+                    format_duration_exact(Duration::from_std(dur).unwrap())
+                )?,
+                None => write!(
+                    f,
+                    " [elapsed {}]",
+                    format_duration_exact(Utc::now().signed_duration_since(interval.start))
+                )?,
+            }
         }
+
+        Ok(())
     }
 }
 
 /// A time interval with an associated tag.
-#[derive(Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Hash, Serialize, Deserialize)]
+///
+/// Every tagged interval has a primary tag, which is the tag it is opened, closed, and listed
+/// under. It may also carry any number of extra tags, for time that counts toward several tags at
+/// once. Extra tags are serialized under an `extra_tags` key, which is omitted when there are none,
+/// so single-tag intervals have the same form as before extra tags were introduced.
+///
+/// An interval opened deliberately alongside other open intervals of its tag is marked as
+/// concurrent, so that the overlap is not reported as a problem. This is serialized under a
+/// `concurrent` key, which is likewise omitted when unset.
+///
+/// The derived ordering compares tag IDs first, then intervals, which groups intervals by tag.
+/// Use `TaggedInterval::by_start` to sort intervals chronologically instead.
+#[derive(Debug, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct TaggedInterval {
     tag: TagId,
     interval: Interval,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    extra_tags: Vec<TagId>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    concurrent: bool,
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
 }
 
 impl TaggedInterval {
     /// Create a new tagged interval with the given tag ID and interval.
     pub fn new(tag: TagId, interval: Interval) -> TaggedInterval {
-        TaggedInterval { tag, interval }
+        TaggedInterval {
+            tag,
+            interval,
+            extra_tags: Vec::new(),
+            concurrent: false,
+        }
     }
 
-    /// Get the tag ID of this tagged interval.
+    /// Get the primary tag ID of this tagged interval.
     pub fn tag(&self) -> TagId {
         self.tag
     }
 
+    /// Set the primary tag ID of this tagged interval.
+    ///
+    /// If the new primary tag was one of the extra tags, it is removed from them.
+    pub fn set_tag(&mut self, tag: TagId) {
+        self.tag = tag;
+        self.extra_tags.retain(|&extra| extra != tag);
+    }
+
+    /// The extra tag IDs of this tagged interval, in the order they were added.
+    pub fn extra_tags(&self) -> &[TagId] {
+        &self.extra_tags
+    }
+
+    /// An iterator over all tag IDs of this tagged interval, primary tag first.
+    pub fn tags(&self) -> impl Iterator<Item = TagId> + '_ {
+        std::iter::once(self.tag).chain(self.extra_tags.iter().copied())
+    }
+
+    /// Does this tagged interval have the given tag, either as its primary tag or an extra tag?
+    pub fn has_tag(&self, tag: TagId) -> bool {
+        self.tags().any(|t| t == tag)
+    }
+
+    /// Add an extra tag to this tagged interval.
+    ///
+    /// Returns `false` if the interval already has the tag.
+    pub fn add_tag(&mut self, tag: TagId) -> bool {
+        if self.has_tag(tag) {
+            false
+        } else {
+            self.extra_tags.push(tag);
+            true
+        }
+    }
+
+    /// Remove an extra tag from this tagged interval.
+    ///
+    /// The primary tag cannot be removed. Returns `false` if the given tag is not an extra tag of
+    /// this interval.
+    pub fn remove_tag(&mut self, tag: TagId) -> bool {
+        let len = self.extra_tags.len();
+        self.extra_tags.retain(|&extra| extra != tag);
+        self.extra_tags.len() != len
+    }
+
+    /// Was this interval opened alongside other open intervals with the same tag?
+    pub fn is_concurrent(&self) -> bool {
+        self.concurrent
+    }
+
+    /// Mark whether this interval was opened alongside other open intervals with the same tag.
+    pub fn set_concurrent(&mut self, concurrent: bool) {
+        self.concurrent = concurrent;
+    }
+
+    /// Replace every tag ID of this tagged interval using the given mapping.
+    pub fn remap_tags<F: FnMut(TagId) -> TagId>(&mut self, mut remap: F) {
+        self.tag = remap(self.tag);
+        for extra in &mut self.extra_tags {
+            *extra = remap(*extra);
+        }
+    }
+
+    /// Compare two tagged intervals by start time.
+    ///
+    /// Intervals with the same start time are ordered by end time, with open intervals last, and
+    /// then by tag ID. This is a total order consistent with equality, for use with `sort_by`,
+    /// e.g. `intervals.sort_by(TaggedInterval::by_start)`.
+    pub fn by_start(a: &TaggedInterval, b: &TaggedInterval) -> Ordering {
+        let key = |int: &TaggedInterval| {
+            let interval = int.interval;
+            (
+                interval.start,
+                interval.duration.is_none(),
+                interval.duration,
+                int.tag,
+            )
+        };
+        key(a).cmp(&key(b))
+    }
+
     /// Get the interval of this tagged interval.
     pub fn interval(&self) -> &Interval {
         &self.interval
@@ -168,7 +438,7 @@ impl TaggedInterval {
     /// Open a new interval with the given tag at the given start time.
     pub fn open(tag: TagId, start: DateTime<Utc>) -> TaggedInterval {
         let interval = Interval::open(start);
-        TaggedInterval { tag, interval }
+        TaggedInterval::new(tag, interval)
     }
 
     /// Close this tagged interval at the given end time.
@@ -176,13 +446,16 @@ impl TaggedInterval {
     /// Returns `None` if the given end time is before this interval's start time.
     pub fn close(&self, end: DateTime<Utc>) -> Option<TaggedInterval> {
         let interval = self.interval.close(end)?;
-        Some(TaggedInterval { interval, ..*self })
+        Some(TaggedInterval {
+            interval,
+            ..self.clone()
+        })
     }
 
     /// Open a new interval with the given tag at the current time.
     pub fn open_now(tag: TagId) -> TaggedInterval {
         let interval = Interval::open_now();
-        TaggedInterval { tag, interval }
+        TaggedInterval::new(tag, interval)
     }
 
     /// Close this tagged interval at the current time.
@@ -190,7 +463,10 @@ impl TaggedInterval {
     /// Returns `None` if this interval's start time is in the future.
     pub fn close_now(&self) -> Option<TaggedInterval> {
         let interval = self.interval.close_now()?;
-        Some(TaggedInterval { interval, ..*self })
+        Some(TaggedInterval {
+            interval,
+            ..self.clone()
+        })
     }
 
     /// Is this tagged interval closed?
@@ -208,6 +484,11 @@ impl TaggedInterval {
         self.interval.end()
     }
 
+    /// Does this tagged interval contain the given time?
+    pub fn contains(&self, time: DateTime<Utc>) -> bool {
+        self.interval.contains(time)
+    }
+
     /// Get the duration of this tagged interval.
     ///
     /// If the interval is not yet closed, this will return the duration elapsed between the
@@ -217,19 +498,83 @@ impl TaggedInterval {
         self.interval.duration()
     }
 
+    /// Get the duration of this tagged interval, clamped to be non-negative.
+    ///
+    /// This is the same as `duration`, except that an open interval starting in the future has a
+    /// duration of zero rather than a negative duration.
+    pub fn duration_clamped(&self) -> Duration {
+        self.interval.duration_clamped()
+    }
+
     /// Round the start time back to the nearest quarter hour, and the end time forward to the
     /// nearest quarter hour.
+    ///
+    /// See `Interval::round_to_quarter_hours`.
     pub fn round_to_quarter_hours(&self) -> TaggedInterval {
         let interval = self.interval.round_to_quarter_hours();
-        TaggedInterval { interval, ..*self }
+        TaggedInterval {
+            interval,
+            ..self.clone()
+        }
+    }
+
+    /// Split this tagged interval in two at the given time.
+    ///
+    /// The first interval ends at `at`, and the second starts at `at`; both keep this interval's
+    /// tag.
+    ///
+    /// Returns `None` if this interval is open, or if `at` is not strictly between its start and end
+    /// times.
+    pub fn split_at(&self, at: DateTime<Utc>) -> Option<(TaggedInterval, TaggedInterval)> {
+        let end = self.end()?;
+        if at <= self.start() || end <= at {
+            return None;
+        }
+
+        let first = Interval::open(self.start()).close(at)?;
+        let second = Interval::open(at).close(end)?;
+        Some((
+            TaggedInterval::new(self.tag, first),
+            TaggedInterval::new(self.tag, second),
+        ))
     }
 }
 
+/// Format a duration as hours and minutes, `H:MM`.
+///
+/// Negative durations are formatted with a leading minus sign, `-H:MM`.
+pub fn format_duration(dur: Duration) -> String {
+    if dur < Duration::zero() {
+        return format!("-{}", format_duration(-dur));
+    }
+
+    format!("{}:{:02}", dur.num_hours(), dur.num_minutes() % 60)
+}
+
+/// Format a duration as hours, minutes, and seconds, `H:MM:SS`.
+///
+/// Negative durations are formatted with a leading minus sign, `-H:MM:SS`.
+pub fn format_duration_exact(dur: Duration) -> String {
+    if dur < Duration::zero() {
+        return format!("-{}", format_duration_exact(-dur));
+    }
+
+    format!(
+        "{}:{:02}:{:02}",
+        dur.num_hours(),
+        dur.num_minutes() % 60,
+        dur.num_seconds() % 60
+    )
+}
+
 /// Attach a tag to an interval.
 pub fn tag(tag: TagId, interval: Interval) -> TaggedInterval {
     TaggedInterval::new(tag, interval)
 }
 
+/// The increment, in minutes, to which interval start and end times are rounded.
+pub const ROUNDING_MINUTES: i64 = 15;
+
 /// Quarter hour increments. Utility type for rounding times to adjacent quarter hours.
 #[derive(Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Hash)]
 enum QuarterHour {
@@ -323,3 +668,81 @@ where
 {
     QuarterHour::ceil(time)
 }
+
+/// Serialization of interval durations as human-readable strings.
+mod duration_serde {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use std::time::Duration as StdDuration;
+
+    /// The serialized forms of a duration.
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum DurationRepr {
+        /// `H:MM`, `H:MM:SS`, or `H:MM:SS.fffffffff`
+        Text(String),
+        /// The form used by serde's default `Duration` implementation
+        Struct { secs: u64, nanos: u32 },
+    }
+
+    pub fn serialize<S>(duration: &Option<StdDuration>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match duration {
+            Some(duration) => serializer.serialize_str(&format(*duration)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<StdDuration>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Option::<DurationRepr>::deserialize(deserializer)?
+            .map(|repr| match repr {
+                DurationRepr::Text(text) => parse(&text)
+                    .ok_or_else(|| D::Error::custom(format!("invalid duration '{}'", text))),
+                DurationRepr::Struct { secs, nanos } => Ok(StdDuration::new(secs, nanos)),
+            })
+            .transpose()
+    }
+
+    fn format(duration: StdDuration) -> String {
+        let secs = duration.as_secs();
+        let nanos = duration.subsec_nanos();
+        let hm = format!("{}:{:02}", secs / 3600, secs / 60 % 60);
+
+        if nanos != 0 {
+            format!("{}:{:02}.{:09}", hm, secs % 60, nanos)
+        } else if !secs.is_multiple_of(60) {
+            format!("{}:{:02}", hm, secs % 60)
+        } else {
+            hm
+        }
+    }
+
+    fn parse(text: &str) -> Option<StdDuration> {
+        let mut parts = text.split(':');
+        let hours: u64 = parts.next()?.parse().ok()?;
+        let minutes: u64 = parts.next()?.parse().ok()?;
+        let (seconds, nanos) = match parts.next() {
+            Some(seconds) => match seconds.split_once('.') {
+                Some((whole, frac)) if frac.len() == 9 => (whole.parse().ok()?, frac.parse().ok()?),
+                Some(_) => return None,
+                None => (seconds.parse().ok()?, 0),
+            },
+            None => (0, 0),
+        };
+
+        if parts.next().is_some() || minutes >= 60 || seconds >= 60 {
+            return None;
+        }
+
+        Some(StdDuration::new(
+            hours * 3600 + minutes * 60 + seconds,
+            nanos,
+        ))
+    }
+}
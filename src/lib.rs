@@ -1,6 +1,35 @@
+pub mod clock;
 pub mod commands;
 pub mod config;
+pub mod export;
 pub mod filter;
 pub mod interval;
 pub mod tags;
+pub mod template;
 pub mod timelog;
+pub mod zone;
+
+use crate::commands::{ChangeStatus, Command, CommandError};
+use crate::timelog::TimeLog;
+
+use structopt::StructOpt;
+
+/// Run a command line against an in-memory timelog, with default settings.
+///
+/// The arguments are those that would follow the program name, e.g. `["open", "work"]`.
+/// On success, returns whether the timelog was changed, along with everything the command wrote
+/// to its output stream.
+///
+/// Commands that would prompt for confirmation read the answer from standard input if it is a
+/// terminal, and otherwise fail with `CommandError::ConfirmationRequired`. To proceed without a
+/// prompt, run the command with `Command::execute` and settings with `yes` set, as the `--yes`
+/// option does, or avoid the prompt with the command's own options, such as `open --create`.
+pub fn run_command(
+    timelog: &mut TimeLog,
+    args: &[&str],
+) -> Result<(ChangeStatus, String), CommandError> {
+    let command = Command::from_iter_safe(std::iter::once("timelog").chain(args.iter().copied()))
+        .map_err(|err| CommandError::InvalidArguments(err.message))?;
+    let (status, output, _) = command.run_to_string(timelog)?;
+    Ok((status, output))
+}
@@ -1,30 +1,108 @@
-use timelog::commands::{CommandError, StdOutputs};
+use timelog::commands::{ChangeStatus, Command, CommandError, StdOutputs};
 use timelog::config::{ConfigError, Options};
+use timelog::filter;
 
 use structopt::StructOpt;
 
 use std::error::Error;
 use std::fmt::{self, Display, Formatter};
+use std::io::{self, Write};
 use std::process;
 
+/// Exit code for commands that ran successfully but matched no intervals.
+const NO_MATCHES_EXIT_CODE: i32 = 2;
+
 fn main() {
-    if let Err(err) = run() {
-        eprintln!("Error: {}", err);
-        process::exit(1);
+    match run() {
+        Ok(status) if status.is_no_matches() => process::exit(NO_MATCHES_EXIT_CODE),
+        Ok(_) => {}
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        }
     }
 }
 
-fn run() -> Result<(), MainError> {
+fn run() -> Result<ChangeStatus, MainError> {
     let options = Options::from_args();
 
     stderrlog::new().verbosity(options.verbose).init().unwrap();
 
-    let mut timelog = options.current_timelog()?;
-    let outputs = StdOutputs::default();
-    if options.command.execute(&mut timelog, outputs)?.is_changed() {
+    if let Command::External(args) = &options.command {
+        run_external(args);
+    }
+
+    if let Command::Doctor = options.command {
+        if !options.doctor(io::stdout()).map_err(CommandError::from)? {
+            process::exit(1);
+        }
+        return Ok(ChangeStatus::Unchanged);
+    }
+
+    let _lock = if options.command.is_read_only() {
+        options.lock_logfile_shared()?
+    } else {
+        options.lock_logfile()?
+    };
+
+    let mut outputs = StdOutputs {
+        quiet: options.quiet,
+        ..StdOutputs::default()
+    };
+
+    if let Command::Backup { path } = &options.command {
+        let backup = options.backup(path.as_deref())?;
+        writeln!(outputs.info_mut(), "Backed up log to {}", backup.display())
+            .map_err(CommandError::from)?;
+        return Ok(ChangeStatus::Unchanged);
+    }
+
+    let mut timelog = match options.command.read_only_filter() {
+        Some(info) => {
+            // The filter is built while the logfile is being read, once its tags are known; keep
+            // any error in building it to report once reading stops.
+            let mut filter_error = None;
+            let timelog = options.current_timelog_filtered(|timelog| {
+                info.filter(timelog, &options.settings)
+                    .unwrap_or_else(|err| {
+                        filter_error = Some(err);
+                        filter::filter_false()
+                    })
+            })?;
+            if let Some(err) = filter_error {
+                return Err(err.into());
+            }
+            timelog
+        }
+        None => options.current_timelog()?,
+    };
+    let status = options
+        .command
+        .execute(&mut timelog, outputs, &options.settings)?;
+    if status.is_changed() {
         options.write_timelog(&timelog)?;
     }
-    Ok(())
+    Ok(status)
+}
+
+/// Run the unrecognized subcommand `NAME ARGS...` as the external command `timelog-NAME ARGS...`,
+/// and exit with its exit status.
+fn run_external(args: &[String]) -> ! {
+    let (name, args) = args.split_first().expect("external subcommand has a name");
+
+    match process::Command::new(format!("timelog-{}", name))
+        .args(args)
+        .status()
+    {
+        Ok(status) => process::exit(status.code().unwrap_or(1)),
+        Err(err) => {
+            eprintln!(
+                "Error: unknown command '{}' (cannot run timelog-{}: {})",
+                name, name, err
+            );
+            process::exit(1);
+        }
+    }
 }
 
 #[derive(Debug)]
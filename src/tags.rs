@@ -18,12 +18,34 @@ pub type TagId = u32;
 
 /// A record of the interval tags in use by a timelog.
 ///
-/// Tag records are serialized as a simple array of tag names. The index of a name in the array is
-/// its ID.
+/// Each tag has a name, and optionally a color and a description.
+///
+/// Tag records are serialized as an array of objects with a `name` key and optional `color` and
+/// `description` keys. The index of a tag in the array is its ID. Older timelogs stored a simple
+/// array of tag names, which is still accepted when deserializing.
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub struct Tags {
     tag_ids: HashMap<String, TagId>,
-    tag_names: Vec<String>,
+    tag_entries: Vec<TagEntry>,
+}
+
+/// A tag name and its metadata.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+struct TagEntry {
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    color: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+}
+
+/// The serialized forms of a tag.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TagRepr {
+    /// A bare tag name, as stored by older timelogs
+    Name(String),
+    Entry(TagEntry),
 }
 
 impl Tags {
@@ -31,7 +53,7 @@ impl Tags {
     pub fn new() -> Tags {
         Tags {
             tag_ids: HashMap::new(),
-            tag_names: Vec::new(),
+            tag_entries: Vec::new(),
         }
     }
 
@@ -42,9 +64,12 @@ impl Tags {
         match self.tag_ids.entry(tag_name.into()) {
             Entry::Occupied(_) => Err(TagExists),
             Entry::Vacant(ent) => {
-                let id = self.tag_names.len() as TagId;
+                let id = self.tag_entries.len() as TagId;
                 ent.insert(id);
-                self.tag_names.push(tag_name.into());
+                self.tag_entries.push(TagEntry {
+                    name: tag_name.into(),
+                    ..TagEntry::default()
+                });
                 Ok(id)
             }
         }
@@ -55,9 +80,103 @@ impl Tags {
         self.tag_ids.get(tag_name).copied()
     }
 
+    /// Get the tag ID of the tag with the given name, ignoring case, if it exists.
+    ///
+    /// A tag whose name matches exactly is preferred. Otherwise, if several tags' names differ from
+    /// the given name only in case, the one created first is returned.
+    pub fn get_id_ignore_case(&self, tag_name: &str) -> Option<TagId> {
+        self.get_id(tag_name).or_else(|| {
+            let folded = tag_name.to_lowercase();
+            self.iter()
+                .find(|(_, name)| name.to_lowercase() == folded)
+                .map(|(id, _)| id)
+        })
+    }
+
     /// Get the name associated with the given tag ID, if it exists.
     pub fn get_name(&self, tag_id: TagId) -> Option<&str> {
-        self.tag_names.get(tag_id as usize).map(String::as_ref)
+        self.entry(tag_id).map(|entry| entry.name.as_ref())
+    }
+
+    /// Get the color of the tag with the given ID, if it exists and has one.
+    pub fn get_color(&self, tag_id: TagId) -> Option<&str> {
+        self.entry(tag_id).and_then(|entry| entry.color.as_deref())
+    }
+
+    /// Set or clear the color of the tag with the given ID.
+    ///
+    /// Colors are stored as given; their interpretation is left to the consumer.
+    ///
+    /// Returns an error if no tag has the given ID.
+    pub fn set_color(&mut self, tag_id: TagId, color: Option<&str>) -> Result<(), TagsError> {
+        self.entry_mut(tag_id)?.color = color.map(Into::into);
+        Ok(())
+    }
+
+    /// Get the description of the tag with the given ID, if it exists and has one.
+    pub fn get_description(&self, tag_id: TagId) -> Option<&str> {
+        self.entry(tag_id)
+            .and_then(|entry| entry.description.as_deref())
+    }
+
+    /// Set or clear the description of the tag with the given ID.
+    ///
+    /// Returns an error if no tag has the given ID.
+    pub fn set_description(
+        &mut self,
+        tag_id: TagId,
+        description: Option<&str>,
+    ) -> Result<(), TagsError> {
+        self.entry_mut(tag_id)?.description = description.map(Into::into);
+        Ok(())
+    }
+
+    /// An iterator over the IDs and names of all tags in this record, in ID order.
+    pub fn iter(&self) -> impl Iterator<Item = (TagId, &str)> {
+        self.tag_entries
+            .iter()
+            .enumerate()
+            .map(|(id, entry)| (id as TagId, entry.name.as_ref()))
+    }
+
+    /// The number of tags in this record.
+    pub fn len(&self) -> usize {
+        self.tag_entries.len()
+    }
+
+    /// Is this record empty?
+    pub fn is_empty(&self) -> bool {
+        self.tag_entries.is_empty()
+    }
+
+    fn entry(&self, tag_id: TagId) -> Option<&TagEntry> {
+        self.tag_entries.get(tag_id as usize)
+    }
+
+    fn entry_mut(&mut self, tag_id: TagId) -> Result<&mut TagEntry, TagsError> {
+        self.tag_entries.get_mut(tag_id as usize).ok_or(UnknownTag)
+    }
+
+    /// Change the name of the tag with the given ID.
+    ///
+    /// Returns an error if no tag has the given ID, or if a different tag already has the new
+    /// name. Renaming a tag to its current name does nothing.
+    pub fn rename(&mut self, tag_id: TagId, new_name: &str) -> Result<(), TagsError> {
+        let old_name = self.get_name(tag_id).ok_or(UnknownTag)?;
+        if old_name == new_name {
+            return Ok(());
+        }
+
+        match self.tag_ids.entry(new_name.into()) {
+            Entry::Occupied(_) => Err(TagExists),
+            Entry::Vacant(ent) => {
+                ent.insert(tag_id);
+                let old_name =
+                    std::mem::replace(&mut self.tag_entries[tag_id as usize].name, new_name.into());
+                self.tag_ids.remove(&old_name);
+                Ok(())
+            }
+        }
     }
 
     /// Insert the tag of the given name if it does not yet exist, and return its tag ID.
@@ -74,7 +193,7 @@ impl Serialize for Tags {
     where
         S: Serializer,
     {
-        self.tag_names.serialize(s)
+        self.tag_entries.serialize(s)
     }
 }
 
@@ -83,11 +202,20 @@ impl<'de> Deserialize<'de> for Tags {
     where
         D: Deserializer<'de>,
     {
-        let tag_names = Vec::<String>::deserialize(d)?;
+        let tag_entries: Vec<_> = Vec::<TagRepr>::deserialize(d)?
+            .into_iter()
+            .map(|repr| match repr {
+                TagRepr::Name(name) => TagEntry {
+                    name,
+                    ..TagEntry::default()
+                },
+                TagRepr::Entry(entry) => entry,
+            })
+            .collect();
         let mut tag_ids = HashMap::new();
 
-        for (id, name) in tag_names.iter().enumerate() {
-            match tag_ids.entry(name.into()) {
+        for (id, entry) in tag_entries.iter().enumerate() {
+            match tag_ids.entry(entry.name.clone()) {
                 Entry::Occupied(_) => return Err(D::Error::custom(TagExists)),
                 Entry::Vacant(ent) => {
                     ent.insert(id as TagId);
@@ -95,7 +223,10 @@ impl<'de> Deserialize<'de> for Tags {
             }
         }
 
-        Ok(Tags { tag_ids, tag_names })
+        Ok(Tags {
+            tag_ids,
+            tag_entries,
+        })
     }
 }
 
@@ -104,12 +235,16 @@ impl<'de> Deserialize<'de> for Tags {
 pub enum TagsError {
     /// Attempted to create a tag that already exists.
     TagExists,
+
+    /// Attempted to modify a tag that does not exist.
+    UnknownTag,
 }
 
 impl Display for TagsError {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self {
             TagExists => write!(f, "attempt to insert tag that already exists"),
+            UnknownTag => write!(f, "attempt to modify tag that does not exist"),
         }
     }
 }
@@ -0,0 +1,153 @@
+//! Templates for rendering intervals as text.
+
+use crate::interval::{self, Interval, TimeFormat};
+use crate::zone;
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use TemplateError::*;
+
+/// A template for rendering a tagged interval on a single line.
+///
+/// Templates are plain text with named placeholders in braces, e.g. `{tag}\t{duration}`. The
+/// available placeholders are:
+///
+/// - `{tag}`: the tag name
+/// - `{start}`: the start time
+/// - `{end}`: the end time, or `OPEN` if the interval is open
+/// - `{duration}`: the duration, as `H:MM`
+///
+/// Literal braces are written as `{{` and `}}`, and `\t`, `\n`, and `\\` are recognized as escape
+/// sequences for a tab, a newline, and a backslash.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Template {
+    pieces: Vec<Piece>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Piece {
+    Literal(String),
+    Field(Field),
+}
+
+/// Interval fields that may appear as template placeholders.
+#[derive(Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Hash)]
+enum Field {
+    Tag,
+    Start,
+    End,
+    Duration,
+}
+
+impl Template {
+    /// Render the given interval with this template.
+    pub fn render(&self, tag: &str, int: &Interval, time_format: &TimeFormat) -> String {
+        let mut out = String::new();
+
+        for piece in &self.pieces {
+            match piece {
+                Piece::Literal(s) => out.push_str(s),
+                Piece::Field(Field::Tag) => out.push_str(tag),
+                Piece::Field(Field::Start) => {
+                    let start = zone::current().from_utc(&int.start());
+                    out.push_str(&start.format(time_format.as_str()).to_string());
+                }
+                Piece::Field(Field::End) => match int.end() {
+                    Some(end) => {
+                        let end = zone::current().from_utc(&end);
+                        out.push_str(&end.format(time_format.as_str()).to_string());
+                    }
+                    None => out.push_str("OPEN"),
+                },
+                Piece::Field(Field::Duration) => {
+                    out.push_str(&interval::format_duration(int.duration()))
+                }
+            }
+        }
+
+        out
+    }
+}
+
+impl FromStr for Template {
+    type Err = TemplateError;
+
+    fn from_str(s: &str) -> Result<Template, TemplateError> {
+        let mut pieces = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest.find('}').ok_or(UnclosedPlaceholder)?;
+                    let field = match &rest[..end] {
+                        "tag" => Field::Tag,
+                        "start" => Field::Start,
+                        "end" => Field::End,
+                        "duration" => Field::Duration,
+                        name => return Err(UnknownPlaceholder(name.into())),
+                    };
+
+                    if !literal.is_empty() {
+                        pieces.push(Piece::Literal(literal.split_off(0)));
+                    }
+                    pieces.push(Piece::Field(field));
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => return Err(UnmatchedBrace),
+                '\\' => match chars.next() {
+                    Some('t') => literal.push('\t'),
+                    Some('n') => literal.push('\n'),
+                    Some('\\') => literal.push('\\'),
+                    Some(c) => {
+                        literal.push('\\');
+                        literal.push(c);
+                    }
+                    None => literal.push('\\'),
+                },
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            pieces.push(Piece::Literal(literal));
+        }
+
+        Ok(Template { pieces })
+    }
+}
+
+/// Template parsing errors.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TemplateError {
+    /// The template contains a placeholder with an unrecognized name.
+    UnknownPlaceholder(String),
+    /// The template contains a `{` with no matching `}`.
+    UnclosedPlaceholder,
+    /// The template contains a `}` with no matching `{`.
+    UnmatchedBrace,
+}
+
+impl Display for TemplateError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            UnknownPlaceholder(name) => write!(f, "unknown template placeholder '{{{}}}'", name),
+            UnclosedPlaceholder => write!(f, "unclosed '{{' in template"),
+            UnmatchedBrace => write!(f, "unmatched '}}' in template"),
+        }
+    }
+}
+
+impl Error for TemplateError {}
@@ -1,43 +1,212 @@
 //! Timelogs; records of tagged time intervals.
 
-use crate::filter;
+use crate::clock::{Clock, SystemClock};
+use crate::filter::{self, Filter};
 use crate::interval::{self, Interval, TaggedInterval};
-use crate::tags::{TagId, Tags};
+use crate::tags::{TagId, Tags, TagsError};
 
-use chrono::Utc;
-use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Duration, Utc};
+use serde::de::{self, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
 
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::error::Error;
 use std::fmt::{self, Display, Formatter};
+use std::io::Read;
+use std::sync::OnceLock;
 
 use TimeLogError::*;
 
 /// A record of tagged time intervals.
-#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct TimeLog {
     tags: Tags,
     intervals: Vec<TaggedInterval>,
+
+    /// Names of tags declared with `declare_tag`, which are kept even when no interval uses them.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    declared: BTreeSet<String>,
+
+    /// Name of the tag used when no tag is given to `open`, `close`, or `continue`, if one has
+    /// been set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    default_tag: Option<String>,
+
+    /// Index of intervals by start time, built on first use and discarded whenever the intervals
+    /// are modified.
+    #[serde(skip)]
+    index: OnceLock<StartIndex>,
+}
+
+impl PartialEq for TimeLog {
+    fn eq(&self, other: &TimeLog) -> bool {
+        self.tags == other.tags
+            && self.intervals == other.intervals
+            && self.declared == other.declared
+            && self.default_tag == other.default_tag
+    }
 }
 
+impl Eq for TimeLog {}
+
 impl TimeLog {
     /// Create a new, empty timelog.
     pub fn new() -> TimeLog {
         TimeLog {
             tags: Tags::new(),
             intervals: Vec::new(),
+            declared: BTreeSet::new(),
+            default_tag: None,
+            index: OnceLock::new(),
         }
     }
 
+    /// Create a timelog from a list of tag names and a list of intervals.
+    ///
+    /// The tag IDs of the intervals are indices into `tags`. Returns an error if a tag name appears
+    /// more than once, or if an interval refers to a tag ID with no name.
+    pub fn from_intervals(
+        tags: Vec<String>,
+        intervals: Vec<TaggedInterval>,
+    ) -> Result<TimeLog, TagsError> {
+        let mut timelog = TimeLog::new();
+        for name in &tags {
+            timelog.tags.insert(name)?;
+        }
+
+        if intervals
+            .iter()
+            .flat_map(|int| int.tags())
+            .any(|tag| tag as usize >= tags.len())
+        {
+            return Err(TagsError::UnknownTag);
+        }
+
+        timelog.intervals = intervals;
+        Ok(timelog)
+    }
+
+    /// Deserialize a timelog from JSON, keeping only the intervals that pass a filter.
+    ///
+    /// The filter is constructed by `make_filter` from a timelog containing only the deserialized
+    /// tags. Intervals are deserialized one at a time and discarded immediately if they do not
+    /// pass the filter, so the full list of intervals is never held in memory. Tags must precede
+    /// intervals in the input, as they do in timelogs serialized by this crate.
+    pub fn from_reader_filtered<R, F>(reader: R, make_filter: F) -> serde_json::Result<TimeLog>
+    where
+        R: Read,
+        F: FnOnce(&TimeLog) -> Filter,
+    {
+        let mut deserializer = serde_json::Deserializer::from_reader(reader);
+        let timelog = FilteredTimeLog(make_filter).deserialize(&mut deserializer)?;
+        deserializer.end()?;
+        Ok(timelog)
+    }
+
     /// Get the name of the tag with the given ID, if it exists in this timelog.
     pub fn tag_name(&self, tag: TagId) -> Option<&str> {
         self.tags.get_name(tag)
     }
 
+    /// An iterator over the IDs and names of all tags in this timelog, in ID order.
+    pub fn tags(&self) -> impl Iterator<Item = (TagId, &str)> {
+        self.tags.iter()
+    }
+
+    /// An iterator over the names of all tags in this timelog, in ID order.
+    ///
+    /// This includes tags that no interval uses, such as declared tags.
+    pub fn tag_names(&self) -> impl Iterator<Item = &str> {
+        self.tags.iter().map(|(_, name)| name)
+    }
+
+    /// The number of tags in this timelog, including those that no interval uses.
+    pub fn tag_count(&self) -> usize {
+        self.tags.len()
+    }
+
     /// Get the ID of the tag with the given name, if it exists in this timelog.
     pub fn tag_id(&self, tag: &str) -> Option<TagId> {
         self.tags.get_id(tag)
     }
 
+    /// Get the ID of the tag with the given name, ignoring case, if it exists in this timelog.
+    ///
+    /// See `Tags::get_id_ignore_case`.
+    pub fn tag_id_ignore_case(&self, tag: &str) -> Option<TagId> {
+        self.tags.get_id_ignore_case(tag)
+    }
+
+    /// Get the color of the tag with the given ID, if it exists and has one.
+    pub fn tag_color(&self, tag: TagId) -> Option<&str> {
+        self.tags.get_color(tag)
+    }
+
+    /// Set or clear the color of the tag with the given ID.
+    ///
+    /// See `Tags::set_color`.
+    pub fn set_tag_color(&mut self, tag: TagId, color: Option<&str>) -> Result<(), TagsError> {
+        self.tags.set_color(tag, color)
+    }
+
+    /// Get the description of the tag with the given ID, if it exists and has one.
+    pub fn tag_description(&self, tag: TagId) -> Option<&str> {
+        self.tags.get_description(tag)
+    }
+
+    /// Set or clear the description of the tag with the given ID.
+    pub fn set_tag_description(
+        &mut self,
+        tag: TagId,
+        description: Option<&str>,
+    ) -> Result<(), TagsError> {
+        self.tags.set_description(tag, description)
+    }
+
+    /// An iterator over the names of tags that are not used by any interval in this timelog.
+    ///
+    /// Declared tags are never considered unused.
+    pub fn unused_tag_names(&self) -> impl Iterator<Item = &str> {
+        let used: HashSet<_> = self.iter().flat_map(|int| int.tags()).collect();
+        self.tags
+            .iter()
+            .filter(move |(id, name)| !used.contains(id) && !self.declared.contains(*name))
+            .map(|(_, name)| name)
+    }
+
+    /// Declare a tag, creating it if it does not yet exist.
+    ///
+    /// Declared tags are kept by `gc_tag_names` even if no interval uses them. The declarations
+    /// are stored in the timelog, under a `declared` key listing the declared tag names.
+    ///
+    /// Returns `false` if the tag was already declared.
+    pub fn declare_tag(&mut self, name: &str) -> bool {
+        self.tags.get_id_or_insert(name);
+        self.declared.insert(name.into())
+    }
+
+    /// The name of the default tag, if one has been set with `set_default_tag`.
+    pub fn default_tag(&self) -> Option<&str> {
+        self.default_tag.as_deref()
+    }
+
+    /// Set the default tag, or clear it if `name` is `None`.
+    ///
+    /// The default tag is declared with `declare_tag`, so it is created if it does not yet exist.
+    /// It is stored in the timelog under a `default_tag` key, which is omitted when no default tag
+    /// is set. Returns the previous default tag.
+    pub fn set_default_tag(&mut self, name: Option<&str>) -> Option<String> {
+        if let Some(name) = name {
+            self.declare_tag(name);
+        }
+        std::mem::replace(&mut self.default_tag, name.map(Into::into))
+    }
+
+    /// An iterator over the names of the declared tags, in sorted order.
+    pub fn declared_tag_names(&self) -> impl Iterator<Item = &str> {
+        self.declared.iter().map(String::as_ref)
+    }
+
     /// An iterator over the intervals stored in this timelog.
     pub fn iter(&self) -> impl Iterator<Item = &TaggedInterval> {
         self.intervals.iter()
@@ -45,7 +214,128 @@ impl TimeLog {
 
     /// An iterator over mutable references to the intervals stored in this timelog.
     pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut TaggedInterval> {
-        self.intervals.iter_mut()
+        self.intervals_mut().iter_mut()
+    }
+
+    /// An iterator over the intervals in this timelog that pass the given filter.
+    pub fn query<'a, 'f>(
+        &'a self,
+        filter: &'f Filter,
+    ) -> impl Iterator<Item = &'a TaggedInterval> + use<'a, 'f> {
+        self.iter().filter(filter.build_ref())
+    }
+
+    /// An iterator over mutable references to the intervals in this timelog that pass the given
+    /// filter.
+    pub fn query_mut<'a, 'f>(
+        &'a mut self,
+        filter: &'f Filter,
+    ) -> impl Iterator<Item = &'a mut TaggedInterval> + use<'a, 'f> {
+        self.iter_mut().filter(filter.build_mut())
+    }
+
+    /// An iterator over the intervals that may overlap the time range from `start` to `end`.
+    ///
+    /// This yields every interval that started no later than `end` and either is open or ended
+    /// after `start`, in the same order as `iter`. It may also yield some intervals outside the
+    /// range, so callers should still apply their own filter. A bound of `None` leaves that side
+    /// of the range unrestricted.
+    ///
+    /// For large timelogs this avoids visiting most intervals outside the range. The index it uses
+    /// is built on first use and kept until the timelog is next modified.
+    pub fn iter_overlapping(
+        &self,
+        start: Option<DateTime<Utc>>,
+        end: Option<DateTime<Utc>>,
+    ) -> impl Iterator<Item = &TaggedInterval> {
+        let index = self.index.get_or_init(|| StartIndex::new(&self.intervals));
+        let start_of = |i: &usize| self.intervals[*i].start();
+
+        let earliest = start.map(|start| {
+            let earliest = start - index.longest;
+            index.earliest_open.map_or(earliest, |t| t.min(earliest))
+        });
+        let hi = end.map_or(index.order.len(), |end| {
+            index.order.partition_point(|i| start_of(i) <= end)
+        });
+        let lo = earliest.map_or(0, |earliest| {
+            index.order.partition_point(|i| start_of(i) < earliest)
+        });
+
+        // The candidates are those starting between `earliest` and `end`. Rather than sorting
+        // their indices back into storage order, scan the span of storage they occupy; for a
+        // timelog kept in start order, that span holds only the candidates.
+        let candidates = &index.order[lo..hi.max(lo)];
+        let first = candidates.iter().min().copied().unwrap_or(0);
+        let last = candidates.iter().max().map_or(0, |&i| i + 1);
+        self.intervals[first..last].iter().filter(move |int| {
+            earliest.is_none_or(|earliest| int.start() >= earliest)
+                && end.is_none_or(|end| int.start() <= end)
+        })
+    }
+
+    /// Mutable access to the intervals, discarding the start time index.
+    fn intervals_mut(&mut self) -> &mut Vec<TaggedInterval> {
+        self.index.take();
+        &mut self.intervals
+    }
+
+    /// An iterator over the intervals in this timelog with the given tag ID.
+    pub fn intervals_for_tag(&self, tag: TagId) -> impl Iterator<Item = &TaggedInterval> {
+        self.iter().filter(move |int| int.tag() == tag)
+    }
+
+    /// An iterator over the intervals in this timelog with the given tag name.
+    ///
+    /// The iterator is empty if no tag with this name exists.
+    pub fn intervals_for_tag_name(&self, name: &str) -> impl Iterator<Item = &TaggedInterval> {
+        let tag = self.tag_id(name);
+        self.iter().filter(move |int| Some(int.tag()) == tag)
+    }
+
+    /// The total duration of the intervals with each tag.
+    ///
+    /// Intervals with extra tags count toward each of their tags. Open intervals count the time
+    /// elapsed since their start, as with `TaggedInterval::duration_clamped`. Tags with no
+    /// intervals are absent from the map.
+    pub fn tag_totals(&self) -> HashMap<TagId, Duration> {
+        self.tag_totals_filtered(&filter::filter_true())
+    }
+
+    /// The total duration of the intervals with each tag, counting only intervals that pass the
+    /// given filter.
+    pub fn tag_totals_filtered(&self, filter: &Filter) -> HashMap<TagId, Duration> {
+        self.query(filter).fold(HashMap::new(), |mut totals, int| {
+            for tag in int.tags() {
+                let total = totals.entry(tag).or_insert_with(Duration::zero);
+                *total = *total + int.duration_clamped();
+            }
+            totals
+        })
+    }
+
+    /// The total duration of the intervals that pass the given filter.
+    ///
+    /// Each interval counts once regardless of how many tags it has. Open intervals count the
+    /// time elapsed since their start, as with `TaggedInterval::duration_clamped`.
+    pub fn aggregate(&self, filter: &Filter) -> Duration {
+        self.aggregate_within(filter, None, None)
+    }
+
+    /// The total duration of the intervals that pass the given filter, counting only the part of
+    /// each interval that lies between `start` and `end`.
+    ///
+    /// This clips each interval as with `Interval::duration_within`; a bound of `None` leaves that
+    /// side unclipped. With no bounds, this is the same as `aggregate`.
+    pub fn aggregate_within(
+        &self,
+        filter: &Filter,
+        start: Option<DateTime<Utc>>,
+        end: Option<DateTime<Utc>>,
+    ) -> Duration {
+        self.query(filter).fold(Duration::zero(), |total, int| {
+            total + int.interval().duration_within(start, end)
+        })
     }
 
     /// Remove all intervals from this timelog that satisfy the given predicate.
@@ -61,81 +351,804 @@ impl TimeLog {
     where
         F: FnMut(&TaggedInterval) -> bool,
     {
-        self.intervals = self.iter().cloned().filter(filter).collect();
+        self.intervals_mut().retain(filter);
+    }
+
+    /// Rename the tag with the name `from` to `to`, keeping its tag ID and intervals.
+    ///
+    /// Fails with `TagsError::UnknownTag` if there is no tag named `from`, or with
+    /// `TagsError::TagExists` if a different tag is already named `to`; renaming never merges two
+    /// tags.
+    pub fn rename_tag(&mut self, from: &str, to: &str) -> Result<TagId, TagsError> {
+        let tag = self.tag_id(from).ok_or(TagsError::UnknownTag)?;
+        self.tags.rename(tag, to)?;
+        if self.declared.remove(from) {
+            self.declared.insert(to.into());
+        }
+        if self.default_tag.as_deref() == Some(from) {
+            self.default_tag = Some(to.into());
+        }
+        Ok(tag)
     }
 
     /// Garbage collect tag names.
     ///
-    /// This removes all tag names that are not in use by any interval in the timelog, shifting the
-    /// remaining tag IDs as necessary.
-    pub fn gc_tag_names(&mut self) {
+    /// This removes all tag names that are neither in use by any interval in the timelog nor
+    /// declared, shifting the remaining tag IDs as necessary. The order of intervals is preserved,
+    /// but tag IDs are reassigned in order of first use, followed by the unused declared tags in
+    /// sorted order, so any `TagId`s obtained before calling this are invalidated.
+    ///
+    /// Returns a map from each old tag ID still in use or declared to its new tag ID.
+    pub fn gc_tag_names(&mut self) -> BTreeMap<TagId, TagId> {
         let mut new_log = TimeLog::new();
+        let mut remapping = BTreeMap::new();
         for int in self.intervals.iter() {
-            let tag = self.tags.get_name(int.tag()).unwrap();
+            let mut new_int = int.clone();
+            new_int.remap_tags(|old| {
+                let name = self.tags.get_name(old).unwrap();
+                let new = new_log.tags.get_id_or_insert(name);
+                remapping.insert(old, new);
+                new
+            });
+            new_log.intervals.push(new_int);
+        }
+
+        for name in &self.declared {
+            if let Some(old) = self.tags.get_id(name) {
+                remapping.insert(old, new_log.tags.get_id_or_insert(name));
+            }
+        }
 
-            new_log.insert_unchecked(tag, *int.interval());
+        for (&old, &new) in &remapping {
+            new_log
+                .set_tag_color(new, self.tags.get_color(old))
+                .unwrap();
+            new_log
+                .set_tag_description(new, self.tags.get_description(old))
+                .unwrap();
         }
 
         self.tags = new_log.tags;
-        self.intervals = new_log.intervals;
+        *self.intervals_mut() = new_log.intervals;
+        remapping
     }
 
-    /// Insert an interval with the given tag name into this timelog, without checking for
-    /// overlapping intervals.
-    fn insert_unchecked(&mut self, tag: &str, int: Interval) -> TaggedInterval {
-        let tag = self.tags.get_id_or_insert(tag);
-        let int = TaggedInterval::new(tag, int);
-        self.intervals.push(int);
-        *self.intervals.last().unwrap()
+    /// Merge the intervals of another timelog into this one.
+    ///
+    /// Tags are matched by name, and tags that do not yet exist in this timelog are created. Tag
+    /// colors and descriptions are taken from the other timelog where this one has none.
+    /// Intervals already present in this timelog (with the same tag and times) are skipped.
+    ///
+    /// Returns a summary of the intervals and tags that were added.
+    pub fn merge(&mut self, other: TimeLog) -> MergeSummary {
+        let mut summary = MergeSummary::default();
+
+        for mut int in other.intervals {
+            int.remap_tags(|other_tag| {
+                let name = other.tags.get_name(other_tag).unwrap();
+                match self.tags.get_id(name) {
+                    Some(tag) => tag,
+                    None => {
+                        summary.tags_added += 1;
+                        self.tags.get_id_or_insert(name)
+                    }
+                }
+            });
+
+            if !self.intervals.contains(&int) {
+                self.intervals_mut().push(int);
+                summary.intervals_added += 1;
+            }
+        }
+
+        for (other_tag, name) in other.tags.iter() {
+            if let Some(tag) = self.tags.get_id(name) {
+                if self.tags.get_color(tag).is_none() {
+                    self.set_tag_color(tag, other.tags.get_color(other_tag))
+                        .unwrap();
+                }
+                if self.tags.get_description(tag).is_none() {
+                    self.set_tag_description(tag, other.tags.get_description(other_tag))
+                        .unwrap();
+                }
+            }
+        }
+
+        for name in other.declared {
+            if self.tags.get_id(&name).is_none() {
+                summary.tags_added += 1;
+            }
+            self.declare_tag(&name);
+        }
+
+        summary
+    }
+
+    /// Compare this timelog to a newer version of it.
+    ///
+    /// Intervals are compared by their tag names (primary tag first, joined by `+`), start time,
+    /// and end time, since tag IDs may differ between the two timelogs; identical intervals are
+    /// counted rather than merged, so duplicates are not lost. Intervals only in `newer` are
+    /// reported as added and those only in this timelog as removed, except that a removed and an
+    /// added interval with the same tags and start time are reported together as changed. Each
+    /// list is in order of start time.
+    pub fn diff(&self, newer: &TimeLog) -> TimeLogDiff {
+        type Key = (DateTime<Utc>, String, Interval);
+        let counted = |log: &TimeLog| -> BTreeMap<Key, usize> {
+            let mut counts = BTreeMap::new();
+            for int in log.iter() {
+                let names: Vec<_> = int
+                    .tags()
+                    .map(|tag| log.tag_name(tag).unwrap_or_default())
+                    .collect();
+                let key = (int.start(), names.join("+"), *int.interval());
+                *counts.entry(key).or_insert(0) += 1;
+            }
+            counts
+        };
+        let old = counted(self);
+        let mut new = counted(newer);
+
+        let mut removed = Vec::new();
+        for (key, count) in old {
+            let kept = new.get_mut(&key).map_or(0, |new_count| {
+                let kept = count.min(*new_count);
+                *new_count -= kept;
+                kept
+            });
+            removed.extend(std::iter::repeat_n(key, count - kept));
+        }
+        let mut added: Vec<_> = new
+            .into_iter()
+            .flat_map(|(key, count)| std::iter::repeat_n(key, count))
+            .collect();
+
+        let mut diff = TimeLogDiff::default();
+        for (start, name, old_int) in removed {
+            match added.iter().position(|(s, n, _)| *s == start && *n == name) {
+                Some(i) => {
+                    let (_, _, new_int) = added.remove(i);
+                    diff.changed.push((name, old_int, new_int));
+                }
+                None => diff.removed.push((name, old_int)),
+            }
+        }
+        diff.added = added
+            .into_iter()
+            .map(|(_, name, int)| (name, int))
+            .collect();
+
+        diff
+    }
+
+    /// Change the tag of a single interval.
+    ///
+    /// The interval changed is the one tagged `from` that contains the time `at`, or the most
+    /// recently started interval tagged `from` if `at` is not given. The tag `to` is created if it
+    /// does not yet exist.
+    ///
+    /// Returns the retagged interval.
+    ///
+    /// Returns an error if there is no such interval.
+    pub fn retag(
+        &mut self,
+        from: &str,
+        to: &str,
+        at: Option<DateTime<Utc>>,
+    ) -> Result<TaggedInterval, TimeLogError> {
+        let from = self.tags.get_id(from).ok_or(NoMatchingInterval)?;
+        let candidates = self
+            .intervals
+            .iter()
+            .enumerate()
+            .filter(|(_, int)| int.tag() == from && at.is_none_or(|at| int.contains(at)));
+
+        let index = candidates
+            .max_by_key(|(_, int)| int.start())
+            .map(|(index, _)| index)
+            .ok_or(NoMatchingInterval)?;
+
+        let to = self.tags.get_id_or_insert(to);
+        let int = &mut self.intervals_mut()[index];
+        int.set_tag(to);
+        Ok(int.clone())
+    }
+
+    /// Add an extra tag to an interval of this timelog.
+    ///
+    /// The extra tag is created if it does not yet exist. Returns the changed interval, or an error
+    /// if the given interval is not in this timelog.
+    pub fn add_extra_tag(
+        &mut self,
+        int: &TaggedInterval,
+        extra: &str,
+    ) -> Result<TaggedInterval, TimeLogError> {
+        let index = self
+            .intervals
+            .iter()
+            .position(|other| other == int)
+            .ok_or(NoMatchingInterval)?;
+
+        let extra = self.tags.get_id_or_insert(extra);
+        let int = &mut self.intervals_mut()[index];
+        int.add_tag(extra);
+        Ok(int.clone())
+    }
+
+    /// The intervals of this timelog with intervals of the same tags merged across short gaps.
+    ///
+    /// Two intervals are merged if they have the same primary and extra tags, and the later one
+    /// starts no more than `gap` after the earlier one ends; overlapping intervals are always
+    /// merged. A merged interval runs from the earliest start to the latest end, and is open if
+    /// any of the intervals merged into it is open. The result keeps the order of the timelog, a
+    /// merged interval taking the place of the first of its intervals in that order, and the
+    /// timelog itself is not changed.
+    pub fn coalesced(&self, gap: Duration) -> Vec<TaggedInterval> {
+        let mut by_start: Vec<_> = self.intervals.iter().enumerate().collect();
+        by_start.sort_by(|(_, a), (_, b)| TaggedInterval::by_start(a, b));
+
+        // Each coalesced interval, with the position in the timelog of its first interval.
+        let mut coalesced: Vec<(usize, TaggedInterval)> = Vec::new();
+        let mut latest: HashMap<(TagId, Vec<TagId>), usize> = HashMap::new();
+        for (position, int) in by_start {
+            let mut extra_tags = int.extra_tags().to_vec();
+            extra_tags.sort_unstable();
+            let key = (int.tag(), extra_tags);
+
+            if let Some(&index) = latest.get(&key) {
+                let (first, prev) = &mut coalesced[index];
+                if let Some(merged) = merge_within(prev.interval(), int.interval(), gap) {
+                    *prev.interval_mut() = merged;
+                    *first = (*first).min(position);
+                    continue;
+                }
+            }
+
+            latest.insert(key, coalesced.len());
+            coalesced.push((position, int.clone()));
+        }
+
+        coalesced.sort_by_key(|&(position, _)| position);
+        coalesced.into_iter().map(|(_, int)| int).collect()
+    }
+
+    /// Replace the intervals of this timelog with those returned by `coalesced`.
+    ///
+    /// Returns the number of intervals removed by merging.
+    pub fn coalesce(&mut self, gap: Duration) -> usize {
+        let coalesced = self.coalesced(gap);
+        let removed = self.intervals.len() - coalesced.len();
+        if removed > 0 {
+            *self.intervals_mut() = coalesced;
+        }
+        removed
+    }
+
+    /// Round every closed interval of this timelog to quarter hours, as when it was closed.
+    ///
+    /// Open intervals are left unchanged. Returns the number of intervals changed. See
+    /// `Interval::round_to_quarter_hours`.
+    pub fn round_closed_intervals(&mut self) -> usize {
+        let needs_rounding =
+            |int: &TaggedInterval| int.is_closed() && int.round_to_quarter_hours() != *int;
+        if !self.intervals.iter().any(needs_rounding) {
+            return 0;
+        }
+
+        let mut changed = 0;
+        for int in self
+            .intervals_mut()
+            .iter_mut()
+            .filter(|int| needs_rounding(int))
+        {
+            *int = int.round_to_quarter_hours();
+            changed += 1;
+        }
+        changed
+    }
+
+    /// Check this timelog for structural problems.
+    ///
+    /// Returns the list of problems found, which is empty if the timelog is well-formed. Intervals
+    /// are identified by their index in iteration order.
+    pub fn validate(&self) -> Vec<ValidationIssue> {
+        let mut issues = Vec::new();
+        let mut by_tag: BTreeMap<TagId, Vec<(usize, &TaggedInterval)>> = BTreeMap::new();
+
+        for (index, int) in self.intervals.iter().enumerate() {
+            for tag in int.tags() {
+                if self.tags.get_name(tag).is_none() {
+                    issues.push(ValidationIssue::UnknownTag { index, tag });
+                }
+            }
+
+            by_tag.entry(int.tag()).or_default().push((index, int));
+        }
+
+        for (&tag, ints) in by_tag.iter_mut() {
+            let open = ints
+                .iter()
+                .filter(|(_, int)| !int.is_closed() && !int.is_concurrent())
+                .count();
+            if open > 1 {
+                issues.push(ValidationIssue::MultipleOpen { tag, count: open });
+            }
+
+            // Sweep the intervals in start order, keeping those that are still running at each
+            // start, so that an interval is checked against every earlier one it overlaps rather
+            // than only its predecessor.
+            ints.sort_by_key(|(_, int)| int.start());
+            let mut running: Vec<(usize, &TaggedInterval)> = Vec::new();
+            for &(second, b) in ints.iter() {
+                running.retain(|(_, a)| a.end().is_none_or(|end| end > b.start()));
+                for &(first, a) in &running {
+                    if !a.is_concurrent() && !b.is_concurrent() {
+                        issues.push(ValidationIssue::Overlap { tag, first, second });
+                    }
+                }
+                running.push((second, b));
+            }
+        }
+
+        issues
     }
 
     /// Open a new interval with the given tag at the current time.
     ///
-    /// If a closed interval with this tag exists and has an end time that is not before the
-    /// current time, this will re-open that interval rather than creating a new one.
+    /// The start time is rounded down to the nearest quarter hour. If `reopen` is true and a closed
+    /// interval with this tag has an end time that is not before this rounded start time, that
+    /// interval is re-opened rather than creating a new one. If `reopen` is false, a new interval
+    /// is always created, starting no earlier than the end of any such interval.
     ///
     /// Returns the newly opened interval.
     ///
-    /// Returns an error if an interval with this tag is already open.
-    pub fn open(&mut self, tag: &str) -> Result<TaggedInterval, TimeLogError> {
+    /// Returns an error if an interval with this tag is already open, or if `reopen` is false and
+    /// an interval with this tag ends after the current time, as it may when its end was rounded
+    /// up; a new interval would otherwise start in the future.
+    pub fn open(&mut self, tag: &str, reopen: bool) -> Result<TaggedInterval, TimeLogError> {
+        self.open_with(tag, reopen, &SystemClock)
+    }
+
+    /// Open a new interval with the given tag at the current time according to the given clock.
+    ///
+    /// See `open` for details.
+    pub fn open_with<C>(
+        &mut self,
+        tag: &str,
+        reopen: bool,
+        clock: &C,
+    ) -> Result<TaggedInterval, TimeLogError>
+    where
+        C: Clock,
+    {
         let tag = self.tags.get_id_or_insert(tag);
-        let now_floor = interval::floor_time(&Utc::now());
-        let filter = filter::has_tag(tag) & (filter::is_open() | filter::ended_after(now_floor));
+        let now_floor = interval::floor_time(&clock.now());
+        let filter =
+            filter::has_primary_tag(tag) & (filter::is_open() | filter::ended_after(now_floor));
 
-        let int = self.iter_mut().find(filter.build_mut());
-        if let Some(int) = int {
+        let mut start = now_floor;
+        for int in self.query_mut(&filter) {
             if !int.is_closed() {
-                Err(TagAlreadyOpen)
+                return Err(TagAlreadyOpen);
+            } else if reopen {
+                *int.interval_mut() = Interval::open(int.start());
+                return Ok(int.clone());
             } else {
-                *int = TaggedInterval::open(int.tag(), int.start());
-                Ok(*int)
+                start = start.max(int.end().unwrap());
             }
-        } else {
-            let new_int = TaggedInterval::open(tag, now_floor);
-            self.intervals.push(new_int);
-            Ok(*self.intervals.last().unwrap())
         }
+        if start > clock.now() {
+            return Err(EndsInFuture);
+        }
+
+        let new_int = TaggedInterval::open(tag, start);
+        self.intervals_mut().push(new_int);
+        Ok(self.intervals.last().unwrap().clone())
+    }
+
+    /// Open a new interval with the given tag at the current time, even if intervals with this
+    /// tag are already open.
+    ///
+    /// The start time is rounded down to the nearest quarter hour. Recently closed intervals are
+    /// never reopened. This permits several concurrently open intervals with the same tag; see
+    /// `close_nth` for closing them individually. The new interval is marked as concurrent, so
+    /// `validate` does not report its overlap with the others.
+    ///
+    /// Returns the newly opened interval.
+    pub fn open_another(&mut self, tag: &str) -> TaggedInterval {
+        self.open_another_with(tag, &SystemClock)
+    }
+
+    /// Open another interval with the given tag at the current time according to the given
+    /// clock.
+    ///
+    /// See `open_another` for details.
+    pub fn open_another_with<C>(&mut self, tag: &str, clock: &C) -> TaggedInterval
+    where
+        C: Clock,
+    {
+        let tag = self.tags.get_id_or_insert(tag);
+        let mut new_int = TaggedInterval::open(tag, interval::floor_time(&clock.now()));
+        new_int.set_concurrent(true);
+        self.intervals_mut().push(new_int.clone());
+        new_int
+    }
+
+    /// Reopen the most recently closed interval with the given tag, if it ended no earlier than
+    /// `within` before the current time.
+    ///
+    /// Returns the reopened interval, or `None` if there is no such interval.
+    ///
+    /// Returns an error if an interval with this tag is already open.
+    pub fn resume(
+        &mut self,
+        tag: &str,
+        within: Duration,
+    ) -> Result<Option<TaggedInterval>, TimeLogError> {
+        self.resume_with(tag, within, &SystemClock)
+    }
+
+    /// Reopen the most recently closed interval with the given tag, using the given clock for the
+    /// current time.
+    ///
+    /// See `resume` for details.
+    pub fn resume_with<C>(
+        &mut self,
+        tag: &str,
+        within: Duration,
+        clock: &C,
+    ) -> Result<Option<TaggedInterval>, TimeLogError>
+    where
+        C: Clock,
+    {
+        let tag = match self.tags.get_id(tag) {
+            Some(tag) => tag,
+            None => return Ok(None),
+        };
+
+        if self
+            .iter()
+            .any((filter::has_primary_tag(tag) & filter::is_open()).build())
+        {
+            return Err(TagAlreadyOpen);
+        }
+
+        let filter = filter::has_primary_tag(tag) & filter::ended_after(clock.now() - within);
+        let int = self.query_mut(&filter).max_by_key(|int| int.end());
+
+        Ok(int.map(|int| {
+            *int.interval_mut() = Interval::open(int.start());
+            int.clone()
+        }))
     }
 
     /// Close an open interval with the given tag.
     ///
+    /// If more than one interval with this tag is open, the one that started earliest is closed.
+    ///
     /// Returns the newly closed interval.
     ///
     /// Returns an error if no interval with this tag is open.
     pub fn close(&mut self, tag: &str) -> Result<TaggedInterval, TimeLogError> {
+        self.close_with(tag, &SystemClock)
+    }
+
+    /// Close an open interval with the given tag at the current time according to the given
+    /// clock.
+    ///
+    /// See `close` for details.
+    pub fn close_with<C>(&mut self, tag: &str, clock: &C) -> Result<TaggedInterval, TimeLogError>
+    where
+        C: Clock,
+    {
+        self.close_nth_with(tag, 0, clock)
+    }
+
+    /// Close the `n`th open interval with the given tag.
+    ///
+    /// Open intervals with the same tag are numbered from 0 in order of their start times, so the
+    /// interval that started earliest is number 0.
+    ///
+    /// Returns the newly closed interval.
+    ///
+    /// Returns an error if fewer than `n + 1` intervals with this tag are open.
+    pub fn close_nth(&mut self, tag: &str, n: usize) -> Result<TaggedInterval, TimeLogError> {
+        self.close_nth_with(tag, n, &SystemClock)
+    }
+
+    /// Close the `n`th open interval with the given tag at the current time according to the
+    /// given clock.
+    ///
+    /// See `close_nth` for details.
+    pub fn close_nth_with<C>(
+        &mut self,
+        tag: &str,
+        n: usize,
+        clock: &C,
+    ) -> Result<TaggedInterval, TimeLogError>
+    where
+        C: Clock,
+    {
+        self.close_nth_at_least_with(tag, n, Duration::zero(), clock)
+    }
+
+    /// Close the `n`th open interval with the given tag, unless it would be shorter than `min`.
+    ///
+    /// The duration is checked after rounding to quarter hours. If it is shorter than `min`, the
+    /// interval is left open and `TimeLogError::TooShort` is returned. Otherwise this is the same
+    /// as `close_nth`.
+    pub fn close_nth_at_least(
+        &mut self,
+        tag: &str,
+        n: usize,
+        min: Duration,
+    ) -> Result<TaggedInterval, TimeLogError> {
+        self.close_nth_at_least_with(tag, n, min, &SystemClock)
+    }
+
+    /// Close the `n`th open interval with the given tag at the current time according to the
+    /// given clock, unless it would be shorter than `min`.
+    ///
+    /// See `close_nth_at_least` for details.
+    pub fn close_nth_at_least_with<C>(
+        &mut self,
+        tag: &str,
+        n: usize,
+        min: Duration,
+        clock: &C,
+    ) -> Result<TaggedInterval, TimeLogError>
+    where
+        C: Clock,
+    {
+        let int = self.nth_open(tag, n)?;
+        let closed = int
+            .close(clock.now())
+            .ok_or(StartsInFuture)?
+            .round_to_quarter_hours();
+        if closed.duration() < min {
+            return Err(TooShort);
+        }
+
+        *int = closed.clone();
+        Ok(closed)
+    }
+
+    /// Remove the `n`th open interval with the given tag, numbered as for `close_nth`.
+    ///
+    /// Returns the removed interval, or an error if fewer than `n + 1` intervals with this tag
+    /// are open.
+    pub fn discard_nth(&mut self, tag: &str, n: usize) -> Result<TaggedInterval, TimeLogError> {
+        let int = self.nth_open(tag, n)?.clone();
+        let index = self
+            .intervals
+            .iter()
+            .position(|other| *other == int)
+            .unwrap();
+        self.intervals_mut().remove(index);
+        Ok(int)
+    }
+
+    /// The `n`th open interval with the given tag, in order of start time.
+    fn nth_open(&mut self, tag: &str, n: usize) -> Result<&mut TaggedInterval, TimeLogError> {
         let tag = self.tags.get_id(tag).ok_or(TagNotOpen)?;
-        let filter = filter::has_tag(tag) & filter::is_open();
+        let filter = filter::has_primary_tag(tag) & filter::is_open();
 
-        if let Some(int) = self.iter_mut().find(filter.build_mut()) {
-            *int = int.close_now().unwrap();
-            *int = int.round_to_quarter_hours();
-            Ok(*int)
-        } else {
-            Err(TagNotOpen)
+        let mut open: Vec<_> = self.query_mut(&filter).collect();
+        open.sort_by_key(|int| int.start());
+        open.into_iter().nth(n).ok_or(TagNotOpen)
+    }
+}
+
+/// Deserializer for a timelog that filters intervals as they are read.
+struct FilteredTimeLog<F>(F);
+
+impl<'de, F> DeserializeSeed<'de> for FilteredTimeLog<F>
+where
+    F: FnOnce(&TimeLog) -> Filter,
+{
+    type Value = TimeLog;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<TimeLog, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, F> Visitor<'de> for FilteredTimeLog<F>
+where
+    F: FnOnce(&TimeLog) -> Filter,
+{
+    type Value = TimeLog;
+
+    fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "a timelog")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<TimeLog, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut timelog = TimeLog::new();
+        let mut make_filter = Some(self.0);
+
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "tags" => timelog.tags = map.next_value()?,
+                "declared" => timelog.declared = map.next_value()?,
+                "default_tag" => timelog.default_tag = map.next_value()?,
+                "intervals" => {
+                    let make_filter = make_filter
+                        .take()
+                        .ok_or_else(|| de::Error::duplicate_field("intervals"))?;
+                    let filter = make_filter(&timelog);
+                    timelog.intervals = map.next_value_seed(FilteredIntervals(&filter))?;
+                }
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
         }
+
+        Ok(timelog)
+    }
+}
+
+/// Deserializer for a list of intervals that discards those not passing a filter.
+struct FilteredIntervals<'f>(&'f Filter);
+
+impl<'de, 'f> DeserializeSeed<'de> for FilteredIntervals<'f> {
+    type Value = Vec<TaggedInterval>;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Vec<TaggedInterval>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(self)
     }
 }
 
+impl<'de, 'f> Visitor<'de> for FilteredIntervals<'f> {
+    type Value = Vec<TaggedInterval>;
+
+    fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "a list of intervals")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Vec<TaggedInterval>, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let filter = self.0.compile();
+        let mut intervals = Vec::new();
+        while let Some(int) = seq.next_element::<TaggedInterval>()? {
+            if filter.eval(&int) {
+                intervals.push(int);
+            }
+        }
+
+        Ok(intervals)
+    }
+}
+
+/// How far the given interval starts after `now`, if it is by more than `threshold`.
+///
+/// Intervals are opened at the current time, rounded down, so an interval that starts well after
+/// the current time suggests that the system clock has moved backward since it was opened or
+/// since a neighboring interval was closed.
+pub fn start_skew(
+    int: &TaggedInterval,
+    now: DateTime<Utc>,
+    threshold: Duration,
+) -> Option<Duration> {
+    let ahead = int.start() - now;
+    if ahead > threshold {
+        Some(ahead)
+    } else {
+        None
+    }
+}
+
+/// Merge `next` into `prev` if it starts no more than `gap` after `prev` ends.
+///
+/// `next` must not start before `prev`.
+fn merge_within(prev: &Interval, next: &Interval, gap: Duration) -> Option<Interval> {
+    let prev_end = match prev.end() {
+        Some(end) => end,
+        None => return Some(*prev),
+    };
+    if next.start() > prev_end + gap {
+        return None;
+    }
+
+    let merged = Interval::open(prev.start());
+    match next.end() {
+        Some(end) => merged.close(end.max(prev_end)),
+        None => Some(merged),
+    }
+}
+
+/// Index of a timelog's intervals by start time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct StartIndex {
+    /// Indices of the intervals, ordered by start time.
+    order: Vec<usize>,
+    /// The duration of the longest closed interval.
+    longest: Duration,
+    /// The start time of the earliest open interval, if any.
+    earliest_open: Option<DateTime<Utc>>,
+}
+
+impl StartIndex {
+    fn new(intervals: &[TaggedInterval]) -> StartIndex {
+        let mut order: Vec<_> = (0..intervals.len()).collect();
+        order.sort_by_key(|&i| intervals[i].start());
+
+        let longest = intervals
+            .iter()
+            .filter(|int| int.is_closed())
+            .map(|int| int.duration())
+            .max()
+            .unwrap_or_else(Duration::zero);
+        let earliest_open = intervals
+            .iter()
+            .filter(|int| !int.is_closed())
+            .map(|int| int.start())
+            .min();
+
+        StartIndex {
+            order,
+            longest,
+            earliest_open,
+        }
+    }
+}
+
+/// Summary of the changes made by `TimeLog::merge`.
+#[derive(Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Hash, Default)]
+pub struct MergeSummary {
+    /// The number of intervals added to the timelog.
+    pub intervals_added: usize,
+    /// The number of tags added to the timelog.
+    pub tags_added: usize,
+}
+
+/// Differences between two timelogs, as reported by `TimeLog::diff`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct TimeLogDiff {
+    /// Intervals only in the newer timelog, with their tag names.
+    pub added: Vec<(String, Interval)>,
+    /// Intervals only in the older timelog, with their tag names.
+    pub removed: Vec<(String, Interval)>,
+    /// Intervals in both timelogs with different end times, with their tag names and their old
+    /// and new versions.
+    pub changed: Vec<(String, Interval, Interval)>,
+}
+
+impl TimeLogDiff {
+    /// Are the two timelogs free of differences?
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Structural problems in a timelog, as reported by `TimeLog::validate`.
+#[derive(Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Hash)]
+pub enum ValidationIssue {
+    /// The interval at `index` has a tag ID with no associated name.
+    UnknownTag { index: usize, tag: TagId },
+    /// More than one interval with this tag is open, not counting concurrent intervals.
+    MultipleOpen { tag: TagId, count: usize },
+    /// The intervals at `first` and `second` have the same tag and overlap in time, and neither
+    /// is a concurrent interval.
+    Overlap {
+        tag: TagId,
+        first: usize,
+        second: usize,
+    },
+}
+
 /// Errors in opening and closing intervals.
 #[derive(Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Hash)]
 pub enum TimeLogError {
@@ -143,6 +1156,16 @@ pub enum TimeLogError {
     TagAlreadyOpen,
     /// Attempted to close a tag that has no open interval.
     TagNotOpen,
+    /// No interval matched the given criteria.
+    NoMatchingInterval,
+    /// Attempted to close an interval that would be shorter than the minimum duration.
+    TooShort,
+    /// Attempted to close an interval that starts after the current time, as happens when the
+    /// system clock moves backward while the interval is open.
+    StartsInFuture,
+    /// Attempted to open a new interval after one with the same tag that ends after the current
+    /// time, so that the new interval would start in the future.
+    EndsInFuture,
 }
 
 impl Display for TimeLogError {
@@ -151,6 +1174,21 @@ impl Display for TimeLogError {
             TagAlreadyOpen => write!(f, "attempt to open a tag that is already open"),
 
             TagNotOpen => write!(f, "attempt to close a tag that is not open"),
+
+            NoMatchingInterval => write!(f, "no interval matches the given criteria"),
+
+            TooShort => write!(f, "interval would be shorter than the minimum duration"),
+
+            StartsInFuture => write!(
+                f,
+                "interval starts after the current time; has the system clock moved backward?"
+            ),
+
+            EndsInFuture => write!(
+                f,
+                "the last interval with this tag ends after the current time; reopen it instead, \
+                 or wait until it ends"
+            ),
         }
     }
 }
@@ -0,0 +1,143 @@
+//! The time zone in which local times are displayed and interpreted.
+
+use chrono::offset::{LocalResult, Offset};
+use chrono::{DateTime, Duration, FixedOffset, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+use std::env;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// The environment variable naming a time zone to use instead of the system's local time zone.
+pub const TZ_VAR: &str = "TIMELOG_TZ";
+
+static CURRENT: OnceLock<Zone> = OnceLock::new();
+
+/// A time zone in which to display and interpret local times.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Zone {
+    /// The system's local time zone.
+    Local,
+    /// A named IANA time zone, such as `America/New_York`.
+    Named(Tz),
+}
+
+impl Zone {
+    /// Convert a UTC time to this zone.
+    pub fn from_utc(&self, time: &DateTime<Utc>) -> DateTime<FixedOffset> {
+        match self {
+            Zone::Local => fixed(Local.from_utc_datetime(&time.naive_utc())),
+            Zone::Named(tz) => fixed(tz.from_utc_datetime(&time.naive_utc())),
+        }
+    }
+
+    /// The current time in this zone.
+    pub fn now(&self) -> DateTime<FixedOffset> {
+        self.from_utc(&Utc::now())
+    }
+
+    /// The current date in this zone.
+    pub fn today(&self) -> NaiveDate {
+        self.now().naive_local().date()
+    }
+
+    /// Interpret a date and time as a local time in this zone.
+    ///
+    /// The result may be ambiguous or nonexistent around daylight saving time transitions.
+    pub fn from_local(&self, local: &NaiveDateTime) -> LocalResult<DateTime<FixedOffset>> {
+        match self {
+            Zone::Local => Local.from_local_datetime(local).map(fixed),
+            Zone::Named(tz) => tz.from_local_datetime(local).map(fixed),
+        }
+    }
+
+    /// Interpret a date and time as a single instant in this zone.
+    ///
+    /// A local time that occurs twice, when clocks are set back, resolves to its earlier
+    /// occurrence. A local time that is skipped, when clocks are set forward, is interpreted with
+    /// the offset in effect before the change, so it resolves to the instant as long after the
+    /// change as the time is after the start of the skipped range. In particular, a skipped
+    /// midnight resolves to the instant the clocks were set forward.
+    pub fn resolve_local(&self, local: &NaiveDateTime) -> DateTime<Utc> {
+        match self.from_local(local) {
+            LocalResult::Single(time) | LocalResult::Ambiguous(time, _) => time.with_timezone(&Utc),
+            LocalResult::None => {
+                // Offsets change at most a few times a year, so the offset a day earlier is the
+                // one in effect before the change.
+                let day_before = Utc.from_utc_datetime(&(*local - Duration::days(1)));
+                let offset = self.from_utc(&day_before).offset().fix();
+                Utc.from_utc_datetime(&(*local - offset))
+            }
+        }
+    }
+}
+
+impl Display for Zone {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Zone::Local => write!(f, "local"),
+            Zone::Named(tz) => write!(f, "{}", tz.name()),
+        }
+    }
+}
+
+impl FromStr for Zone {
+    type Err = ZoneError;
+
+    /// Parse an IANA time zone name, or `local` for the system's local time zone.
+    fn from_str(s: &str) -> Result<Zone, ZoneError> {
+        if s.eq_ignore_ascii_case("local") {
+            Ok(Zone::Local)
+        } else {
+            s.parse().map(Zone::Named).map_err(|_| ZoneError(s.into()))
+        }
+    }
+}
+
+/// Error returned when parsing an unknown time zone name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ZoneError(String);
+
+impl Display for ZoneError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "unknown time zone '{}'", self.0)
+    }
+}
+
+impl Error for ZoneError {}
+
+/// The zone in which local times are displayed and interpreted.
+///
+/// This is the zone given to `set_current` if it has been called, or otherwise the zone named by
+/// the `TIMELOG_TZ` environment variable if it is set, or otherwise the system's local time zone.
+pub fn current() -> Zone {
+    *CURRENT.get_or_init(from_env)
+}
+
+/// Set the zone returned by `current`.
+///
+/// This must be called before `current` is first used. Returns `false`, leaving the zone
+/// unchanged, if the zone has already been set or used.
+pub fn set_current(zone: Zone) -> bool {
+    CURRENT.set(zone).is_ok()
+}
+
+fn from_env() -> Zone {
+    match env::var(TZ_VAR) {
+        Ok(name) => name.parse().unwrap_or_else(|err| {
+            log::warn!("{}; using the local time zone", err);
+            Zone::Local
+        }),
+        Err(_) => Zone::Local,
+    }
+}
+
+fn fixed<Tz>(time: DateTime<Tz>) -> DateTime<FixedOffset>
+where
+    Tz: TimeZone,
+{
+    let offset = time.offset().fix();
+    time.with_timezone(&offset)
+}
@@ -0,0 +1,426 @@
+use timelog::commands::{ChangeStatus, CommandError};
+use timelog::run_command;
+use timelog::timelog::TimeLog;
+use timelog::zone;
+
+#[test]
+fn open_close_list_sequence() {
+    let mut log = TimeLog::new();
+
+    let (status, output) = run_command(&mut log, &["open", "--create", "work"]).unwrap();
+    assert_eq!(status, ChangeStatus::Changed);
+    assert_eq!(output, "");
+
+    let (status, output) = run_command(&mut log, &["list", "--open"]).unwrap();
+    assert_eq!(status, ChangeStatus::Unchanged);
+    assert!(
+        output.starts_with("work | ") && output.contains("-- OPEN"),
+        "{}",
+        output
+    );
+
+    let (status, _) = run_command(&mut log, &["close", "work"]).unwrap();
+    assert_eq!(status, ChangeStatus::Changed);
+
+    let (status, _) = run_command(&mut log, &["list", "--open"]).unwrap();
+    assert_eq!(status, ChangeStatus::NoMatches);
+
+    let (_, output) = run_command(&mut log, &["list", "--closed", "--count-only"]).unwrap();
+    assert_eq!(output, "1\n");
+}
+
+#[test]
+fn closing_unopened_tag_fails() {
+    let mut log = TimeLog::new();
+
+    match run_command(&mut log, &["close", "work"]) {
+        Err(CommandError::TimeLogError(_)) => {}
+        other => panic!("{:?}", other),
+    }
+    assert_eq!(log, TimeLog::new());
+}
+
+#[test]
+fn invalid_arguments_are_errors() {
+    let mut log = TimeLog::new();
+
+    match run_command(&mut log, &["list", "--after", "not a time"]) {
+        Err(CommandError::InvalidArguments(message)) => {
+            assert!(message.contains("--after"), "{}", message)
+        }
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn status_include_today_lists_closed_intervals() {
+    let mut log = TimeLog::new();
+    run_command(&mut log, &["open", "--create", "work"]).unwrap();
+    run_command(&mut log, &["close", "work"]).unwrap();
+    run_command(&mut log, &["open", "--create", "home"]).unwrap();
+
+    let (_, output) = run_command(&mut log, &["status"]).unwrap();
+    assert_eq!(output.lines().count(), 1);
+    assert!(output.starts_with("home | "), "{}", output);
+
+    let (_, output) = run_command(&mut log, &["status", "--include-today"]).unwrap();
+    let lines: Vec<_> = output.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("home | "), "{}", output);
+    assert!(lines[1].starts_with("work | "), "{}", output);
+
+    let (_, output) = run_command(&mut log, &["status", "--include-today", "home"]).unwrap();
+    assert_eq!(output.lines().count(), 1);
+}
+
+#[test]
+fn add_tag_allows_open_without_create() {
+    let mut log = TimeLog::new();
+
+    let (status, _) = run_command(&mut log, &["add-tag", "work", "home"]).unwrap();
+    assert_eq!(status, ChangeStatus::Changed);
+
+    let (status, _) = run_command(&mut log, &["open", "home"]).unwrap();
+    assert_eq!(status, ChangeStatus::Changed);
+
+    let (_, output) = run_command(&mut log, &["tags"]).unwrap();
+    assert_eq!(output, "home\nwork\n");
+
+    let (status, _) = run_command(&mut log, &["tags", "--gc"]).unwrap();
+    assert_eq!(status, ChangeStatus::Unchanged);
+}
+
+#[test]
+fn aggregate_round_to_rounds_total_up() {
+    let mut log: TimeLog = serde_json::from_str(
+        r#"{
+            "tags": ["work"],
+            "intervals": [
+                {"tag": 0, "interval": {"start": "2020-03-02T09:00:00Z", "duration": "1:00"}},
+                {"tag": 0, "interval": {"start": "2020-03-02T11:00:00Z", "duration": "0:15"}}
+            ]
+        }"#,
+    )
+    .unwrap();
+
+    let (_, output) = run_command(&mut log, &["aggregate"]).unwrap();
+    assert_eq!(output.lines().last(), Some("Total 1:15"));
+
+    let (_, output) = run_command(&mut log, &["aggregate", "--round-to", "15"]).unwrap();
+    assert_eq!(output.lines().last(), Some("Total 1:15"));
+
+    let (_, output) = run_command(&mut log, &["aggregate", "--round-to", "30"]).unwrap();
+    assert_eq!(output.lines().last(), Some("Total 1:30"));
+
+    assert!(run_command(&mut log, &["aggregate", "--round-to", "0"]).is_err());
+}
+
+#[test]
+fn aggregate_reports_no_matches() {
+    let mut log: TimeLog = serde_json::from_str(
+        r#"{
+            "tags": ["work", "home"],
+            "intervals": [
+                {"tag": 0, "interval": {"start": "2020-03-02T09:00:00Z", "duration": "1:00"}}
+            ]
+        }"#,
+    )
+    .unwrap();
+
+    let (status, output) = run_command(&mut log, &["aggregate", "home"]).unwrap();
+    assert_eq!(status, ChangeStatus::NoMatches);
+    assert!(!output.contains("Total"), "{}", output);
+
+    let (status, output) = run_command(&mut log, &["aggregate", "work"]).unwrap();
+    assert_eq!(status, ChangeStatus::Unchanged);
+    assert_eq!(output.lines().last(), Some("Total 1:00"));
+}
+
+#[test]
+fn retag_exact_requires_full_date_and_time() {
+    let mut log = TimeLog::new();
+    run_command(&mut log, &["open", "--create", "work"]).unwrap();
+    run_command(&mut log, &["add-tag", "home"]).unwrap();
+
+    let start = log.iter().next().unwrap().start();
+    let at = zone::current()
+        .from_utc(&start)
+        .format("%Y-%m-%d,%H:%M")
+        .to_string();
+    let time = zone::current().from_utc(&start).format("%H:%M").to_string();
+
+    match run_command(
+        &mut log,
+        &["retag", "work", "home", "--exact", "--at", &time],
+    ) {
+        Err(CommandError::InexactTime(s)) => assert_eq!(s, time),
+        other => panic!("expected InexactTime, got {:?}", other),
+    }
+
+    let (status, _) =
+        run_command(&mut log, &["retag", "work", "home", "--exact", "--at", &at]).unwrap();
+    assert_eq!(status, ChangeStatus::Changed);
+    assert_eq!(log.intervals_for_tag_name("home").count(), 1);
+}
+
+#[test]
+fn open_also_counts_toward_extra_tags() {
+    let mut log: TimeLog = serde_json::from_str(
+        r#"{
+            "tags": ["work", "meeting"],
+            "intervals": [
+                {"tag": 0, "interval": {"start": "2020-03-02T09:00:00Z", "duration": "1:00"},
+                 "extra_tags": [1]},
+                {"tag": 0, "interval": {"start": "2020-03-02T11:00:00Z", "duration": "0:30"}}
+            ]
+        }"#,
+    )
+    .unwrap();
+
+    let (_, output) = run_command(&mut log, &["list", "meeting"]).unwrap();
+    assert_eq!(output.lines().count(), 1);
+    assert!(output.starts_with("work+meeting | "), "{}", output);
+
+    let (_, output) = run_command(&mut log, &["aggregate"]).unwrap();
+    assert_eq!(output.lines().last(), Some("Total 1:30"));
+    let (_, output) = run_command(&mut log, &["aggregate", "--per-tag"]).unwrap();
+    assert_eq!(output.lines().last(), Some("Total 2:30"));
+    let (_, output) = run_command(&mut log, &["aggregate", "--per-tag", "work"]).unwrap();
+    assert_eq!(output.lines().last(), Some("Total 1:30"));
+
+    run_command(&mut log, &["open", "--create", "home", "--also", "errands"]).unwrap();
+    let (_, output) = run_command(&mut log, &["list", "--open", "errands"]).unwrap();
+    assert!(output.starts_with("home+errands | "), "{}", output);
+}
+
+#[test]
+fn tags_json_reports_tag_statistics() {
+    let mut log: TimeLog = serde_json::from_str(
+        r#"{
+            "tags": ["work", "meeting", "stale"],
+            "intervals": [
+                {"tag": 0, "interval": {"start": "2020-03-02T09:00:00Z", "duration": "1:00"},
+                 "extra_tags": [1]},
+                {"tag": 0, "interval": {"start": "2020-03-03T09:00:00Z", "duration": "0:30"}}
+            ]
+        }"#,
+    )
+    .unwrap();
+    run_command(&mut log, &["add-tag", "later"]).unwrap();
+
+    let (_, output) = run_command(&mut log, &["tags", "--json"]).unwrap();
+    let tags: serde_json::Value = serde_json::from_str(&output).unwrap();
+
+    assert_eq!(
+        tags,
+        serde_json::json!([
+            {"id": 3, "name": "later", "interval_count": 0, "total_minutes": 0,
+             "last_active": null},
+            {"id": 1, "name": "meeting", "interval_count": 1, "total_minutes": 60,
+             "last_active": "2020-03-02T10:00:00Z"},
+            {"id": 0, "name": "work", "interval_count": 2, "total_minutes": 90,
+             "last_active": "2020-03-03T09:30:00Z"},
+        ])
+    );
+}
+
+#[test]
+fn status_since_midnight_splits_carried_over_time() {
+    let mut log: TimeLog = serde_json::from_str(
+        r#"{
+            "tags": ["work"],
+            "intervals": [
+                {"tag": 0, "interval": {"start": "2020-03-02T09:00:00Z", "duration": null}}
+            ]
+        }"#,
+    )
+    .unwrap();
+    run_command(&mut log, &["open", "--create", "home"]).unwrap();
+
+    let (_, output) = run_command(&mut log, &["status"]).unwrap();
+    assert_eq!(output.lines().count(), 2);
+
+    let (_, output) = run_command(&mut log, &["status", "--since-midnight"]).unwrap();
+    let lines: Vec<_> = output.lines().collect();
+    assert_eq!(lines.len(), 3, "{}", output);
+    assert!(lines[1].starts_with("     | "), "{}", output);
+    assert!(lines[1].ends_with(" of it today"), "{}", output);
+    assert!(lines[2].starts_with("home | "), "{}", output);
+}
+
+#[test]
+fn set_default_changes_tag_for_bare_commands() {
+    let mut log = TimeLog::new();
+    let (_, output) = run_command(&mut log, &["default"]).unwrap();
+    assert_eq!(output, "default\n");
+
+    let (status, _) = run_command(&mut log, &["set-default", "work"]).unwrap();
+    assert_eq!(status, ChangeStatus::Changed);
+    let (_, output) = run_command(&mut log, &["default"]).unwrap();
+    assert_eq!(output, "work\n");
+    assert_eq!(
+        serde_json::to_value(&log).unwrap()["default_tag"],
+        serde_json::json!("work")
+    );
+
+    run_command(&mut log, &["open"]).unwrap();
+    assert_eq!(log.intervals_for_tag_name("work").count(), 1);
+    run_command(&mut log, &["close"]).unwrap();
+    assert!(log.iter().all(|int| int.is_closed()));
+
+    let (status, _) = run_command(&mut log, &["set-default", "--clear"]).unwrap();
+    assert_eq!(status, ChangeStatus::Changed);
+    assert_eq!(log.default_tag(), None);
+    assert!(serde_json::to_value(&log)
+        .unwrap()
+        .get("default_tag")
+        .is_none());
+    assert!(run_command(&mut log, &["set-default"]).is_err());
+}
+
+#[test]
+fn inverted_time_ranges_are_rejected() {
+    let mut log = TimeLog::new();
+    run_command(&mut log, &["open", "--create", "work"]).unwrap();
+
+    match run_command(
+        &mut log,
+        &["list", "--after", "2020-03-02", "--before", "2020-03-01"],
+    ) {
+        Err(CommandError::InvertedRange(start, end)) => assert!(start > end),
+        other => panic!("{:?}", other),
+    }
+
+    assert!(run_command(&mut log, &["list", "--after", "2020-03-02"]).is_ok());
+    assert!(run_command(&mut log, &["list", "--before", "2020-03-01"]).is_ok());
+    assert!(run_command(
+        &mut log,
+        &["list", "--after", "2020-03-01", "--before", "2020-03-01"]
+    )
+    .is_ok());
+}
+
+#[test]
+fn gaps_between_selected_tags_respect_min_gap() {
+    let mut log: TimeLog = serde_json::from_str(
+        r#"{
+            "tags": ["client", "home"],
+            "intervals": [
+                {"tag": 0, "interval": {"start": "2020-03-02T11:45:00Z", "duration": "0:15"}},
+                {"tag": 0, "interval": {"start": "2020-03-02T09:00:00Z", "duration": "1:00"}},
+                {"tag": 1, "interval": {"start": "2020-03-02T10:00:00Z", "duration": "0:30"}},
+                {"tag": 0, "interval": {"start": "2020-03-02T11:00:00Z", "duration": "0:15"}}
+            ]
+        }"#,
+    )
+    .unwrap();
+    let suffixes = |output: &str| -> Vec<String> {
+        output
+            .lines()
+            .map(|line| line[line.find(" (").unwrap()..].to_string())
+            .collect()
+    };
+
+    let (_, output) = run_command(&mut log, &["gaps"]).unwrap();
+    assert_eq!(
+        suffixes(&output),
+        vec![
+            " (0:30) | after home, before client",
+            " (0:30) | after client, before client",
+        ]
+    );
+
+    let (_, output) = run_command(&mut log, &["gaps", "client"]).unwrap();
+    assert_eq!(
+        suffixes(&output),
+        vec![
+            " (1:00) | after client, before client",
+            " (0:30) | after client, before client",
+        ]
+    );
+
+    let (_, output) = run_command(&mut log, &["gaps", "client", "--min-gap", "0:45"]).unwrap();
+    assert_eq!(output.lines().count(), 1);
+
+    let (status, _) = run_command(&mut log, &["gaps", "home"]).unwrap();
+    assert_eq!(status, ChangeStatus::NoMatches);
+}
+
+#[test]
+fn stats_reports_summary_figures() {
+    let mut log: TimeLog = serde_json::from_str(
+        r#"{
+            "tags": ["work", "home"],
+            "intervals": [
+                {"tag": 0, "interval": {"start": "2020-03-02T11:00:00Z", "duration": "1:00"}},
+                {"tag": 0, "interval": {"start": "2020-03-02T12:00:00Z", "duration": "0:30"}},
+                {"tag": 0, "interval": {"start": "2020-03-03T11:00:00Z", "duration": "2:00"}},
+                {"tag": 0, "interval": {"start": "2020-03-05T11:00:00Z", "duration": "0:30"}},
+                {"tag": 1, "interval": {"start": "2020-03-05T12:00:00Z", "duration": "5:00"}}
+            ]
+        }"#,
+    )
+    .unwrap();
+
+    let (status, output) = run_command(&mut log, &["stats", "work"]).unwrap();
+    assert_eq!(status, ChangeStatus::Unchanged);
+    assert_eq!(
+        output,
+        "Intervals:                4\n\
+         Total:                    4:00\n\
+         Mean:                     1:00\n\
+         Median:                   0:45\n\
+         Longest:                  2:00\n\
+         Shortest:                 0:30\n\
+         Intervals per active day: 1.33\n"
+    );
+
+    let (status, output) = run_command(&mut log, &["stats", "nothing"]).unwrap();
+    assert_eq!(status, ChangeStatus::NoMatches);
+    assert_eq!(output, "");
+}
+
+#[test]
+fn exclude_tag_removes_intervals_with_that_tag_anywhere() {
+    let mut log: TimeLog = serde_json::from_str(
+        r#"{
+            "tags": ["work", "meeting", "home"],
+            "intervals": [
+                {"tag": 0, "interval": {"start": "2020-03-02T09:00:00Z", "duration": "1:00"}},
+                {"tag": 0, "interval": {"start": "2020-03-02T10:00:00Z", "duration": "0:30"},
+                 "extra_tags": [1]},
+                {"tag": 1, "interval": {"start": "2020-03-02T11:00:00Z", "duration": "0:15"}},
+                {"tag": 2, "interval": {"start": "2020-03-02T12:00:00Z", "duration": "2:00"}}
+            ]
+        }"#,
+    )
+    .unwrap();
+
+    let (_, output) = run_command(&mut log, &["list", "--exclude-tag", "meeting"]).unwrap();
+    let tags: Vec<_> = output
+        .lines()
+        .map(|line| line.split(" | ").next().unwrap().trim())
+        .collect();
+    assert_eq!(tags, vec!["work", "home"]);
+
+    let (_, output) = run_command(
+        &mut log,
+        &[
+            "aggregate",
+            "--exclude-tag",
+            "meeting",
+            "--exclude-tag",
+            "home",
+        ],
+    )
+    .unwrap();
+    assert_eq!(output.lines().last(), Some("Total 1:00"));
+
+    let (_, output) = run_command(&mut log, &["list", "--exclude-tag", "work", "meeting"]).unwrap();
+    assert!(output.starts_with("meeting | "), "{}", output);
+    assert_eq!(output.lines().count(), 1);
+
+    let (status, _) =
+        run_command(&mut log, &["list", "--exclude-tag", "meeting", "meeting"]).unwrap();
+    assert_eq!(status, ChangeStatus::NoMatches);
+}
@@ -0,0 +1,802 @@
+use timelog::commands::{self, ChangeStatus, Command, DurationSpec, Outputs};
+use timelog::config::Settings;
+use timelog::interval::{Interval, TimeFormat};
+use timelog::template::{Template, TemplateError};
+use timelog::timelog::TimeLog;
+use timelog::zone;
+
+use chrono::{Datelike, Duration, NaiveTime, TimeZone, Utc, Weekday};
+use structopt::StructOpt;
+
+use std::env;
+use std::fs;
+use std::process;
+
+fn command(args: &[&str]) -> Command {
+    Command::from_iter_safe(std::iter::once("timelog").chain(args.iter().cloned())).unwrap()
+}
+
+#[test]
+fn run_to_string_captures_output_streams() {
+    let mut log = TimeLog::new();
+
+    let (status, output, error) = command(&["open", "--create", "work"])
+        .run_to_string(&mut log)
+        .unwrap();
+    assert_eq!(status, ChangeStatus::Changed);
+    assert_eq!(output, "");
+    assert!(error.starts_with("Opened new interval for tag 'work'"));
+
+    let (status, output, _) = command(&["tags"]).run_to_string(&mut log).unwrap();
+    assert_eq!(status, ChangeStatus::Unchanged);
+    assert_eq!(output, "work\n");
+}
+
+#[test]
+fn after_accepts_weekday_names() {
+    let today = zone::current().now().date();
+
+    for name in ["monday", "Wed", "SUNDAY"].iter() {
+        let cmd = command(&["list", "--after", name]);
+        let (after, _) = cmd.read_only_filter().unwrap().bounds(&Settings::default());
+        let after = zone::current().from_utc(&after.unwrap());
+
+        assert_eq!(after.time(), NaiveTime::from_hms(0, 0, 0));
+        assert_eq!(Some(after.weekday()), name.parse::<Weekday>().ok());
+        assert!(after.date() <= today && today - after.date() < Duration::weeks(1));
+    }
+}
+
+#[test]
+fn unknown_subcommands_are_external() {
+    match command(&["chart", "--since", "monday"]) {
+        Command::External(args) => assert_eq!(args, vec!["chart", "--since", "monday"]),
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn list_markdown_table() {
+    let mut log: TimeLog = serde_json::from_str(
+        r#"{
+            "tags": ["work", "a|b", "*big*_deal_"],
+            "intervals": [
+                {"tag": 0, "interval": {"start": "2020-03-02T09:00:00Z", "duration": "1:30"}},
+                {"tag": 1, "interval": {"start": "2020-03-02T11:00:00Z", "duration": "0:15"}},
+                {"tag": 2, "interval": {"start": "2020-03-02T12:00:00Z", "duration": "0:45"}}
+            ]
+        }"#,
+    )
+    .unwrap();
+
+    let (_, output, _) = command(&["list", "--format", "markdown"])
+        .run_to_string(&mut log)
+        .unwrap();
+    let lines: Vec<_> = output.lines().collect();
+
+    assert_eq!(lines.len(), 5);
+    assert_eq!(lines[0], "| tag | start | end | duration |");
+    assert_eq!(lines[1], "| --- | --- | --- | ---: |");
+    assert!(lines[2].starts_with("| work | ") && lines[2].ends_with(" | 1:30 |"));
+    assert!(lines[3].starts_with("| a\\|b | ") && lines[3].ends_with(" | 0:15 |"));
+    assert!(
+        lines[4].starts_with(r"| \*big\*\_deal\_ | ") && lines[4].ends_with(" | 0:45 |"),
+        "{}",
+        lines[4]
+    );
+}
+
+#[test]
+fn list_json_lines() {
+    let mut log: TimeLog = serde_json::from_str(
+        r#"{
+            "tags": ["work"],
+            "intervals": [
+                {"tag": 0, "interval": {"start": "2020-03-02T09:00:00Z", "duration": "1:30"}},
+                {"tag": 0, "interval": {"start": "2020-03-02T11:00:00Z", "duration": null}}
+            ]
+        }"#,
+    )
+    .unwrap();
+
+    let (_, output, _) = command(&["list", "--format", "jsonl"])
+        .run_to_string(&mut log)
+        .unwrap();
+    let lines: Vec<serde_json::Value> = output
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    assert_eq!(lines.len(), 2);
+    assert_eq!(
+        lines[0],
+        serde_json::json!({
+            "tag": "work",
+            "start": "2020-03-02T09:00:00Z",
+            "end": "2020-03-02T10:30:00Z",
+            "duration": 5400,
+        })
+    );
+    assert_eq!(lines[1]["start"], "2020-03-02T11:00:00Z");
+    assert!(lines[1]["end"].is_null());
+}
+
+#[test]
+fn tag_case_insensitive_reuses_existing_tag() {
+    let mut log = TimeLog::new();
+    let settings = Settings {
+        tag_case_insensitive: true,
+        ..Settings::default()
+    };
+    let run = |log: &mut TimeLog, args: &[&str]| {
+        let (mut output, mut error) = (Vec::new(), Vec::new());
+        command(args)
+            .execute(log, Outputs::new(&mut output, Some(&mut error)), &settings)
+            .unwrap();
+        String::from_utf8(output).unwrap()
+    };
+
+    run(&mut log, &["open", "--create", "work"]);
+    run(&mut log, &["close", "WORK"]);
+    run(&mut log, &["open", "--strict", "Work"]);
+
+    assert_eq!(run(&mut log, &["tags"]), "work\n");
+    assert_eq!(log.intervals_for_tag_name("work").count(), 2);
+    assert_eq!(
+        run(&mut log, &["status", "--porcelain", "wORK"])
+            .lines()
+            .count(),
+        1
+    );
+    assert_eq!(
+        serde_json::to_value(&log).unwrap()["tags"],
+        serde_json::json!([{"name": "work"}])
+    );
+}
+
+#[test]
+fn first_shows_earliest_interval_per_tag() {
+    let mut log: TimeLog = serde_json::from_str(
+        r#"{
+            "tags": ["work", "home", "client"],
+            "intervals": [
+                {"tag": 0, "extra_tags": [2],
+                 "interval": {"start": "2020-03-03T09:00:00Z", "duration": "1:00"}},
+                {"tag": 1, "interval": {"start": "2020-03-02T18:00:00Z", "duration": "0:45"}},
+                {"tag": 0, "interval": {"start": "2020-03-02T09:00:00Z", "duration": "2:00"}}
+            ]
+        }"#,
+    )
+    .unwrap();
+
+    let (_, output, _) = command(&["first"]).run_to_string(&mut log).unwrap();
+    let lines: Vec<_> = output.lines().collect();
+    assert_eq!(lines.len(), 1);
+    assert!(lines[0].starts_with("work | ") && lines[0].ends_with("(2:00)"));
+
+    let (_, output, _) = command(&["oldest", "home", "work", "gym"])
+        .run_to_string(&mut log)
+        .unwrap();
+    let lines: Vec<_> = output.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("home | ") && lines[0].ends_with("(0:45)"));
+    assert!(lines[1].starts_with("work | ") && lines[1].ends_with("(2:00)"));
+
+    let (_, output, _) = command(&["first", "client"])
+        .run_to_string(&mut log)
+        .unwrap();
+    assert!(
+        output.starts_with("work+client | ") && output.ends_with("(1:00)\n"),
+        "{}",
+        output
+    );
+
+    let (status, _, _) = command(&["first", "gym"]).run_to_string(&mut log).unwrap();
+    assert_eq!(status, ChangeStatus::NoMatches);
+}
+
+#[test]
+fn yes_setting_skips_confirmation() {
+    let mut log = TimeLog::new();
+    let settings = Settings {
+        yes: true,
+        ..Settings::default()
+    };
+
+    let (mut output, mut error) = (Vec::new(), Vec::new());
+    let status = command(&["open", "work"])
+        .execute(
+            &mut log,
+            Outputs::new(&mut output, Some(&mut error)),
+            &settings,
+        )
+        .unwrap();
+
+    assert_eq!(status, ChangeStatus::Changed);
+    assert_eq!(log.intervals_for_tag_name("work").count(), 1);
+}
+
+#[test]
+fn compare_reports_signed_variance_against_estimates() {
+    let mut log: TimeLog = serde_json::from_str(
+        r#"{
+            "tags": ["work", "home"],
+            "intervals": [
+                {"tag": 0, "interval": {"start": "2020-03-02T09:00:00Z", "duration": "2:30"}},
+                {"tag": 1, "interval": {"start": "2020-03-02T12:00:00Z", "duration": "0:45"}}
+            ]
+        }"#,
+    )
+    .unwrap();
+    let settings = Settings {
+        estimates: vec![
+            "work=2".parse().unwrap(),
+            "home=1:00".parse().unwrap(),
+            "misc=0:15".parse().unwrap(),
+        ],
+        ..Settings::default()
+    };
+
+    let (mut output, mut error) = (Vec::new(), Vec::new());
+    command(&["compare"])
+        .execute(
+            &mut log,
+            Outputs::new(&mut output, Some(&mut error)),
+            &settings,
+        )
+        .unwrap();
+
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "Tag  | Estimated |    Actual |  Variance\n\
+         work |      2:00 |      2:30 |     +0:30 OVER ESTIMATE\n\
+         home |      1:00 |      0:45 |     -0:15 UNDER ESTIMATE\n\
+         misc |      0:15 |      0:00 |     -0:15 UNDER ESTIMATE\n"
+    );
+}
+
+#[test]
+fn export_append_writes_header_once() {
+    let mut log: TimeLog = serde_json::from_str(
+        r#"{
+            "tags": ["work"],
+            "intervals": [
+                {"tag": 0, "interval": {"start": "2020-03-02T09:00:00Z", "duration": "1:00"}}
+            ]
+        }"#,
+    )
+    .unwrap();
+    let path = std::env::temp_dir().join(format!("timelog-append-{}.csv", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let path_str = path.to_str().unwrap();
+    let export = &["export", "--output-file", path_str, "--append"];
+
+    command(export).run_to_string(&mut log).unwrap();
+    command(export).run_to_string(&mut log).unwrap();
+    let contents = std::fs::read_to_string(&path).unwrap();
+
+    std::fs::write(&path, "not,a,timelog,export\n").unwrap();
+    let mismatched = command(export).run_to_string(&mut log);
+    let untouched = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let row = "work,2020-03-02T09:00:00Z,2020-03-02T10:00:00Z,3600\n";
+    assert_eq!(contents, format!("tag,start,end,duration\n{}{}", row, row));
+    assert!(mismatched.is_err());
+    assert_eq!(untouched, "not,a,timelog,export\n");
+}
+
+#[test]
+fn coalesce_lists_by_default_and_writes_on_request() {
+    let mut log: TimeLog = serde_json::from_str(
+        r#"{
+            "tags": ["work"],
+            "intervals": [
+                {"tag": 0, "interval": {"start": "2020-03-02T09:00:00Z", "duration": "1:00"}},
+                {"tag": 0, "interval": {"start": "2020-03-02T10:03:00Z", "duration": "0:57"}}
+            ]
+        }"#,
+    )
+    .unwrap();
+    let settings = Settings {
+        yes: true,
+        ..Settings::default()
+    };
+    let mut run = |args: &[&str]| {
+        let (mut output, mut error) = (Vec::new(), Vec::new());
+        let status = command(args)
+            .execute(
+                &mut log,
+                Outputs::new(&mut output, Some(&mut error)),
+                &settings,
+            )
+            .unwrap();
+        (status, String::from_utf8(output).unwrap())
+    };
+
+    let (status, output) = run(&["coalesce", "--gap", "0:05"]);
+    assert_eq!(status, ChangeStatus::Unchanged);
+    assert_eq!(output.lines().count(), 1);
+
+    assert_eq!(run(&["coalesce"]).0, ChangeStatus::Unchanged);
+    assert_eq!(run(&["coalesce", "--write"]).0, ChangeStatus::NoMatches);
+    assert_eq!(
+        run(&["coalesce", "--gap", "0:05", "--write"]).0,
+        ChangeStatus::Changed
+    );
+    assert_eq!(log.iter().count(), 1);
+    assert_eq!(log.iter().next().unwrap().duration(), Duration::hours(2));
+}
+
+#[test]
+fn list_explain_counts_matches_per_clause() {
+    let mut log: TimeLog = serde_json::from_str(
+        r#"{
+            "tags": ["work", "home"],
+            "intervals": [
+                {"tag": 0, "interval": {"start": "2020-03-02T09:00:00Z", "duration": "1:00"}},
+                {"tag": 1, "interval": {"start": "2020-03-02T11:00:00Z", "duration": null}}
+            ]
+        }"#,
+    )
+    .unwrap();
+
+    let cmd = command(&["list", "work", "--open", "--explain"]);
+    assert!(cmd.read_only_filter().is_none());
+    let (status, output, error) = cmd.run_to_string(&mut log).unwrap();
+
+    assert_eq!(status, ChangeStatus::NoMatches);
+    assert_eq!(output, "");
+    assert_eq!(
+        error,
+        "No intervals match filter criteria. Of 2 intervals, each clause alone matches:\n\
+         \x20      1  has_tag(0)\n\
+         \x20      1  not closed\n\
+         where tag 0 is 'work'\n"
+    );
+}
+
+#[test]
+fn duration_spec_round_trips_through_display() {
+    for (input, displayed, minutes) in [
+        ("2", "2:00", 120),
+        ("1:05", "1:05", 65),
+        ("0:00:30", "0:00:30", 0),
+    ] {
+        let spec: DurationSpec = input.parse().unwrap();
+        assert_eq!(spec.to_string(), displayed);
+        assert_eq!(spec.to_string().parse::<DurationSpec>().unwrap(), spec);
+        assert_eq!(spec.duration().num_minutes(), minutes);
+    }
+
+    for invalid in ["", "1:60", "1:2:3:4", "-1", "abc"] {
+        assert!(invalid.parse::<DurationSpec>().is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn export_csv_delimiter_quotes_fields_containing_it() {
+    let mut log: TimeLog = serde_json::from_str(
+        r#"{
+            "tags": ["a;b", "c,d"],
+            "intervals": [
+                {"tag": 0, "interval": {"start": "2020-03-02T09:00:00Z", "duration": "1:00"}},
+                {"tag": 1, "interval": {"start": "2020-03-02T11:00:00Z", "duration": "0:15"}}
+            ]
+        }"#,
+    )
+    .unwrap();
+
+    let (_, output, _) = command(&["export", "--csv-delimiter", ";"])
+        .run_to_string(&mut log)
+        .unwrap();
+    assert_eq!(
+        output,
+        "tag;start;end;duration\n\
+         \"a;b\";2020-03-02T09:00:00Z;2020-03-02T10:00:00Z;3600\n\
+         c,d;2020-03-02T11:00:00Z;2020-03-02T11:15:00Z;900\n"
+    );
+
+    let (_, output, _) = command(&["export"]).run_to_string(&mut log).unwrap();
+    assert!(output.contains("\na;b,"), "{}", output);
+    assert!(output.contains("\n\"c,d\","), "{}", output);
+
+    // Every field is quoted when it contains the delimiter, including timestamps and the header.
+    let (_, output, _) = command(&["export", "--csv-delimiter", ":"])
+        .run_to_string(&mut log)
+        .unwrap();
+    assert_eq!(
+        output,
+        "tag:start:end:duration\n\
+         a;b:\"2020-03-02T09:00:00Z\":\"2020-03-02T10:00:00Z\":3600\n\
+         c,d:\"2020-03-02T11:00:00Z\":\"2020-03-02T11:15:00Z\":900\n"
+    );
+
+    let (_, output, _) = command(&["export", "--csv-delimiter", "t"])
+        .run_to_string(&mut log)
+        .unwrap();
+    assert!(
+        output.starts_with("\"tag\"t\"start\"tendt\"duration\"\n"),
+        "{}",
+        output
+    );
+
+    let (_, output, _) = command(&["export", "--csv-delimiter", "T"])
+        .run_to_string(&mut log)
+        .unwrap();
+    assert!(
+        output.contains("\na;bT\"2020-03-02T09:00:00Z\"T"),
+        "{}",
+        output
+    );
+
+    assert!(command(&["export", "--csv-delimiter", "\""])
+        .run_to_string(&mut log)
+        .is_err());
+}
+
+#[test]
+fn quiet_suppresses_informational_messages_but_not_prompts() {
+    let mut log = TimeLog::new();
+    let settings = Settings {
+        yes: true,
+        ..Settings::default()
+    };
+    let (mut output, mut error) = (Vec::new(), Vec::new());
+    let mut outputs = Outputs::new(&mut output, Some(&mut error));
+    outputs.quiet = true;
+
+    command(&["open", "newtag"])
+        .execute(&mut log, outputs, &settings)
+        .unwrap();
+
+    let error = String::from_utf8(error).unwrap();
+    assert_eq!(error, "Creating new tag 'newtag'.\n");
+    assert!(output.is_empty());
+    assert_eq!(log.intervals_for_tag_name("newtag").count(), 1);
+}
+
+#[test]
+fn bare_open_ignores_git_branch_unless_enabled() {
+    // Tests run in the package directory, which may be a git checkout; with the setting off, its
+    // branch is never consulted.
+    let mut log = TimeLog::new();
+    let settings = Settings {
+        yes: true,
+        ..Settings::default()
+    };
+    let (mut output, mut error) = (Vec::new(), Vec::new());
+    command(&["open"])
+        .execute(
+            &mut log,
+            Outputs::new(&mut output, Some(&mut error)),
+            &settings,
+        )
+        .unwrap();
+
+    assert_eq!(log.tag_names().collect::<Vec<_>>(), vec!["default"]);
+}
+
+#[test]
+fn git_branch_is_none_outside_a_repository() {
+    let dir = env::temp_dir().join(format!("timelog-git-{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    let outside = commands::git_branch(&dir);
+    let git = |args: &[&str]| {
+        process::Command::new("git")
+            .args([
+                "-c",
+                "user.name=timelog",
+                "-c",
+                "user.email=timelog@example.com",
+            ])
+            .args(args)
+            .current_dir(&dir)
+            .output()
+            .map(|output| output.status.success())
+            .unwrap_or(false)
+    };
+    let inside = if git(&["init", "-q"])
+        && git(&["checkout", "-q", "-b", "feature"])
+        && git(&["commit", "-q", "--allow-empty", "-m", "init"])
+    {
+        Some(commands::git_branch(&dir))
+    } else {
+        // git is not available; only the fallback can be checked.
+        None
+    };
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(outside, None);
+    if let Some(inside) = inside {
+        assert_eq!(inside.as_deref(), Some("feature"));
+    }
+}
+
+#[test]
+fn close_offers_to_discard_interval_starting_in_future() {
+    let start = (Utc::now() + Duration::hours(2)).to_rfc3339();
+    let json = format!(
+        r#"{{"tags": ["work"], "intervals": [{{"tag": 0, "interval": {{"start": "{}", "duration": null}}}}]}}"#,
+        start
+    );
+    let mut log: TimeLog = serde_json::from_str(&json).unwrap();
+    let settings = Settings {
+        yes: true,
+        ..Settings::default()
+    };
+    let (mut output, mut error) = (Vec::new(), Vec::new());
+
+    let status = command(&["close", "work"])
+        .execute(
+            &mut log,
+            Outputs::new(&mut output, Some(&mut error)),
+            &settings,
+        )
+        .unwrap();
+
+    let error = String::from_utf8(error).unwrap();
+    assert_eq!(status, ChangeStatus::Changed);
+    assert!(error.contains("starts after the current time"), "{}", error);
+    assert!(error.contains("Discarded interval"), "{}", error);
+    assert_eq!(log.iter().count(), 0);
+}
+
+#[test]
+fn template_parses_placeholders_and_escapes() {
+    let start = Utc.ymd(2020, 3, 2).and_hms(9, 0, 0);
+    let closed = Interval::open(start)
+        .close(start + Duration::minutes(90))
+        .unwrap();
+    let open = Interval::open(start);
+    let format: TimeFormat = "%s".parse().unwrap();
+
+    let template: Template = "{tag}\\t{start}--{end} ({duration})".parse().unwrap();
+    assert_eq!(
+        template.render("work", &closed, &format),
+        "work\t1583139600--1583145000 (1:30)"
+    );
+    let rendered = template.render("work", &open, &format);
+    assert!(
+        rendered.starts_with("work\t1583139600--OPEN (") && rendered.ends_with(')'),
+        "{}",
+        rendered
+    );
+
+    let template: Template = "{{{tag}}} \\\\ \\n \\x".parse().unwrap();
+    assert_eq!(template.render("a", &closed, &format), "{a} \\ \n \\x");
+}
+
+#[test]
+fn template_rejects_malformed_placeholders() {
+    assert_eq!(
+        "{tag} {nope}".parse::<Template>(),
+        Err(TemplateError::UnknownPlaceholder("nope".into()))
+    );
+    assert_eq!(
+        "{tag".parse::<Template>(),
+        Err(TemplateError::UnclosedPlaceholder)
+    );
+    assert_eq!(
+        "tag}".parse::<Template>(),
+        Err(TemplateError::UnmatchedBrace)
+    );
+    assert_eq!(
+        "".parse::<Template>().map(|t| t.render(
+            "a",
+            &Interval::open_now(),
+            &TimeFormat::default()
+        )),
+        Ok(String::new())
+    );
+}
+
+#[test]
+fn continue_opens_new_interval_when_nothing_to_continue() {
+    let mut log: TimeLog = serde_json::from_str(
+        r#"{
+            "tags": ["work"],
+            "intervals": [
+                {"tag": 0, "interval": {"start": "2020-03-02T09:00:00Z", "duration": "1:00"}}
+            ]
+        }"#,
+    )
+    .unwrap();
+
+    let (status, _, error) = command(&["continue", "work"])
+        .run_to_string(&mut log)
+        .unwrap();
+    assert_eq!(status, ChangeStatus::Changed);
+    assert!(error.starts_with("Opened new interval"), "{}", error);
+    assert_eq!(log.iter().count(), 2);
+    assert_eq!(log.iter().filter(|int| !int.is_closed()).count(), 1);
+}
+
+#[test]
+fn export_ics_escapes_summaries_and_skips_open_intervals() {
+    let mut log: TimeLog = serde_json::from_str(
+        r#"{
+            "tags": ["a,b;c\\d", "open"],
+            "intervals": [
+                {"tag": 0, "interval": {"start": "2020-03-02T09:00:00Z", "duration": "1:30"}},
+                {"tag": 1, "interval": {"start": "2020-03-02T11:00:00Z", "duration": null}}
+            ]
+        }"#,
+    )
+    .unwrap();
+
+    let (_, output, _) = command(&["export", "--format", "ics"])
+        .run_to_string(&mut log)
+        .unwrap();
+
+    assert!(output.ends_with("\r\n"));
+    assert!(
+        output.split("\r\n").all(|line| !line.contains('\n')),
+        "{:?}",
+        output
+    );
+    let lines: Vec<_> = output
+        .split_terminator("\r\n")
+        .filter(|line| !line.starts_with("DTSTAMP:"))
+        .collect();
+    assert_eq!(
+        lines,
+        vec![
+            "BEGIN:VCALENDAR",
+            "VERSION:2.0",
+            "PRODID:-//timelog//timelog//EN",
+            "BEGIN:VEVENT",
+            r"UID:1583139600-a\,b\;c\\d@timelog",
+            "DTSTART:20200302T090000Z",
+            "DTEND:20200302T103000Z",
+            r"SUMMARY:a\,b\;c\\d",
+            "END:VEVENT",
+            "END:VCALENDAR",
+        ]
+    );
+}
+
+#[test]
+fn export_timewarrior_golden_output() {
+    let mut log: TimeLog = serde_json::from_str(
+        r#"{
+            "tags": ["work", "side project", "say \"hi\""],
+            "intervals": [
+                {"tag": 0, "interval": {"start": "2020-03-02T09:00:00Z", "duration": "1:30"}},
+                {"tag": 1, "interval": {"start": "2020-03-02T11:00:00Z", "duration": "0:15"}},
+                {"tag": 2, "interval": {"start": "2020-03-02T12:00:00Z", "duration": null}}
+            ]
+        }"#,
+    )
+    .unwrap();
+
+    let (_, output, _) = command(&["export", "--format", "timewarrior"])
+        .run_to_string(&mut log)
+        .unwrap();
+    assert_eq!(
+        output,
+        "inc 20200302T090000Z - 20200302T103000Z # work\n\
+         inc 20200302T110000Z - 20200302T111500Z # \"side project\"\n\
+         inc 20200302T120000Z # \"say \\\"hi\\\"\"\n"
+    );
+}
+
+#[test]
+fn budget_reports_usage_against_allocations() {
+    let mut log: TimeLog = serde_json::from_str(
+        r#"{
+            "tags": ["work", "home", "client"],
+            "intervals": [
+                {"tag": 0, "extra_tags": [2],
+                 "interval": {"start": "2020-03-02T09:00:00Z", "duration": "1:30"}},
+                {"tag": 1, "interval": {"start": "2020-03-02T11:00:00Z", "duration": "1:15"}}
+            ]
+        }"#,
+    )
+    .unwrap();
+    let settings = Settings {
+        budgets: vec![
+            "work=2:00".parse().unwrap(),
+            "home=1:00".parse().unwrap(),
+            "client=1:00".parse().unwrap(),
+            "gym=0:30".parse().unwrap(),
+        ],
+        ..Settings::default()
+    };
+    let (mut output, mut error) = (Vec::new(), Vec::new());
+
+    let status = command(&["budget"])
+        .execute(
+            &mut log,
+            Outputs::new(&mut output, Some(&mut error)),
+            &settings,
+        )
+        .unwrap();
+    assert_eq!(status, ChangeStatus::Unchanged);
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "Tag    | Allocated |      Used | Remaining\n\
+         work   |      2:00 |      1:30 |      0:30\n\
+         home   |      1:00 |      1:15 |     -0:15 OVER BUDGET\n\
+         client |      1:00 |      1:30 |     -0:30 OVER BUDGET\n\
+         gym    |      0:30 |      0:00 |      0:30\n"
+    );
+}
+
+#[test]
+fn budget_without_budgets_matches_nothing() {
+    let mut log = TimeLog::new();
+    let (status, output, error) = command(&["budget"]).run_to_string(&mut log).unwrap();
+
+    assert_eq!(status, ChangeStatus::NoMatches);
+    assert_eq!(output, "");
+    assert_eq!(error, "No budgets set.\n");
+}
+
+#[test]
+fn hook_output_goes_to_standard_error() {
+    let dir = env::temp_dir().join(format!("timelog-hook-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let hook = dir.join("hook.sh");
+    fs::write(&hook, "#!/bin/sh\necho \"hook ran for $1\"\n").unwrap();
+    process::Command::new("chmod")
+        .arg("+x")
+        .arg(&hook)
+        .status()
+        .unwrap();
+
+    let output = process::Command::new(env!("CARGO_BIN_EXE_timelog"))
+        .arg("--file")
+        .arg(dir.join("log.json"))
+        .arg("--on-open")
+        .arg(&hook)
+        .args(["open", "--create", "work"])
+        .env_remove("TIMELOG_ON_OPEN")
+        .output()
+        .unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("hook ran for work"),
+        "{:?}",
+        output
+    );
+}
+
+#[test]
+fn diff_reads_logfiles_with_comments() {
+    let path = env::temp_dir().join(format!("timelog-diff-{}.jsonc", process::id()));
+    fs::write(
+        &path,
+        r#"// Backup taken before cleanup
+        {
+            "tags": ["work"],
+            "intervals": [
+                /* the only interval */
+                {"tag": 0, "interval": {"start": "2020-03-02T09:00:00Z", "duration": "1:00"}}
+            ]
+        }"#,
+    )
+    .unwrap();
+    let mut log: TimeLog = serde_json::from_str(
+        r#"{
+            "tags": ["work"],
+            "intervals": [
+                {"tag": 0, "interval": {"start": "2020-03-02T09:00:00Z", "duration": "1:00"}}
+            ]
+        }"#,
+    )
+    .unwrap();
+
+    let result = command(&["diff", path.to_str().unwrap()]).run_to_string(&mut log);
+    fs::remove_file(&path).unwrap();
+
+    let (status, output, error) = result.unwrap();
+    assert_eq!(status, ChangeStatus::Unchanged);
+    assert_eq!(output, "");
+    assert_eq!(error, "No differences.\n");
+}
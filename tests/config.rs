@@ -0,0 +1,222 @@
+use timelog::config::{ConfigError, Options};
+use timelog::zone;
+
+use chrono::{Duration, NaiveTime, Utc};
+use structopt::StructOpt;
+
+use std::env;
+use std::fs;
+
+fn doctor(path: &str) -> (bool, String) {
+    let options = Options::from_iter(&["timelog", "-f", path, "doctor"]);
+    let mut out = Vec::new();
+    let healthy = options.doctor(&mut out).unwrap();
+    (healthy, String::from_utf8(out).unwrap())
+}
+
+#[test]
+fn doctor_reports_parse_errors() {
+    let path = env::temp_dir().join(format!("timelog-doctor-{}.json", std::process::id()));
+    fs::write(&path, r#"{"tags":["work"],"intervals":[}"#).unwrap();
+
+    let (healthy, report) = doctor(path.to_str().unwrap());
+    fs::remove_file(&path).unwrap();
+
+    assert!(!healthy);
+    assert!(report.contains("cannot be parsed: "), "{}", report);
+    assert!(report.contains("line 1 column"), "{}", report);
+}
+
+#[test]
+fn doctor_reports_missing_directory() {
+    let dir = env::temp_dir().join(format!("timelog-doctor-dir-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let (healthy, report) = doctor(dir.join("log.json").to_str().unwrap());
+
+    assert!(healthy, "{}", report);
+    assert!(report.contains("does not exist yet"), "{}", report);
+    let creatable = format!("directory {} will be created on first write", dir.display());
+    assert!(report.contains(&creatable), "{}", report);
+    assert!(report.contains("Rounding: interval times to 15 minutes"));
+    assert!(report.contains("Minimum duration: none"));
+
+    // A directory under a regular file can never be created.
+    let file = env::temp_dir().join(format!("timelog-doctor-file-{}", std::process::id()));
+    fs::write(&file, "").unwrap();
+    let (healthy, report) = doctor(file.join("sub").join("log.json").to_str().unwrap());
+    fs::remove_file(&file).unwrap();
+
+    assert!(!healthy);
+    assert!(report.contains("cannot be opened: "), "{}", report);
+}
+
+#[test]
+fn day_start_shifts_today_window() {
+    let options = Options::from_iter(&["timelog", "--day-start", "4:00", "list", "--today"]);
+    let info = options.command.read_only_filter().unwrap();
+    let (start, end) = info.bounds(&options.settings);
+    let (start, end) = (start.unwrap(), end.unwrap());
+
+    let now = Utc::now();
+    assert!(start <= now && now < end, "{} {}", start, end);
+    assert_eq!(end - start, Duration::days(1));
+    assert_eq!(
+        zone::current().from_utc(&start).time(),
+        NaiveTime::from_hms(4, 0, 0)
+    );
+}
+
+#[test]
+fn day_start_rejects_malformed_times() {
+    assert!(Options::from_iter_safe(&["timelog", "--day-start", "4am", "check"]).is_err());
+    assert!(Options::from_iter_safe(&["timelog", "--day-start", "25:00", "check"]).is_err());
+}
+
+#[test]
+fn confirm_default_accepts_yes_or_no() {
+    let parse = |answer| {
+        Options::from_iter_safe(&["timelog", "--confirm-default", answer, "check"])
+            .map(|options| options.settings.confirm_default)
+    };
+
+    assert_eq!(parse("yes").unwrap(), Some(true));
+    assert_eq!(parse("N").unwrap(), Some(false));
+    assert!(parse("maybe").is_err());
+}
+
+#[test]
+fn tag_case_insensitive_parses_switch_values() {
+    let parse = |value| {
+        Options::from_iter_safe(&["timelog", "--tag-case-insensitive", value, "check"])
+            .map(|options| options.settings.tag_case_insensitive)
+    };
+
+    assert!(parse("yes").unwrap());
+    assert!(parse("1").unwrap());
+    assert!(!parse("no").unwrap());
+    assert!(!parse("0").unwrap());
+    assert!(!parse("false").unwrap());
+    assert!(parse("maybe").is_err());
+}
+
+#[test]
+fn jsonc_logfile_may_contain_comments() {
+    let contents = r#"{
+        // Tags in use
+        "tags": ["work"],
+        "intervals": [
+            /* Monday */
+            {"tag": 0, "interval": {"start": "2020-03-02T09:00:00Z", "duration": "1:00"}}
+        ]
+    }"#;
+    let dir = env::temp_dir();
+    let jsonc = dir.join(format!("timelog-comments-{}.jsonc", std::process::id()));
+    let json = dir.join(format!("timelog-comments-{}.json", std::process::id()));
+    fs::write(&jsonc, contents).unwrap();
+    fs::write(&json, contents).unwrap();
+
+    let options = |path: &std::path::Path| {
+        Options::from_iter(&["timelog", "-f", path.to_str().unwrap(), "list"])
+    };
+    let from_jsonc = options(&jsonc).current_timelog();
+    let from_json = options(&json).current_timelog();
+    fs::remove_file(&jsonc).unwrap();
+    fs::remove_file(&json).unwrap();
+
+    assert_eq!(from_jsonc.unwrap().iter().count(), 1);
+    let err = from_json.unwrap_err().to_string();
+    assert!(err.starts_with("error parsing log as JSON: "), "{}", err);
+}
+
+#[test]
+fn backup_copies_logfile_alongside_it() {
+    let dir = env::temp_dir().join(format!("timelog-backup-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let logfile = dir.join("log.json");
+    fs::write(&logfile, r#"{"tags":[],"intervals":[]}"#).unwrap();
+
+    let options = Options::from_iter(&["timelog", "-f", logfile.to_str().unwrap(), "backup"]);
+    let backup = options.backup(None);
+    let second = options.backup(None);
+    let third = options.backup(None);
+    let named = options.backup(Some(&dir.join("named.json")));
+    let contents = backup.as_ref().ok().map(fs::read_to_string);
+    fs::remove_dir_all(&dir).unwrap();
+
+    // Backups taken in quick succession never replace one another.
+    let (second, third) = (second.unwrap(), third.unwrap());
+    assert_ne!(backup.as_ref().unwrap(), &second);
+    assert_ne!(backup.as_ref().unwrap(), &third);
+    assert_ne!(second, third);
+
+    let backup = backup.unwrap();
+    assert_eq!(backup.parent(), Some(dir.as_path()));
+    let name = backup.file_name().unwrap().to_str().unwrap();
+    assert!(
+        name.starts_with("log.json.") && name.ends_with(".bak"),
+        "{}",
+        name
+    );
+    assert_eq!(contents.unwrap().unwrap(), r#"{"tags":[],"intervals":[]}"#);
+    assert_eq!(named.unwrap(), dir.join("named.json"));
+}
+
+#[test]
+fn first_write_creates_logfile_directory() {
+    let dir = env::temp_dir().join(format!("timelog-newdir-{}", std::process::id()));
+    let logfile = dir.join("nested").join("log.json");
+    let _ = fs::remove_dir_all(&dir);
+
+    let options = Options::from_iter(&["timelog", "-f", logfile.to_str().unwrap(), "open"]);
+    let lock = options.lock_logfile();
+    let locked_only = dir.exists();
+    let written = lock.and_then(|_lock| options.write_timelog(&Default::default()));
+    let exists = logfile.is_file();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(!locked_only);
+    written.unwrap();
+    assert!(exists);
+}
+
+#[test]
+fn writers_exclude_each_other_and_readers() {
+    let dir = env::temp_dir().join(format!("timelog-lock-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let logfile = dir.join("log.json");
+    let options = Options::from_iter(&["timelog", "-f", logfile.to_str().unwrap(), "open"]);
+
+    let writer = options.lock_logfile().unwrap();
+    let second_writer = options.lock_logfile();
+    let reader = options.lock_logfile_shared();
+    drop(writer);
+
+    let first_reader = options.lock_logfile_shared().unwrap();
+    let second_reader = options.lock_logfile_shared();
+    let blocked_writer = options.lock_logfile();
+    drop(first_reader);
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(matches!(second_writer, Err(ConfigError::LogFileLocked)));
+    assert!(matches!(reader, Err(ConfigError::LogFileLocked)));
+    assert!(second_reader.is_ok());
+    assert!(matches!(blocked_writer, Err(ConfigError::LogFileLocked)));
+}
+
+#[test]
+fn shared_lock_creates_nothing() {
+    let dir = env::temp_dir().join(format!("timelog-lock-ro-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let logfile = dir.join("log.json");
+    let options = Options::from_iter(&["timelog", "-f", logfile.to_str().unwrap(), "status"]);
+
+    assert!(options.command.is_read_only());
+    options.lock_logfile_shared().unwrap();
+    assert!(!dir.exists());
+
+    fs::create_dir_all(&dir).unwrap();
+    options.lock_logfile_shared().unwrap();
+    let entries = fs::read_dir(&dir).unwrap().count();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(entries, 0);
+}
@@ -0,0 +1,156 @@
+use timelog::filter::{self, filter_false, filter_true, has_tag, is_closed, is_open};
+
+use chrono::{TimeZone, Utc};
+
+#[test]
+fn display_renders_infix() {
+    assert_eq!(filter_true().to_string(), "true");
+    assert_eq!(filter_false().to_string(), "false");
+    assert_eq!(
+        (has_tag(0) & is_open()).to_string(),
+        "has_tag(0) and not closed"
+    );
+    assert_eq!(
+        (has_tag(0) | has_tag(1) | has_tag(2)).to_string(),
+        "has_tag(0) or has_tag(1) or has_tag(2)"
+    );
+    assert_eq!(
+        ((has_tag(0) | has_tag(1)) & !(is_closed() & has_tag(2))).to_string(),
+        "(has_tag(0) or has_tag(1)) and not (closed and has_tag(2))"
+    );
+    assert_eq!(
+        filter::started_before(Utc.ymd(2020, 3, 2).and_hms(9, 0, 0)).to_string(),
+        "started_before(2020-03-02 09:00:00 UTC)"
+    );
+}
+
+#[test]
+fn simplify_removes_double_negation() {
+    assert_eq!((!!has_tag(0)).simplify(), has_tag(0));
+    assert_eq!((!!!is_closed()).simplify(), is_open());
+    assert_eq!(is_open().simplify(), is_open());
+}
+
+#[test]
+fn simplify_folds_nested_redundancy() {
+    let redundant = !!(!!has_tag(1) & !!has_tag(1)) | !(!(has_tag(1) & has_tag(1)));
+    assert_eq!(redundant.simplify(), has_tag(1));
+
+    let nested = (!!has_tag(0) | !!has_tag(0)) & (!!is_closed() & !!is_closed());
+    assert_eq!(nested.simplify(), has_tag(0) & is_closed());
+}
+
+#[test]
+fn simplify_folds_constants_exposed_by_negation() {
+    let collapsed = !(!(has_tag(0) & has_tag(0)) & !(has_tag(0)));
+    assert_eq!(collapsed.simplify(), has_tag(0));
+
+    let constant = !!filter_true() & !!(filter_false() | has_tag(2));
+    assert_eq!(constant.simplify(), has_tag(2));
+    assert!((!!filter_true() | has_tag(2)).simplify().evals_true());
+}
+
+#[test]
+fn or_all_and_and_all_dedup_inputs() {
+    let tags = vec![has_tag(0), has_tag(1), has_tag(0), has_tag(1), has_tag(0)];
+
+    let any = filter::or_all(tags.clone());
+    assert_eq!(any, has_tag(0) | has_tag(1));
+    assert_eq!(any.to_string(), "has_tag(0) or has_tag(1)");
+
+    let all = filter::and_all(tags);
+    assert_eq!(all, has_tag(0) & has_tag(1));
+
+    assert_eq!(filter::or_all(vec![is_open(), is_open()]), is_open());
+}
+
+#[test]
+fn active_at_matches_containing_intervals() {
+    use timelog::interval::{Interval, TaggedInterval};
+
+    let time = |h, m| Utc.ymd(2020, 3, 2).and_hms(h, m, 0);
+    let closed = TaggedInterval::new(
+        0,
+        Interval::closed(time(9, 0), std::time::Duration::from_secs(3600)),
+    );
+    let open = TaggedInterval::open(0, time(9, 0));
+
+    assert!(filter::active_at(time(9, 0)).eval(&closed));
+    assert!(filter::active_at(time(9, 59)).eval(&closed));
+    assert!(!filter::active_at(time(10, 0)).eval(&closed));
+    assert!(!filter::active_at(time(8, 59)).eval(&closed));
+
+    assert!(filter::active_at(time(12, 0)).eval(&open));
+    assert!(!filter::active_at(time(8, 0)).eval(&open));
+    assert!(!filter::active_at(Utc::now() + chrono::Duration::hours(1)).eval(&open));
+}
+
+#[test]
+fn active_at_reads_the_clock_when_built() {
+    use timelog::clock::FixedClock;
+    use timelog::interval::TaggedInterval;
+
+    let time = |h, m| Utc.ymd(2020, 3, 2).and_hms(h, m, 0);
+    let open = TaggedInterval::open(0, time(9, 0));
+
+    let filter = filter::active_at_with(time(11, 0), &FixedClock(time(10, 0)));
+    assert!(!filter.eval(&open));
+    assert_eq!(filter.to_string(), "active_at(2020-03-02 11:00:00 UTC)");
+
+    let filter = filter::active_at_with(time(11, 0), &FixedClock(time(12, 0)));
+    assert!(filter.eval(&open));
+}
+
+#[test]
+fn compiled_filters_agree_with_eval() {
+    use timelog::interval::{Interval, TaggedInterval};
+
+    let time = |h| Utc.ymd(2020, 3, 2).and_hms(h, 0, 0);
+    let intervals = [
+        TaggedInterval::new(
+            0,
+            Interval::closed(time(9), std::time::Duration::from_secs(3600)),
+        ),
+        TaggedInterval::new(
+            1,
+            Interval::closed(time(11), std::time::Duration::from_secs(600)),
+        ),
+        TaggedInterval::open(1, time(12)),
+    ];
+    let filters = [
+        filter_true(),
+        filter_false(),
+        has_tag(1) & is_open(),
+        !(has_tag(0) | filter::started_before(time(10))),
+        filter::or_all(vec![has_tag(0), is_open(), filter::started_after(time(11))])
+            & !filter::shorter_than(chrono::Duration::minutes(10)),
+        !!is_closed() & (has_tag(2) | !has_tag(0)),
+    ];
+
+    for filter in filters.iter() {
+        let compiled = filter.compile();
+        for int in intervals.iter() {
+            assert_eq!(
+                compiled.eval(int),
+                filter.eval(int),
+                "{} on {:?}",
+                filter,
+                int
+            );
+        }
+    }
+}
+
+#[test]
+fn conjuncts_flatten_nested_and() {
+    let f = has_tag(0) & (is_open() & (has_tag(1) | is_closed()));
+    assert_eq!(
+        f.conjuncts(),
+        vec![has_tag(0), is_open(), has_tag(1) | is_closed()]
+    );
+    assert_eq!(
+        (has_tag(0) | is_open()).conjuncts(),
+        vec![has_tag(0) | is_open()]
+    );
+    assert_eq!(f.tag_ids().into_iter().collect::<Vec<_>>(), vec![0, 1]);
+}
@@ -0,0 +1,147 @@
+use timelog::interval::{
+    format_duration, format_duration_exact, Interval, TaggedInterval, TimeFormat,
+};
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+
+use std::time::Duration as StdDuration;
+
+fn time(h: u32, m: u32) -> DateTime<Utc> {
+    Utc.ymd(2020, 3, 2).and_hms(h, m, 0)
+}
+
+fn closed(tag: u32, start: DateTime<Utc>, minutes: u64) -> TaggedInterval {
+    TaggedInterval::new(
+        tag,
+        Interval::closed(start, StdDuration::from_secs(minutes * 60)),
+    )
+}
+
+#[test]
+fn split_at_midpoint() {
+    let int = closed(3, time(9, 0), 90);
+    let (first, second) = int.split_at(time(9, 45)).unwrap();
+
+    assert_eq!(first, closed(3, time(9, 0), 45));
+    assert_eq!(second, closed(3, time(9, 45), 45));
+    assert_eq!(first.end(), Some(second.start()));
+}
+
+#[test]
+fn split_at_boundaries_is_none() {
+    let int = closed(0, time(9, 0), 60);
+
+    assert_eq!(int.split_at(time(9, 0)), None);
+    assert_eq!(int.split_at(time(10, 0)), None);
+    assert_eq!(int.split_at(time(8, 0)), None);
+    assert_eq!(int.split_at(time(11, 0)), None);
+}
+
+#[test]
+fn split_open_interval_is_none() {
+    let int = TaggedInterval::open(0, time(9, 0));
+    assert_eq!(int.split_at(time(9, 30)), None);
+}
+
+#[test]
+fn elapsed_at_open_interval() {
+    let int = Interval::open(time(9, 0));
+
+    assert_eq!(int.elapsed_at(time(9, 0)).num_minutes(), 0);
+    assert_eq!(int.elapsed_at(time(9, 20)).num_minutes(), 30);
+    assert_eq!(int.elapsed_at(time(10, 45)).num_minutes(), 105);
+    assert_eq!(int.elapsed_at(time(8, 30)).num_minutes(), -30);
+}
+
+#[test]
+fn elapsed_at_closed_interval_ignores_now() {
+    let int = closed(0, time(9, 0), 45);
+    assert_eq!(int.interval().elapsed_at(time(12, 0)).num_minutes(), 45);
+}
+
+#[test]
+fn duration_within_clips_to_bounds() {
+    let int = closed(0, time(9, 0), 120);
+    let int = int.interval();
+
+    assert_eq!(int.duration_within(None, None).num_minutes(), 120);
+    assert_eq!(
+        int.duration_within(Some(time(10, 0)), None).num_minutes(),
+        60
+    );
+    assert_eq!(
+        int.duration_within(None, Some(time(9, 15))).num_minutes(),
+        15
+    );
+    assert_eq!(
+        int.duration_within(Some(time(9, 30)), Some(time(10, 15)))
+            .num_minutes(),
+        45
+    );
+    assert_eq!(
+        int.duration_within(Some(time(12, 0)), None).num_minutes(),
+        0
+    );
+}
+
+#[test]
+fn by_start_sorts_chronologically() {
+    let mut ints = vec![
+        closed(0, time(11, 0), 30),
+        TaggedInterval::open(0, time(9, 0)),
+        closed(1, time(9, 0), 60),
+        closed(0, time(9, 0), 60),
+        closed(2, time(10, 0), 15),
+    ];
+    ints.sort_by(TaggedInterval::by_start);
+
+    assert_eq!(
+        ints,
+        vec![
+            closed(0, time(9, 0), 60),
+            closed(1, time(9, 0), 60),
+            TaggedInterval::open(0, time(9, 0)),
+            closed(2, time(10, 0), 15),
+            closed(0, time(11, 0), 30),
+        ]
+    );
+}
+
+#[test]
+fn format_duration_exact_includes_seconds() {
+    assert_eq!(format_duration_exact(Duration::zero()), "0:00:00");
+    assert_eq!(format_duration_exact(Duration::seconds(59)), "0:00:59");
+    assert_eq!(format_duration_exact(Duration::seconds(3725)), "1:02:05");
+    assert_eq!(format_duration_exact(Duration::hours(27)), "27:00:00");
+    assert_eq!(format_duration_exact(Duration::seconds(-3725)), "-1:02:05");
+    assert_eq!(format_duration_exact(Duration::seconds(-1)), "-0:00:01");
+
+    assert_eq!(format_duration(Duration::seconds(3725)), "1:02");
+    assert_eq!(format_duration(Duration::zero()), "0:00");
+    assert_eq!(format_duration(Duration::minutes(-15)), "-0:15");
+}
+
+#[test]
+fn verbose_display_shows_exact_duration() {
+    let format: TimeFormat = "%s".parse().unwrap();
+    let int = Interval::closed(time(9, 0), StdDuration::from_secs(5400 + 7));
+
+    assert_eq!(
+        int.display_with(&format).to_string(),
+        "1583139600 -- 1583145007 (1:30)"
+    );
+    assert_eq!(
+        int.display_with(&format).verbose().to_string(),
+        "1583139600 -- 1583145007 (1:30) [exactly 1:30:07]"
+    );
+
+    let open = Interval::open(time(9, 0))
+        .display_with(&format)
+        .verbose()
+        .to_string();
+    assert!(
+        open.starts_with("1583139600 -- OPEN (") && open.contains(") [elapsed "),
+        "{}",
+        open
+    );
+}
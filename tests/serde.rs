@@ -0,0 +1,173 @@
+use timelog::filter;
+use timelog::interval::{Interval, TaggedInterval};
+use timelog::tags::Tags;
+use timelog::timelog::TimeLog;
+
+use chrono::{TimeZone, Utc};
+
+use std::time::Duration as StdDuration;
+
+const GOLDEN: &str = include_str!("data/timelog.json");
+
+#[test]
+fn timelog_round_trip() {
+    let mut log = TimeLog::new();
+    log.open("work", true).unwrap();
+    log.close("work").unwrap();
+    log.open("home", true).unwrap();
+
+    let json = serde_json::to_string(&log).unwrap();
+    let parsed: TimeLog = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed, log);
+}
+
+#[test]
+fn timelog_golden_format() {
+    let log: TimeLog = serde_json::from_str(GOLDEN).unwrap();
+
+    let work = log.tag_id("work").unwrap();
+    let home = log.tag_id("home").unwrap();
+    assert_eq!(log.tag_color(work), Some("#3366cc"));
+    assert_eq!(log.tag_description(work), Some("Billable hours"));
+    assert_eq!(log.tag_color(home), None);
+    let start = Utc.ymd(2020, 3, 2).and_hms(9, 0, 0);
+    let intervals: Vec<_> = log.iter().cloned().collect();
+    assert_eq!(
+        intervals,
+        vec![
+            TaggedInterval::new(work, Interval::closed(start, StdDuration::from_secs(5400))),
+            TaggedInterval::open(home, Utc.ymd(2020, 3, 2).and_hms(12, 15, 0)),
+        ]
+    );
+
+    assert_eq!(serde_json::to_string(&log).unwrap(), GOLDEN);
+}
+
+#[test]
+fn tags_serialize_as_object_array() {
+    let mut tags = Tags::new();
+    let work = tags.insert("work").unwrap();
+    tags.insert("home").unwrap();
+    tags.set_color(work, Some("red")).unwrap();
+
+    assert_eq!(
+        serde_json::to_string(&tags).unwrap(),
+        r#"[{"name":"work","color":"red"},{"name":"home"}]"#
+    );
+}
+
+#[test]
+fn tags_accept_legacy_name_array() {
+    let tags: Tags = serde_json::from_str(r#"["work","home"]"#).unwrap();
+    assert_eq!(tags.get_id("work"), Some(0));
+    assert_eq!(tags.get_id("home"), Some(1));
+    assert_eq!(tags.get_color(0), None);
+
+    let mut expected = Tags::new();
+    expected.insert("work").unwrap();
+    expected.insert("home").unwrap();
+    assert_eq!(tags, expected);
+}
+
+#[test]
+fn tags_reject_duplicate_names() {
+    let err = serde_json::from_str::<Tags>(r#"["work","home","work"]"#).unwrap_err();
+    assert!(err.to_string().contains("already exists"));
+}
+
+#[test]
+fn from_reader_filtered_keeps_matching_intervals() {
+    let full: TimeLog = serde_json::from_str(GOLDEN).unwrap();
+
+    let filtered = TimeLog::from_reader_filtered(GOLDEN.as_bytes(), |log| {
+        filter::has_tag(log.tag_id("home").unwrap())
+    })
+    .unwrap();
+
+    assert_eq!(filtered.tag_id("work"), full.tag_id("work"));
+    assert_eq!(filtered.tag_id("home"), full.tag_id("home"));
+    let expected: Vec<_> = full
+        .iter()
+        .filter(|int| full.tag_name(int.tag()) == Some("home"))
+        .collect();
+    assert_eq!(filtered.iter().collect::<Vec<_>>(), expected);
+}
+
+#[test]
+fn durations_serialize_as_strings() {
+    let start = Utc.ymd(2020, 3, 2).and_hms(9, 0, 0);
+    let cases = [
+        (StdDuration::from_secs(4500), r#""1:15""#),
+        (StdDuration::from_secs(36 * 3600), r#""36:00""#),
+        (StdDuration::from_secs(75), r#""0:01:15""#),
+        (StdDuration::new(5, 500_000_000), r#""0:00:05.500000000""#),
+    ];
+
+    for (duration, expected) in cases.iter() {
+        let int = Interval::closed(start, *duration);
+        let json = serde_json::to_string(&int).unwrap();
+        assert!(
+            json.ends_with(&format!(r#""duration":{}}}"#, expected)),
+            "{}",
+            json
+        );
+        assert_eq!(serde_json::from_str::<Interval>(&json).unwrap(), int);
+    }
+}
+
+#[test]
+fn durations_accept_legacy_struct_form() {
+    let legacy = GOLDEN.replace(r#""1:30""#, r#"{"secs":5400,"nanos":0}"#);
+    let log: TimeLog = serde_json::from_str(&legacy).unwrap();
+
+    assert_eq!(log, serde_json::from_str(GOLDEN).unwrap());
+    assert_eq!(serde_json::to_string(&log).unwrap(), GOLDEN);
+}
+
+#[test]
+fn durations_reject_malformed_strings() {
+    for bad in ["1", "1:60", "a:00", "1:00:00:00", "1:00:00.5"].iter() {
+        let json = format!(r#"{{"start":"2020-03-02T09:00:00Z","duration":"{}"}}"#, bad);
+        assert!(serde_json::from_str::<Interval>(&json).is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn declared_tags_round_trip() {
+    let mut log: TimeLog = serde_json::from_str(GOLDEN).unwrap();
+    log.declare_tag("planning");
+
+    let json = serde_json::to_string(&log).unwrap();
+    assert!(json.ends_with(r#","declared":["planning"]}"#), "{}", json);
+    assert_eq!(serde_json::from_str::<TimeLog>(&json).unwrap(), log);
+
+    let filtered =
+        TimeLog::from_reader_filtered(json.as_bytes(), |_| filter::filter_true()).unwrap();
+    assert_eq!(filtered, log);
+}
+
+#[test]
+fn extra_tags_serialize_only_when_present() {
+    let start = Utc.ymd(2020, 3, 2).and_hms(9, 0, 0);
+    let mut int = TaggedInterval::open(0, start);
+    assert!(!serde_json::to_string(&int).unwrap().contains("extra_tags"));
+
+    int.add_tag(1);
+    let json = serde_json::to_string(&int).unwrap();
+    assert!(json.ends_with(r#""extra_tags":[1]}"#), "{}", json);
+    assert_eq!(serde_json::from_str::<TaggedInterval>(&json).unwrap(), int);
+}
+
+#[test]
+fn default_tag_round_trips() {
+    let mut log: TimeLog = serde_json::from_str(GOLDEN).unwrap();
+    assert_eq!(log.set_default_tag(Some("work")), None);
+
+    let json = serde_json::to_string(&log).unwrap();
+    assert!(json.ends_with(r#","default_tag":"work"}"#), "{}", json);
+    assert_eq!(serde_json::from_str::<TimeLog>(&json).unwrap(), log);
+
+    let filtered =
+        TimeLog::from_reader_filtered(json.as_bytes(), |_| filter::filter_true()).unwrap();
+    assert_eq!(filtered.default_tag(), Some("work"));
+}
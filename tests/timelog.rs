@@ -0,0 +1,923 @@
+use timelog::clock::FixedClock;
+use timelog::filter;
+use timelog::interval::{Interval, TaggedInterval};
+use timelog::tags::TagsError;
+use timelog::timelog::{start_skew, MergeSummary, TimeLog, TimeLogError, ValidationIssue};
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+
+use std::time::Duration as StdDuration;
+
+fn timelog(json: &str) -> TimeLog {
+    serde_json::from_str(json).unwrap()
+}
+
+#[test]
+fn merge_remaps_tags_and_skips_duplicates() {
+    let mut log = timelog(
+        r#"{
+            "tags": ["work", "home"],
+            "intervals": [
+                {"tag": 0, "interval": {"start": "2020-03-02T09:00:00Z", "duration": {"secs": 3600, "nanos": 0}}}
+            ]
+        }"#,
+    );
+
+    let other = timelog(
+        r#"{
+            "tags": ["email", "work"],
+            "intervals": [
+                {"tag": 1, "interval": {"start": "2020-03-02T09:00:00Z", "duration": {"secs": 3600, "nanos": 0}}},
+                {"tag": 0, "interval": {"start": "2020-03-02T11:00:00Z", "duration": {"secs": 900, "nanos": 0}}},
+                {"tag": 1, "interval": {"start": "2020-03-02T13:00:00Z", "duration": null}}
+            ]
+        }"#,
+    );
+
+    let summary = log.merge(other);
+    assert_eq!(
+        summary,
+        MergeSummary {
+            intervals_added: 2,
+            tags_added: 1,
+        }
+    );
+
+    let email = log.tag_id("email").unwrap();
+    let work = log.tag_id("work").unwrap();
+    assert_eq!(email, 2);
+    assert_eq!(work, 0);
+
+    let tags: Vec<_> = log.iter().map(|int| int.tag()).collect();
+    assert_eq!(tags, vec![work, email, work]);
+}
+
+#[test]
+fn validate_reports_structural_issues() {
+    let log = timelog(
+        r#"{
+            "tags": ["work"],
+            "intervals": [
+                {"tag": 0, "interval": {"start": "2020-03-02T09:00:00Z", "duration": {"secs": 3600, "nanos": 0}}},
+                {"tag": 0, "interval": {"start": "2020-03-02T09:30:00Z", "duration": null}},
+                {"tag": 0, "interval": {"start": "2020-03-02T11:00:00Z", "duration": null}},
+                {"tag": 4, "interval": {"start": "2020-03-02T09:00:00Z", "duration": null}}
+            ]
+        }"#,
+    );
+
+    assert_eq!(
+        log.validate(),
+        vec![
+            ValidationIssue::UnknownTag { index: 3, tag: 4 },
+            ValidationIssue::MultipleOpen { tag: 0, count: 2 },
+            ValidationIssue::Overlap {
+                tag: 0,
+                first: 0,
+                second: 1,
+            },
+            ValidationIssue::Overlap {
+                tag: 0,
+                first: 1,
+                second: 2,
+            },
+        ]
+    );
+}
+
+#[test]
+fn validate_reports_overlaps_with_non_adjacent_intervals() {
+    let log = timelog(
+        r#"{
+            "tags": ["work"],
+            "intervals": [
+                {"tag": 0, "interval": {"start": "2020-03-02T11:00:00Z", "duration": {"secs": 1800, "nanos": 0}}},
+                {"tag": 0, "interval": {"start": "2020-03-02T09:00:00Z", "duration": {"secs": 10800, "nanos": 0}}},
+                {"tag": 0, "interval": {"start": "2020-03-02T10:00:00Z", "duration": {"secs": 1800, "nanos": 0}}}
+            ]
+        }"#,
+    );
+
+    assert_eq!(
+        log.validate(),
+        vec![
+            ValidationIssue::Overlap {
+                tag: 0,
+                first: 1,
+                second: 2,
+            },
+            ValidationIssue::Overlap {
+                tag: 0,
+                first: 1,
+                second: 0,
+            },
+        ]
+    );
+}
+
+fn at(h: u32, m: u32) -> FixedClock {
+    FixedClock(Utc.ymd(2020, 3, 2).and_hms(h, m, 0))
+}
+
+#[test]
+fn validate_accepts_intervals_opened_alongside_others() {
+    let mut log = TimeLog::new();
+    log.open_with("work", true, &at(9, 0)).unwrap();
+    log.open_another_with("work", &at(10, 0));
+    assert_eq!(log.validate(), vec![]);
+
+    let json = serde_json::to_string(&log).unwrap();
+    assert!(json.contains(r#""concurrent":true"#), "{}", json);
+    let log: TimeLog = serde_json::from_str(&json).unwrap();
+    assert_eq!(log.validate(), vec![]);
+
+    // A concurrent interval does not excuse other overlaps of the same tag.
+    let log = timelog(
+        r#"{
+            "tags": ["work"],
+            "intervals": [
+                {"tag": 0, "interval": {"start": "2020-03-02T09:00:00Z", "duration": {"secs": 7200, "nanos": 0}}},
+                {"tag": 0, "interval": {"start": "2020-03-02T10:00:00Z", "duration": null}, "concurrent": true},
+                {"tag": 0, "interval": {"start": "2020-03-02T10:30:00Z", "duration": null}}
+            ]
+        }"#,
+    );
+    assert_eq!(
+        log.validate(),
+        vec![ValidationIssue::Overlap {
+            tag: 0,
+            first: 0,
+            second: 2,
+        }]
+    );
+}
+
+#[test]
+fn open_and_close_round_to_quarter_hours() {
+    let mut log = TimeLog::new();
+
+    let int = log.open_with("work", true, &at(9, 5)).unwrap();
+    assert_eq!(int.start(), at(9, 0).0);
+
+    let int = log.close_with("work", &at(9, 20)).unwrap();
+    assert_eq!(int.start(), at(9, 0).0);
+    assert_eq!(int.end(), Some(at(9, 30).0));
+}
+
+#[test]
+fn open_reopens_recently_closed_interval() {
+    let mut log = TimeLog::new();
+    log.open_with("work", true, &at(9, 5)).unwrap();
+    log.close_with("work", &at(9, 20)).unwrap();
+
+    let int = log.open_with("work", true, &at(9, 25)).unwrap();
+    assert_eq!(int.start(), at(9, 0).0);
+    assert!(!int.is_closed());
+    assert_eq!(log.iter().count(), 1);
+
+    assert_eq!(
+        log.open_with("work", true, &at(9, 40)),
+        Err(TimeLogError::TagAlreadyOpen)
+    );
+}
+
+#[test]
+fn open_after_window_creates_new_interval() {
+    let mut log = TimeLog::new();
+    log.open_with("work", true, &at(9, 5)).unwrap();
+    log.close_with("work", &at(9, 40)).unwrap();
+
+    let int = log.open_with("work", true, &at(10, 0)).unwrap();
+    assert_eq!(int.start(), at(10, 0).0);
+    assert_eq!(log.iter().count(), 2);
+}
+
+#[test]
+fn retag_changes_only_the_selected_interval() {
+    let mut log = TimeLog::new();
+    log.open_with("work", true, &at(9, 0)).unwrap();
+    log.close_with("work", &at(10, 0)).unwrap();
+    log.open_with("work", true, &at(11, 0)).unwrap();
+    log.close_with("work", &at(12, 0)).unwrap();
+
+    let int = log.retag("work", "home", Some(at(9, 30).0)).unwrap();
+    assert_eq!(int.start(), at(9, 0).0);
+    assert_eq!(log.tag_name(int.tag()), Some("home"));
+
+    let int = log.retag("work", "play", None).unwrap();
+    assert_eq!(int.start(), at(11, 0).0);
+
+    assert_eq!(
+        log.retag("work", "home", None),
+        Err(TimeLogError::NoMatchingInterval)
+    );
+    assert_eq!(
+        log.retag("home", "work", Some(at(10, 30).0)),
+        Err(TimeLogError::NoMatchingInterval)
+    );
+}
+
+#[test]
+fn gc_tag_names_remaps_ids_and_preserves_order() {
+    let mut log = timelog(
+        r#"{"tags":["unused","work","home"],"intervals":[
+            {"tag":2,"interval":{"start":"2020-03-02T09:00:00Z","duration":{"secs":3600,"nanos":0}}},
+            {"tag":1,"interval":{"start":"2020-03-02T11:00:00Z","duration":{"secs":3600,"nanos":0}}},
+            {"tag":2,"interval":{"start":"2020-03-02T13:00:00Z","duration":null}}
+        ]}"#,
+    );
+    let starts: Vec<_> = log.iter().map(|int| int.start()).collect();
+
+    let remapping = log.gc_tag_names();
+    assert_eq!(remapping.len(), 2);
+    assert_eq!(remapping[&2], 0);
+    assert_eq!(remapping[&1], 1);
+
+    assert_eq!(log.tag_id("unused"), None);
+    assert_eq!(log.tag_id("home"), Some(0));
+    assert_eq!(log.tag_id("work"), Some(1));
+
+    let after: Vec<_> = log.iter().map(|int| int.start()).collect();
+    assert_eq!(after, starts);
+    let tags: Vec<_> = log.iter().map(|int| int.tag()).collect();
+    assert_eq!(tags, vec![0, 1, 0]);
+}
+
+#[test]
+fn gc_tag_names_keeps_tag_metadata() {
+    let mut log = timelog(
+        r#"{"tags":["unused",{"name":"work","color":"blue","description":"Day job"}],"intervals":[
+            {"tag":1,"interval":{"start":"2020-03-02T09:00:00Z","duration":null}}
+        ]}"#,
+    );
+
+    log.gc_tag_names();
+    let work = log.tag_id("work").unwrap();
+    assert_eq!(work, 0);
+    assert_eq!(log.tag_color(work), Some("blue"));
+    assert_eq!(log.tag_description(work), Some("Day job"));
+}
+
+#[test]
+fn open_without_reopen_starts_after_recent_interval() {
+    let mut log = TimeLog::new();
+    log.open_with("work", true, &at(9, 5)).unwrap();
+    log.close_with("work", &at(9, 20)).unwrap();
+
+    // The closed interval ends at 9:30 after rounding, so opening before then would start in
+    // the future.
+    assert_eq!(
+        log.open_with("work", false, &at(9, 25)),
+        Err(TimeLogError::EndsInFuture)
+    );
+    assert_eq!(log.iter().count(), 1);
+
+    let int = log.open_with("work", false, &at(9, 35)).unwrap();
+    assert_eq!(int.start(), at(9, 30).0);
+    assert_eq!(log.iter().count(), 2);
+    assert!(log.validate().is_empty());
+}
+
+#[test]
+fn intervals_for_tag_selects_by_tag() {
+    let log = timelog(
+        r#"{"tags":["work","home"],"intervals":[
+            {"tag":0,"interval":{"start":"2020-03-02T09:00:00Z","duration":{"secs":3600,"nanos":0}}},
+            {"tag":1,"interval":{"start":"2020-03-02T11:00:00Z","duration":{"secs":3600,"nanos":0}}},
+            {"tag":0,"interval":{"start":"2020-03-02T13:00:00Z","duration":null}}
+        ]}"#,
+    );
+
+    assert_eq!(log.intervals_for_tag(0).count(), 2);
+    assert_eq!(log.intervals_for_tag(1).count(), 1);
+    assert_eq!(log.intervals_for_tag(2).count(), 0);
+    assert_eq!(log.intervals_for_tag_name("work").count(), 2);
+    assert_eq!(log.intervals_for_tag_name("play").count(), 0);
+}
+
+#[test]
+fn timelog_can_be_shared_between_threads() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<TimeLog>();
+}
+
+#[test]
+fn iter_overlapping_matches_linear_scan() {
+    let mut log = timelog(
+        r#"{"tags":["work","home"],"intervals":[
+            {"tag":0,"interval":{"start":"2020-03-02T13:00:00Z","duration":{"secs":3600,"nanos":0}}},
+            {"tag":1,"interval":{"start":"2020-03-01T20:00:00Z","duration":{"secs":36000,"nanos":0}}},
+            {"tag":0,"interval":{"start":"2020-03-02T09:00:00Z","duration":{"secs":1800,"nanos":0}}},
+            {"tag":1,"interval":{"start":"2020-02-28T09:00:00Z","duration":null}},
+            {"tag":0,"interval":{"start":"2020-03-03T09:00:00Z","duration":{"secs":1800,"nanos":0}}}
+        ]}"#,
+    );
+
+    let overlapping = |log: &TimeLog, start: DateTime<Utc>, end: DateTime<Utc>| {
+        let filter =
+            filter::started_before(end) & (filter::is_open() | filter::ended_after_strict(start));
+        let expected: Vec<_> = log.iter().filter(filter.build_ref()).collect();
+        let actual: Vec<_> = log
+            .iter_overlapping(Some(start), Some(end))
+            .filter(filter.build_ref())
+            .collect();
+        assert_eq!(actual, expected);
+        actual.len()
+    };
+
+    assert_eq!(overlapping(&log, at(0, 0).0, at(23, 59).0), 4);
+    assert_eq!(overlapping(&log, at(9, 15).0, at(10, 0).0), 2);
+    assert_eq!(overlapping(&log, at(14, 0).0, at(23, 0).0), 1);
+
+    log.close_with("home", &at(8, 0)).unwrap();
+    assert_eq!(overlapping(&log, at(14, 0).0, at(23, 0).0), 0);
+    assert_eq!(log.iter_overlapping(None, None).count(), 5);
+}
+
+#[test]
+fn open_another_allows_multiple_open_intervals() {
+    let mut log = TimeLog::new();
+    log.open_with("work", true, &at(9, 0)).unwrap();
+    log.open_another_with("work", &at(10, 5));
+    log.open_another_with("work", &at(9, 35));
+
+    assert_eq!(
+        log.open_with("work", true, &at(11, 0)),
+        Err(TimeLogError::TagAlreadyOpen)
+    );
+
+    let int = log.close_nth_with("work", 1, &at(11, 0)).unwrap();
+    assert_eq!(int.start(), at(9, 30).0);
+
+    let int = log.close_with("work", &at(11, 0)).unwrap();
+    assert_eq!(int.start(), at(9, 0).0);
+
+    assert_eq!(
+        log.close_nth_with("work", 1, &at(11, 0)),
+        Err(TimeLogError::TagNotOpen)
+    );
+    let int = log.close_with("work", &at(11, 0)).unwrap();
+    assert_eq!(int.start(), at(10, 0).0);
+}
+
+#[test]
+fn tag_totals_sum_durations_per_tag() {
+    let mut log = timelog(
+        r#"{
+            "tags": ["work", "home", "idle"],
+            "intervals": [
+                {"tag": 0, "interval": {"start": "2020-03-02T09:00:00Z", "duration": "1:00"}},
+                {"tag": 1, "interval": {"start": "2020-03-02T10:00:00Z", "duration": "0:30"}},
+                {"tag": 0, "interval": {"start": "2020-03-02T11:00:00Z", "duration": "0:45"}}
+            ]
+        }"#,
+    );
+    let work = log.tag_id("work").unwrap();
+    let home = log.tag_id("home").unwrap();
+
+    let totals = log.tag_totals();
+    assert_eq!(totals.len(), 2);
+    assert_eq!(totals[&work].num_minutes(), 105);
+    assert_eq!(totals[&home].num_minutes(), 30);
+
+    let start = Utc.ymd(2020, 3, 2).and_hms(10, 30, 0);
+    let totals = log.tag_totals_filtered(&filter::started_after(start));
+    assert_eq!(totals.len(), 1);
+    assert_eq!(totals[&work].num_minutes(), 45);
+
+    log.open_another_with("home", &FixedClock(Utc::now() - chrono::Duration::hours(2)));
+    let elapsed = log.tag_totals()[&home] - chrono::Duration::minutes(30);
+    assert!(elapsed >= chrono::Duration::hours(2), "{}", elapsed);
+    assert!(elapsed <= chrono::Duration::minutes(135), "{}", elapsed);
+}
+
+#[test]
+fn rename_tag_keeps_intervals() {
+    let mut log = timelog(
+        r#"{
+            "tags": ["work", "home"],
+            "intervals": [
+                {"tag": 0, "interval": {"start": "2020-03-02T09:00:00Z", "duration": "1:00"}}
+            ]
+        }"#,
+    );
+
+    assert_eq!(log.rename_tag("work", "job"), Ok(0));
+    assert_eq!(log.tag_id("job"), Some(0));
+    assert_eq!(log.tag_id("work"), None);
+    assert_eq!(log.intervals_for_tag_name("job").count(), 1);
+
+    assert_eq!(log.rename_tag("job", "job"), Ok(0));
+    assert_eq!(log.rename_tag("work", "job"), Err(TagsError::UnknownTag));
+}
+
+#[test]
+fn rename_tag_into_existing_name_fails() {
+    let mut log = timelog(r#"{"tags": ["work", "home"], "intervals": []}"#);
+
+    assert_eq!(log.rename_tag("work", "home"), Err(TagsError::TagExists));
+    assert_eq!(log.tag_id("work"), Some(0));
+    assert_eq!(log.tag_id("home"), Some(1));
+}
+
+#[test]
+fn diff_matches_intervals_by_tag_name_and_start() {
+    let old = timelog(
+        r#"{
+            "tags": ["work", "home"],
+            "intervals": [
+                {"tag": 0, "interval": {"start": "2020-03-02T09:00:00Z", "duration": "1:00"}},
+                {"tag": 1, "interval": {"start": "2020-03-02T12:00:00Z", "duration": "0:30"}},
+                {"tag": 0, "interval": {"start": "2020-03-02T14:00:00Z", "duration": null}}
+            ]
+        }"#,
+    );
+    let new = timelog(
+        r#"{
+            "tags": ["home", "work", "gym"],
+            "intervals": [
+                {"tag": 1, "interval": {"start": "2020-03-02T09:00:00Z", "duration": "1:00"}},
+                {"tag": 1, "interval": {"start": "2020-03-02T14:00:00Z", "duration": "2:00"}},
+                {"tag": 2, "interval": {"start": "2020-03-02T17:00:00Z", "duration": "1:00"}}
+            ]
+        }"#,
+    );
+
+    let diff = old.diff(&new);
+    let names = |ints: &[(String, _)]| {
+        ints.iter()
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(names(&diff.added), vec!["gym"]);
+    assert_eq!(names(&diff.removed), vec!["home"]);
+    assert_eq!(diff.changed.len(), 1);
+
+    let (tag, before, after) = &diff.changed[0];
+    assert_eq!(tag, "work");
+    assert!(!before.is_closed());
+    assert_eq!(after.duration().num_hours(), 2);
+
+    assert!(old.diff(&old).is_empty());
+}
+
+#[test]
+fn diff_distinguishes_intervals_sharing_tag_and_start() {
+    let old = timelog(
+        r#"{
+            "tags": ["work", "errands"],
+            "intervals": [
+                {"tag": 0, "interval": {"start": "2020-03-02T09:00:00Z", "duration": "1:00"}},
+                {"tag": 0, "interval": {"start": "2020-03-02T09:00:00Z", "duration": "1:00"}},
+                {"tag": 0, "interval": {"start": "2020-03-02T12:00:00Z", "duration": "0:30"}}
+            ]
+        }"#,
+    );
+    let new = timelog(
+        r#"{
+            "tags": ["work", "errands"],
+            "intervals": [
+                {"tag": 0, "interval": {"start": "2020-03-02T09:00:00Z", "duration": "1:00"}},
+                {"tag": 0, "extra_tags": [1],
+                 "interval": {"start": "2020-03-02T12:00:00Z", "duration": "0:30"}}
+            ]
+        }"#,
+    );
+
+    let diff = old.diff(&new);
+    assert!(diff.changed.is_empty());
+    assert_eq!(diff.removed.len(), 2);
+    assert_eq!(diff.removed[0].0, "work");
+    assert_eq!(diff.removed[1].0, "work");
+    assert_eq!(diff.added.len(), 1);
+    assert_eq!(diff.added[0].0, "work+errands");
+
+    assert!(new.diff(&new).is_empty());
+    assert!(old.diff(&old).is_empty());
+}
+
+#[test]
+fn close_at_least_leaves_short_intervals_open() {
+    let mut log = TimeLog::new();
+    let min = chrono::Duration::minutes(30);
+    log.open_with("work", false, &at(9, 0)).unwrap();
+
+    assert_eq!(
+        log.close_nth_at_least_with("work", 0, min, &at(9, 10)),
+        Err(TimeLogError::TooShort)
+    );
+    assert!(log.iter().all(|int| !int.is_closed()));
+
+    let closed = log
+        .close_nth_at_least_with("work", 0, min, &at(9, 20))
+        .unwrap();
+    assert_eq!(closed.duration(), min);
+}
+
+#[test]
+fn discard_nth_removes_open_interval() {
+    let mut log = TimeLog::new();
+    log.open_with("work", false, &at(9, 0)).unwrap();
+    log.close_with("work", &at(10, 0)).unwrap();
+    log.open_another_with("work", &at(11, 0));
+
+    let discarded = log.discard_nth("work", 0).unwrap();
+    assert_eq!(discarded.start(), Utc.ymd(2020, 3, 2).and_hms(11, 0, 0));
+    assert_eq!(log.iter().count(), 1);
+    assert_eq!(log.discard_nth("work", 0), Err(TimeLogError::TagNotOpen));
+}
+
+#[test]
+fn query_applies_filter() {
+    let mut log = TimeLog::new();
+    log.open_with("work", false, &at(9, 0)).unwrap();
+    log.close_with("work", &at(10, 0)).unwrap();
+    log.open_with("home", false, &at(11, 0)).unwrap();
+
+    let open = filter::is_open();
+    let found: Vec<_> = log.query(&open).collect();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].tag(), log.tag_id("home").unwrap());
+
+    let end = Utc.ymd(2020, 3, 2).and_hms(12, 0, 0);
+    for int in log.query_mut(&open) {
+        *int = int.close(end).unwrap();
+    }
+    assert_eq!(log.query(&open).count(), 0);
+}
+
+#[test]
+fn close_within_same_quarter_hour_is_zero_length() {
+    let mut log = TimeLog::new();
+    log.open_with("work", true, &at(9, 2)).unwrap();
+
+    let int = log.close_with("work", &at(9, 14)).unwrap();
+    assert_eq!(int.start(), at(9, 0).0);
+    assert_eq!(int.end(), Some(at(9, 0).0));
+    assert_eq!(int.duration(), chrono::Duration::zero());
+}
+
+#[test]
+fn close_at_next_quarter_hour_boundary_is_full_quarter() {
+    let mut log = TimeLog::new();
+    log.open_with("work", true, &at(9, 2)).unwrap();
+
+    let int = log.close_with("work", &at(9, 15)).unwrap();
+    assert_eq!(int.end(), Some(at(9, 15).0));
+
+    log.open_with("home", true, &at(10, 14)).unwrap();
+    let int = log.close_with("home", &at(10, 16)).unwrap();
+    assert_eq!(int.start(), at(10, 0).0);
+    assert_eq!(int.end(), Some(at(10, 30).0));
+}
+
+#[test]
+fn declared_tags_survive_gc() {
+    let mut log = timelog(
+        r#"{
+            "tags": ["old", "work"],
+            "intervals": [
+                {"tag": 1, "interval": {"start": "2020-03-02T09:00:00Z", "duration": "1:00"}}
+            ]
+        }"#,
+    );
+
+    assert!(log.declare_tag("planning"));
+    assert!(!log.declare_tag("planning"));
+    assert_eq!(log.unused_tag_names().collect::<Vec<_>>(), vec!["old"]);
+
+    let remapping = log.gc_tag_names();
+    assert_eq!(log.tag_id("old"), None);
+    assert_eq!(log.tag_id("work"), Some(0));
+    assert_eq!(log.tag_id("planning"), Some(1));
+    assert_eq!(remapping.get(&2), Some(&1));
+    assert_eq!(
+        log.declared_tag_names().collect::<Vec<_>>(),
+        vec!["planning"]
+    );
+}
+
+#[test]
+fn extra_tags_are_matched_counted_and_remapped() {
+    let mut log = timelog(
+        r#"{
+            "tags": ["unused", "work", "meeting"],
+            "intervals": [
+                {"tag": 1, "interval": {"start": "2020-03-02T09:00:00Z", "duration": "1:00"},
+                 "extra_tags": [2]},
+                {"tag": 2, "interval": {"start": "2020-03-02T10:00:00Z", "duration": "0:30"}}
+            ]
+        }"#,
+    );
+    let work = log.tag_id("work").unwrap();
+    let meeting = log.tag_id("meeting").unwrap();
+
+    assert_eq!(log.query(&filter::has_tag(meeting)).count(), 2);
+    assert_eq!(log.query(&filter::has_primary_tag(meeting)).count(), 1);
+
+    let totals = log.tag_totals();
+    assert_eq!(totals[&work].num_minutes(), 60);
+    assert_eq!(totals[&meeting].num_minutes(), 90);
+
+    log.gc_tag_names();
+    let work = log.tag_id("work").unwrap();
+    let meeting = log.tag_id("meeting").unwrap();
+    let first = log.iter().next().unwrap();
+    assert_eq!(first.tag(), work);
+    assert_eq!(first.extra_tags(), &[meeting]);
+
+    let int = log.open_with("meeting", true, &at(10, 30)).unwrap();
+    assert_eq!(int.tag(), meeting);
+    assert!(int.extra_tags().is_empty());
+    assert!(log.iter().next().unwrap().is_closed());
+}
+
+#[test]
+fn add_extra_tag_creates_tag_and_skips_duplicates() {
+    let mut log = TimeLog::new();
+    let int = log.open_with("work", true, &at(9, 0)).unwrap();
+
+    let int = log.add_extra_tag(&int, "meeting").unwrap();
+    let int = log.add_extra_tag(&int, "meeting").unwrap();
+    let int = log.add_extra_tag(&int, "work").unwrap();
+    assert_eq!(int.extra_tags(), &[log.tag_id("meeting").unwrap()]);
+
+    let closed = log.close_with("work", &at(10, 0)).unwrap();
+    assert_eq!(closed.extra_tags(), int.extra_tags());
+    assert_eq!(
+        log.add_extra_tag(&int, "home"),
+        Err(TimeLogError::NoMatchingInterval)
+    );
+}
+
+#[test]
+fn from_intervals_validates_tags() {
+    let start = Utc.ymd(2020, 3, 2).and_hms(9, 0, 0);
+    let intervals = vec![
+        TaggedInterval::new(1, Interval::closed(start, StdDuration::from_secs(3600))),
+        TaggedInterval::open(0, start),
+    ];
+
+    let log =
+        TimeLog::from_intervals(vec!["work".into(), "home".into()], intervals.clone()).unwrap();
+    assert_eq!(log.iter().cloned().collect::<Vec<_>>(), intervals);
+    assert_eq!(
+        log.intervals_for_tag_name("home").next(),
+        Some(&intervals[0])
+    );
+
+    assert_eq!(
+        TimeLog::from_intervals(vec!["work".into()], intervals),
+        Err(TagsError::UnknownTag)
+    );
+    assert_eq!(
+        TimeLog::from_intervals(vec!["work".into(), "work".into()], Vec::new()),
+        Err(TagsError::TagExists)
+    );
+}
+
+#[test]
+fn coalesced_merges_same_tags_across_short_gaps() {
+    let mut log = timelog(
+        r#"{
+            "tags": ["work", "home"],
+            "intervals": [
+                {"tag": 0, "interval": {"start": "2020-03-02T09:00:00Z", "duration": "1:00"}},
+                {"tag": 1, "interval": {"start": "2020-03-02T10:02:00Z", "duration": "0:30"}},
+                {"tag": 0, "interval": {"start": "2020-03-02T10:05:00Z", "duration": "0:55"}},
+                {"tag": 0, "extra_tags": [1], "interval": {"start": "2020-03-02T11:00:00Z", "duration": "0:30"}},
+                {"tag": 0, "interval": {"start": "2020-03-02T13:00:00Z", "duration": null}},
+                {"tag": 0, "interval": {"start": "2020-03-02T11:03:00Z", "duration": "0:10"}}
+            ]
+        }"#,
+    );
+    let start = |h, m| Utc.ymd(2020, 3, 2).and_hms(h, m, 0);
+    let closed = |tag, h, m, mins: u64| {
+        TaggedInterval::new(
+            tag,
+            Interval::closed(start(h, m), StdDuration::from_secs(mins * 60)),
+        )
+    };
+
+    assert_eq!(log.coalesced(Duration::zero()).len(), 6);
+
+    let mut extra = closed(0, 11, 0, 30);
+    extra.add_tag(1);
+    let expected = vec![
+        closed(0, 9, 0, 133),
+        closed(1, 10, 2, 30),
+        extra,
+        TaggedInterval::open(0, start(13, 0)),
+    ];
+    assert_eq!(log.coalesced(Duration::minutes(5)), expected);
+    assert_eq!(log.iter().count(), 6);
+
+    let mut open = expected.clone();
+    open.truncate(3);
+    open[0] = TaggedInterval::open(0, start(9, 0));
+    assert_eq!(log.coalesced(Duration::hours(2)), open);
+
+    assert_eq!(log.coalesce(Duration::minutes(5)), 2);
+    assert_eq!(log.iter().cloned().collect::<Vec<_>>(), expected);
+}
+
+#[test]
+fn coalesce_keeps_the_order_of_untouched_intervals() {
+    let mut log = timelog(
+        r#"{
+            "tags": ["work", "home"],
+            "intervals": [
+                {"tag": 1, "interval": {"start": "2020-03-02T15:00:00Z", "duration": "1:00"}},
+                {"tag": 0, "interval": {"start": "2020-03-02T10:05:00Z", "duration": "0:55"}},
+                {"tag": 1, "interval": {"start": "2020-03-02T08:00:00Z", "duration": "0:30"}},
+                {"tag": 0, "interval": {"start": "2020-03-02T09:00:00Z", "duration": "1:00"}}
+            ]
+        }"#,
+    );
+    let original: Vec<_> = log.iter().cloned().collect();
+    assert_eq!(log.coalesced(Duration::zero()), original);
+
+    assert_eq!(log.coalesce(Duration::minutes(5)), 1);
+    let ints: Vec<_> = log.iter().cloned().collect();
+    assert_eq!(ints.len(), 3);
+    assert_eq!(ints[0], original[0]);
+    assert_eq!(ints[1].start(), original[3].start());
+    assert_eq!(ints[1].duration(), Duration::hours(2));
+    assert_eq!(ints[2], original[2]);
+}
+
+#[test]
+fn round_closed_intervals_skips_open_and_rounded_intervals() {
+    let mut log = timelog(
+        r#"{
+            "tags": ["work"],
+            "intervals": [
+                {"tag": 0, "interval": {"start": "2020-03-02T09:07:00Z", "duration": "1:00"}},
+                {"tag": 0, "interval": {"start": "2020-03-02T11:00:00Z", "duration": "0:15"}},
+                {"tag": 0, "interval": {"start": "2020-03-02T13:07:00Z", "duration": null}}
+            ]
+        }"#,
+    );
+    let start = |h, m| Utc.ymd(2020, 3, 2).and_hms(h, m, 0);
+
+    assert_eq!(log.round_closed_intervals(), 1);
+    assert_eq!(
+        log.iter().cloned().collect::<Vec<_>>(),
+        vec![
+            TaggedInterval::new(
+                0,
+                Interval::closed(start(9, 0), StdDuration::from_secs(75 * 60))
+            ),
+            TaggedInterval::new(
+                0,
+                Interval::closed(start(11, 0), StdDuration::from_secs(15 * 60))
+            ),
+            TaggedInterval::open(0, start(13, 7)),
+        ]
+    );
+    assert_eq!(log.round_closed_intervals(), 0);
+}
+
+#[test]
+fn tag_names_include_unused_tags() {
+    let mut log = timelog(
+        r#"{
+            "tags": ["work", "home"],
+            "intervals": [
+                {"tag": 1, "interval": {"start": "2020-03-02T09:00:00Z", "duration": "1:00"}}
+            ]
+        }"#,
+    );
+    log.declare_tag("errands");
+
+    assert_eq!(
+        log.tag_names().collect::<Vec<_>>(),
+        vec!["work", "home", "errands"]
+    );
+    assert_eq!(log.tag_count(), 3);
+    assert_eq!(TimeLog::new().tag_count(), 0);
+}
+
+#[test]
+fn closing_before_start_reports_clock_skew() {
+    let mut log = TimeLog::new();
+    log.open_with("work", false, &at(10, 0)).unwrap();
+
+    assert_eq!(
+        log.close_with("work", &at(9, 30)),
+        Err(TimeLogError::StartsInFuture)
+    );
+    assert!(log.iter().all(|int| !int.is_closed()));
+
+    // Closing at a later time after the clock recovers still works.
+    let closed = log.close_with("work", &at(11, 0)).unwrap();
+    assert_eq!(closed.duration(), Duration::hours(1));
+}
+
+#[test]
+fn start_skew_flags_starts_beyond_threshold() {
+    let int = TaggedInterval::open(0, Utc.ymd(2020, 3, 2).and_hms(10, 0, 0));
+    let threshold = Duration::minutes(15);
+
+    assert_eq!(start_skew(&int, at(10, 0).0, threshold), None);
+    assert_eq!(start_skew(&int, at(9, 45).0, threshold), None);
+    assert_eq!(
+        start_skew(&int, at(9, 0).0, threshold),
+        Some(Duration::hours(1))
+    );
+}
+
+#[test]
+fn aggregate_sums_matching_intervals_including_open_ones() {
+    let log = timelog(
+        r#"{
+            "tags": ["work", "home"],
+            "intervals": [
+                {"tag": 0, "interval": {"start": "2020-03-02T09:00:00Z", "duration": {"secs": 3600, "nanos": 0}}},
+                {"tag": 1, "interval": {"start": "2020-03-02T11:00:00Z", "duration": {"secs": 900, "nanos": 0}}},
+                {"tag": 0, "interval": {"start": "2020-03-02T13:00:00Z", "duration": null}}
+            ]
+        }"#,
+    );
+    let open_start = Utc.ymd(2020, 3, 2).and_hms(13, 0, 0);
+
+    assert_eq!(log.aggregate(&filter::is_closed()), Duration::minutes(75));
+    assert_eq!(log.aggregate(&filter::filter_false()), Duration::zero());
+
+    // Open intervals count their elapsed time, rounded up to the next quarter hour.
+    let before = Utc::now() - open_start;
+    let open = log.aggregate(&filter::is_open());
+    let after = Utc::now() - open_start + Duration::minutes(15);
+    assert!(before <= open && open <= after);
+    assert_eq!(open.num_seconds() % (15 * 60), 0);
+
+    let work = log.aggregate(&filter::has_tag(0)) - Duration::hours(1);
+    assert!(before <= work && work <= after);
+}
+
+#[test]
+fn aggregate_within_clips_intervals_to_bounds() {
+    let log = timelog(
+        r#"{
+            "tags": ["work", "home"],
+            "intervals": [
+                {"tag": 0, "interval": {"start": "2020-03-02T09:00:00Z", "duration": {"secs": 3600, "nanos": 0}}},
+                {"tag": 1, "interval": {"start": "2020-03-02T11:00:00Z", "duration": {"secs": 1800, "nanos": 0}}},
+                {"tag": 0, "interval": {"start": "2020-03-02T13:00:00Z", "duration": {"secs": 900, "nanos": 0}}}
+            ]
+        }"#,
+    );
+    let start = Some(Utc.ymd(2020, 3, 2).and_hms(9, 30, 0));
+    let end = Some(Utc.ymd(2020, 3, 2).and_hms(11, 15, 0));
+
+    assert_eq!(
+        log.aggregate_within(&filter::filter_true(), start, end),
+        Duration::minutes(45)
+    );
+    assert_eq!(
+        log.aggregate_within(&filter::has_tag(0), start, None),
+        Duration::minutes(45)
+    );
+    assert_eq!(
+        log.aggregate_within(&filter::filter_true(), None, None),
+        log.aggregate(&filter::filter_true())
+    );
+}
+
+#[test]
+fn resume_reopens_most_recent_interval_within_window() {
+    let mut log = TimeLog::new();
+    log.open_with("work", true, &at(9, 0)).unwrap();
+    log.close_with("work", &at(10, 0)).unwrap();
+    log.open_with("work", false, &at(10, 30)).unwrap();
+    log.close_with("work", &at(11, 0)).unwrap();
+
+    assert_eq!(
+        log.resume_with("work", Duration::minutes(15), &at(11, 10))
+            .unwrap()
+            .map(|int| int.start()),
+        Some(Utc.ymd(2020, 3, 2).and_hms(10, 30, 0))
+    );
+    assert_eq!(log.intervals_for_tag_name("work").count(), 2);
+    assert_eq!(
+        log.resume_with("work", Duration::minutes(15), &at(11, 10)),
+        Err(TimeLogError::TagAlreadyOpen)
+    );
+}
+
+#[test]
+fn resume_finds_nothing_to_continue() {
+    let mut log = TimeLog::new();
+    assert_eq!(
+        log.resume_with("work", Duration::minutes(15), &at(9, 0)),
+        Ok(None)
+    );
+
+    log.open_with("work", true, &at(9, 0)).unwrap();
+    log.close_with("work", &at(10, 0)).unwrap();
+    assert_eq!(
+        log.resume_with("work", Duration::minutes(15), &at(10, 30)),
+        Ok(None)
+    );
+    assert!(log.iter().all(|int| int.is_closed()));
+}
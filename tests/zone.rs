@@ -0,0 +1,88 @@
+use timelog::zone::Zone;
+
+use chrono::{NaiveDate, TimeZone, Utc};
+
+#[test]
+fn named_zone_converts_from_utc() {
+    let zone: Zone = "Asia/Tokyo".parse().unwrap();
+    let time = zone.from_utc(&Utc.ymd(2020, 3, 2).and_hms(20, 0, 0));
+
+    assert_eq!(
+        time.naive_local(),
+        NaiveDate::from_ymd(2020, 3, 3).and_hms(5, 0, 0)
+    );
+    assert_eq!(time.offset().local_minus_utc(), 9 * 3600);
+}
+
+#[test]
+fn named_zone_interprets_local_times() {
+    let zone: Zone = "America/New_York".parse().unwrap();
+    let time = zone
+        .from_local(&NaiveDate::from_ymd(2020, 7, 1).and_hms(9, 0, 0))
+        .unwrap();
+
+    assert_eq!(
+        time.with_timezone(&Utc),
+        Utc.ymd(2020, 7, 1).and_hms(13, 0, 0)
+    );
+}
+
+#[test]
+fn parse_zone_names() {
+    assert_eq!("local".parse::<Zone>().unwrap(), Zone::Local);
+    assert_eq!(
+        "UTC".parse::<Zone>().unwrap(),
+        Zone::Named(chrono_tz::Tz::UTC)
+    );
+    assert!("Not/AZone".parse::<Zone>().is_err());
+}
+
+#[test]
+fn resolve_local_handles_daylight_saving_transitions() {
+    let zone: Zone = "America/New_York".parse().unwrap();
+    let local = |m, d, h, min| NaiveDate::from_ymd(2020, m, d).and_hms(h, min, 0);
+
+    // Clocks went from 2:00 EST to 3:00 EDT on 2020-03-08.
+    assert_eq!(
+        zone.resolve_local(&local(3, 8, 0, 0)),
+        Utc.ymd(2020, 3, 8).and_hms(5, 0, 0)
+    );
+    assert_eq!(
+        zone.resolve_local(&local(3, 9, 0, 0)),
+        Utc.ymd(2020, 3, 9).and_hms(4, 0, 0)
+    );
+    assert_eq!(
+        zone.resolve_local(&local(3, 8, 2, 30)),
+        Utc.ymd(2020, 3, 8).and_hms(7, 30, 0)
+    );
+
+    // Clocks went from 2:00 EDT back to 1:00 EST on 2020-11-01.
+    assert_eq!(
+        zone.resolve_local(&local(11, 1, 1, 30)),
+        Utc.ymd(2020, 11, 1).and_hms(5, 30, 0)
+    );
+}
+
+#[test]
+fn resolve_local_handles_skipped_midnight() {
+    // Clocks went from midnight straight to 1:00 on 2018-11-04 in Sao Paulo.
+    let zone: Zone = "America/Sao_Paulo".parse().unwrap();
+    let midnight = NaiveDate::from_ymd(2018, 11, 4).and_hms(0, 0, 0);
+
+    assert!(zone.from_local(&midnight).earliest().is_none());
+    assert_eq!(
+        zone.resolve_local(&midnight),
+        Utc.ymd(2018, 11, 4).and_hms(3, 0, 0)
+    );
+}
+
+#[test]
+fn resolve_local_handles_fractional_offsets() {
+    let zone: Zone = "Asia/Kathmandu".parse().unwrap();
+    let midnight = NaiveDate::from_ymd(2020, 3, 2).and_hms(0, 0, 0);
+
+    assert_eq!(
+        zone.resolve_local(&midnight),
+        Utc.ymd(2020, 3, 1).and_hms(18, 15, 0)
+    );
+}
@@ -0,0 +1,274 @@
+//! Tests of local-time windows and grouping, run with the time zone pinned to America/New_York.
+//!
+//! The zone is set for this whole test binary, so every test here must pin the same zone.
+
+use timelog::clock::FixedClock;
+use timelog::commands::Outputs;
+use timelog::config::Options;
+use timelog::run_command;
+use timelog::timelog::TimeLog;
+use timelog::zone::{self, Zone};
+
+use chrono::{DateTime, TimeZone, Utc};
+use structopt::StructOpt;
+
+fn pin_zone() {
+    let new_york: Zone = "America/New_York".parse().unwrap();
+    zone::set_current(new_york);
+    assert_eq!(zone::current(), new_york);
+}
+
+/// The bounds selected by the given command line at the given time.
+fn bounds_at(args: &[&str], now: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+    pin_zone();
+    let options = Options::from_iter(std::iter::once("timelog").chain(args.iter().copied()));
+    let info = options.command.read_only_filter().unwrap();
+    let (start, end) = info.bounds_with(&options.settings, &FixedClock(now));
+    (start.unwrap(), end.unwrap())
+}
+
+/// The output of the given command line, including global options, run on a timelog.
+fn output_of(log: &mut TimeLog, args: &[&str]) -> String {
+    pin_zone();
+    let options = Options::from_iter(std::iter::once("timelog").chain(args.iter().copied()));
+    let (mut output, mut error) = (Vec::new(), Vec::new());
+    options
+        .command
+        .execute(
+            log,
+            Outputs::new(&mut output, Some(&mut error)),
+            &options.settings,
+        )
+        .unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn week_window_starts_on_week_start_day() {
+    // Tuesday 2020-03-03, 12:00 EST.
+    let now = Utc.ymd(2020, 3, 3).and_hms(17, 0, 0);
+
+    assert_eq!(
+        bounds_at(&["list", "--week"], now),
+        (
+            Utc.ymd(2020, 3, 2).and_hms(5, 0, 0),
+            Utc.ymd(2020, 3, 9).and_hms(4, 0, 0)
+        )
+    );
+    assert_eq!(
+        bounds_at(&["--week-start", "Sun", "list", "--week"], now),
+        (
+            Utc.ymd(2020, 3, 1).and_hms(5, 0, 0),
+            Utc.ymd(2020, 3, 8).and_hms(5, 0, 0)
+        )
+    );
+    assert_eq!(
+        bounds_at(&["--week-start", "Tue", "list", "--week"], now),
+        (
+            Utc.ymd(2020, 3, 3).and_hms(5, 0, 0),
+            Utc.ymd(2020, 3, 10).and_hms(4, 0, 0)
+        )
+    );
+}
+
+#[test]
+fn week_window_spans_daylight_saving_change() {
+    // Clocks went from 2:00 EST to 3:00 EDT on Sunday 2020-03-08, so a week starting that day
+    // is an hour short.
+    let now = Utc.ymd(2020, 3, 10).and_hms(16, 0, 0);
+    let (start, end) = bounds_at(&["--week-start", "Sun", "list", "--week"], now);
+
+    assert_eq!(start, Utc.ymd(2020, 3, 8).and_hms(5, 0, 0));
+    assert_eq!(end, Utc.ymd(2020, 3, 15).and_hms(4, 0, 0));
+    assert_eq!(end - start, chrono::Duration::hours(7 * 24 - 1));
+
+    // Sunday after the change still belongs to the week starting the previous Monday.
+    let now = Utc.ymd(2020, 3, 8).and_hms(16, 0, 0);
+    assert_eq!(
+        bounds_at(&["list", "--week"], now),
+        (
+            Utc.ymd(2020, 3, 2).and_hms(5, 0, 0),
+            Utc.ymd(2020, 3, 9).and_hms(4, 0, 0)
+        )
+    );
+}
+
+#[test]
+fn month_window_covers_calendar_month() {
+    let now = Utc.ymd(2020, 3, 15).and_hms(16, 0, 0);
+
+    // March 2020 starts in EST and ends in EDT.
+    assert_eq!(
+        bounds_at(&["list", "--month"], now),
+        (
+            Utc.ymd(2020, 3, 1).and_hms(5, 0, 0),
+            Utc.ymd(2020, 4, 1).and_hms(4, 0, 0)
+        )
+    );
+    assert_eq!(
+        bounds_at(&["list", "--month", "2020-02"], now),
+        (
+            Utc.ymd(2020, 2, 1).and_hms(5, 0, 0),
+            Utc.ymd(2020, 3, 1).and_hms(5, 0, 0)
+        )
+    );
+}
+
+#[test]
+fn month_window_wraps_from_december_to_january() {
+    assert_eq!(
+        bounds_at(
+            &["list", "--month", "2019-12"],
+            Utc.ymd(2020, 3, 15).and_hms(16, 0, 0)
+        ),
+        (
+            Utc.ymd(2019, 12, 1).and_hms(5, 0, 0),
+            Utc.ymd(2020, 1, 1).and_hms(5, 0, 0)
+        )
+    );
+
+    // New Year's Eve evening in New York is already January in UTC.
+    assert_eq!(
+        bounds_at(&["list", "--month"], Utc.ymd(2021, 1, 1).and_hms(3, 0, 0)),
+        (
+            Utc.ymd(2020, 12, 1).and_hms(5, 0, 0),
+            Utc.ymd(2021, 1, 1).and_hms(5, 0, 0)
+        )
+    );
+}
+
+#[test]
+fn yesterday_window_is_previous_local_day() {
+    // Tuesday 2020-03-10, 00:30 EDT.
+    let now = Utc.ymd(2020, 3, 10).and_hms(4, 30, 0);
+    assert_eq!(
+        bounds_at(&["list", "--yesterday"], now),
+        (
+            Utc.ymd(2020, 3, 9).and_hms(4, 0, 0),
+            Utc.ymd(2020, 3, 10).and_hms(4, 0, 0)
+        )
+    );
+
+    // The day of the DST change is 23 hours long.
+    let now = Utc.ymd(2020, 3, 9).and_hms(16, 0, 0);
+    assert_eq!(
+        bounds_at(&["list", "--yesterday"], now),
+        (
+            Utc.ymd(2020, 3, 8).and_hms(5, 0, 0),
+            Utc.ymd(2020, 3, 9).and_hms(4, 0, 0)
+        )
+    );
+}
+
+#[test]
+fn yesterday_window_follows_day_start() {
+    // With days starting at 4:00, 2:00 on Tuesday still belongs to Monday.
+    let now = Utc.ymd(2020, 3, 10).and_hms(6, 0, 0);
+    assert_eq!(
+        bounds_at(&["--day-start", "4:00", "list", "--yesterday"], now),
+        (
+            Utc.ymd(2020, 3, 8).and_hms(8, 0, 0),
+            Utc.ymd(2020, 3, 9).and_hms(8, 0, 0)
+        )
+    );
+
+    let now = Utc.ymd(2020, 3, 10).and_hms(9, 0, 0);
+    assert_eq!(
+        bounds_at(&["--day-start", "4:00", "list", "--yesterday"], now),
+        (
+            Utc.ymd(2020, 3, 9).and_hms(8, 0, 0),
+            Utc.ymd(2020, 3, 10).and_hms(8, 0, 0)
+        )
+    );
+}
+
+#[test]
+fn aggregate_group_by_uses_local_days_and_iso_weeks() {
+    pin_zone();
+    let mut log: TimeLog = serde_json::from_str(
+        r#"{
+            "tags": ["work"],
+            "intervals": [
+                {"tag": 0, "interval": {"start": "2020-03-02T12:00:00Z", "duration": "1:00"}},
+                {"tag": 0, "interval": {"start": "2020-03-08T12:00:00Z", "duration": "0:30"}},
+                {"tag": 0, "interval": {"start": "2020-03-09T03:00:00Z", "duration": "0:15"}},
+                {"tag": 0, "interval": {"start": "2020-03-09T12:00:00Z", "duration": "0:15"}}
+            ]
+        }"#,
+    )
+    .unwrap();
+
+    // 03:00 UTC on Monday 2020-03-09 is still Sunday evening in New York.
+    let (_, output) = run_command(&mut log, &["aggregate", "--group-by", "week"]).unwrap();
+    let lines: Vec<_> = output.lines().rev().take(3).collect();
+    assert_eq!(lines, vec!["Total 2:00", "2020-W11 0:15", "2020-W10 1:45"]);
+
+    let (_, output) = run_command(&mut log, &["aggregate", "--group-by", "day"]).unwrap();
+    let lines: Vec<_> = output.lines().rev().take(4).collect();
+    assert_eq!(
+        lines,
+        vec![
+            "Total 2:00",
+            "2020-03-09 0:15",
+            "2020-03-08 0:45",
+            "2020-03-02 1:00"
+        ]
+    );
+
+    assert!(run_command(&mut log, &["aggregate", "--group-by", "month"]).is_err());
+}
+
+#[test]
+fn budget_clips_usage_to_the_selected_range_like_aggregate() {
+    let mut log: TimeLog = serde_json::from_str(
+        r#"{
+            "tags": ["work"],
+            "intervals": [
+                {"tag": 0, "interval": {"start": "2020-03-02T14:00:00Z", "duration": "2:00"}},
+                {"tag": 0, "interval": {"start": "2020-03-02T17:00:00Z", "duration": "0:30"}}
+            ]
+        }"#,
+    )
+    .unwrap();
+
+    // 10:00 EST is 15:00 UTC, an hour into the first interval.
+    let after = ["--after", "2020-03-02,10:00"];
+    let aggregate = output_of(&mut log, &["aggregate", after[0], after[1]]);
+    assert_eq!(aggregate.lines().last(), Some("Total 1:30"));
+
+    let budget = output_of(
+        &mut log,
+        &["--budget", "work=2:00", "budget", after[0], after[1]],
+    );
+    assert_eq!(
+        budget.lines().nth(1),
+        Some("work |      2:00 |      1:30 |      0:30")
+    );
+}
+
+#[test]
+fn compare_clips_actual_time_to_the_selected_range_like_aggregate() {
+    let mut log: TimeLog = serde_json::from_str(
+        r#"{
+            "tags": ["work"],
+            "intervals": [
+                {"tag": 0, "interval": {"start": "2020-03-02T14:00:00Z", "duration": "2:00"}},
+                {"tag": 0, "interval": {"start": "2020-03-02T17:00:00Z", "duration": "0:30"}}
+            ]
+        }"#,
+    )
+    .unwrap();
+
+    let after = ["--after", "2020-03-02,10:00"];
+    let aggregate = output_of(&mut log, &["aggregate", after[0], after[1]]);
+    assert_eq!(aggregate.lines().last(), Some("Total 1:30"));
+
+    let compare = output_of(
+        &mut log,
+        &["--estimate", "work=1:00", "compare", after[0], after[1]],
+    );
+    assert_eq!(
+        compare.lines().nth(1),
+        Some("work |      1:00 |      1:30 |     +0:30 OVER ESTIMATE")
+    );
+}